@@ -0,0 +1,147 @@
+// Bookmarks: lines of a buffer to come back to, by name. Toggling one gives it the first free number,
+// and those are what Ctrl+1 to Ctrl+9 go to. They're kept in a bookmarks file with a section per file:
+//
+//     ["/home/me/raxel/src/main.rs"]
+//     "1" = 120
+//     "parser" = 45
+//
+// The lines count from 1 there, like everywhere the user gets to see them
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::editor::Editor;
+use crate::files;
+use crate::theme::strip_comment;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    pub name: String,
+    pub at: usize, // NOTE: In bytes. Starts out at the start of its line and moves along with the text around it
+}
+// Moves the bookmarks along after `removed` bytes at `at` got replaced with `inserted` ones. The ones
+// inside of what was removed end up where it was
+pub fn shift(marks: &mut [Bookmark], at: usize, removed: usize, inserted: usize) {
+    for m in marks.iter_mut() {
+        if m.at >= at + removed {
+            m.at = m.at + inserted - removed;
+        } else if m.at > at {
+            m.at = at;
+        }
+    }
+}
+// The bookmarks of every file that had some, by line
+pub struct BookmarkFile {
+    pub path: Option<PathBuf>, // Where they get saved. Without one they only last as long as the editor is open
+    files: BTreeMap<PathBuf, Vec<(String, usize)>>,
+}
+// NOTE: The same file can be opened under different paths, it should get the same bookmarks each time
+fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s)
+}
+impl BookmarkFile {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path, files: BTreeMap::new() }
+    }
+    // A file that isn't there yet just has no bookmarks in it
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let src = files::read_or_new(&path).map_err(|e| e.to_string())?;
+        let mut file = Self::new(Some(path));
+        file.files = Self::parse(&String::from_utf8_lossy(&src))?;
+        Ok(file)
+    }
+    fn parse(src: &str) -> Result<BTreeMap<PathBuf, Vec<(String, usize)>>, String> {
+        let mut files: BTreeMap<PathBuf, Vec<(String, usize)>> = BTreeMap::new();
+        let mut current = None;
+        for (n, line) in src.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() { continue; }
+            let err = |e: &str| format!("line {}: {}", n + 1, e);
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                current = Some(PathBuf::from(unquote(section)));
+                continue;
+            }
+            let Some(file) = &current else { return Err(err("Expected a [\"file\"] first")); };
+            // NOTE: Names can have '=' in them, the line never does
+            let (name, number) = line.rsplit_once('=').ok_or_else(|| err("Expected `\"name\" = line`"))?;
+            let number = number.trim().parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(|| err("Expected a line number"))?;
+            files.entry(file.clone()).or_default().push((unquote(name).to_string(), number - 1));
+        }
+        Ok(files)
+    }
+    fn to_toml(&self) -> String {
+        let mut out = String::new();
+        for (path, marks) in self.files.iter() {
+            if !out.is_empty() { out.push('\n'); }
+            out += &format!("[\"{}\"]\n", path.display());
+            for (name, line) in marks {
+                out += &format!("\"{}\" = {}\n", name, line + 1);
+            }
+        }
+        out
+    }
+    // Gives the editor the bookmarks its file had
+    pub fn restore(&self, e: &mut Editor) {
+        let Some(path) = &e.path else { return; };
+        let Some(marks) = self.files.get(&key(path)) else { return; };
+        let last = e.buffer.len_lines() - 1;
+        e.bookmarks = marks.iter().map(|(name, line)| Bookmark { name: name.clone(), at: e.buffer.line((*line).min(last)).at }).collect();
+    }
+    // Takes in the bookmarks of the editor and writes everything out
+    pub fn store(&mut self, e: &Editor) -> io::Result<()> {
+        let Some(path) = &e.path else { return Ok(()); };
+        let marks: Vec<(String, usize)> = e.bookmarks.iter().map(|m| (m.name.clone(), e.bookmark_line(m))).collect();
+        if marks.is_empty() {
+            if self.files.remove(&key(path)).is_none() { return Ok(()); }
+        } else {
+            self.files.insert(key(path), marks);
+        }
+        let Some(file) = &self.path else { return Ok(()); };
+        files::write_atomic(file, self.to_toml().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marks(at: &[usize]) -> Vec<Bookmark> {
+        at.iter().enumerate().map(|(i, at)| Bookmark { name: (i + 1).to_string(), at: *at }).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut file = BookmarkFile::new(None);
+        file.files.insert(PathBuf::from("/home/me/src/main.rs"), vec![("1".to_string(), 119), ("a = b".to_string(), 4), ("#3".to_string(), 0)]);
+        file.files.insert(PathBuf::from("/home/me/notes [old].txt"), vec![("x=".to_string(), 9)]);
+        let src = file.to_toml();
+        assert!(src.contains("\"1\" = 120\n"), "{}", src);
+        assert_eq!(BookmarkFile::parse(&src).unwrap(), file.files);
+    }
+    #[test]
+    fn parse_errors() {
+        assert!(BookmarkFile::parse("\"1\" = 3").unwrap_err().starts_with("line 1:"));
+        assert!(BookmarkFile::parse("[\"a\"]\n\"1\" = 0").unwrap_err().starts_with("line 2:"));
+        assert!(BookmarkFile::parse("[\"a\"]\n\"1\" = x").is_err());
+        assert!(BookmarkFile::parse("[\"a\"]\n\"1\"").is_err());
+        assert!(BookmarkFile::parse("# Nothing yet\n").unwrap().is_empty());
+    }
+    #[test]
+    fn shift_around_an_edit() {
+        // 5..8 replaced with a single byte
+        let mut m = marks(&[2, 5, 6, 8, 12]);
+        shift(&mut m, 5, 3, 1);
+        assert_eq!(m, marks(&[2, 5, 5, 6, 10]));
+        // Typing right where a bookmark is leaves it at the start of its line
+        let mut m = marks(&[0, 4]);
+        shift(&mut m, 4, 0, 2);
+        assert_eq!(m, marks(&[0, 6]));
+        let mut m = marks(&[0, 4]);
+        shift(&mut m, 0, 0, 2);
+        assert_eq!(m, marks(&[2, 6]));
+    }
+}
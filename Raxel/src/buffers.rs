@@ -0,0 +1,228 @@
+// The open files, the tab bar above them and the panes showing them. There's always at least
+// one buffer, an empty one once everything else got closed. The focused pane shows the active buffer
+use std::path::{Path, PathBuf};
+
+use crate::bookmarks::BookmarkFile;
+use crate::editor::Editor;
+use crate::panes::{Direction, Layout, Pane, ViewState};
+use crate::renderer::{Boundary, Renderer};
+use crate::theme::Theme;
+use crate::{Font, Vector2f};
+
+const TAB_PADDING: f32 = 12.0;
+const TAB_MIN_WIDTH: f32 = 80.0;
+
+pub struct Buffers {
+    pub editors: Vec<Editor>,
+    pub active: usize,
+    pub layout: Layout,
+    pub bookmarks: BookmarkFile, // Where the buffers get their bookmarks from when they're opened
+}
+// Paths that name the same file should find the same buffer, however they were typed in
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+impl Buffers {
+    pub fn new(mut editors: Vec<Editor>) -> Self {
+        if editors.is_empty() {
+            editors.push(Editor::from_bytes(Vec::new()));
+        }
+        Self { editors, active: 0, layout: Layout::new(), bookmarks: BookmarkFile::new(None) }
+    }
+    pub fn current(&self) -> &Editor {
+        &self.editors[self.active]
+    }
+    pub fn current_mut(&mut self) -> &mut Editor {
+        &mut self.editors[self.active]
+    }
+    pub fn find(&self, path: &Path) -> Option<usize> {
+        self.editors.iter().position(|e| e.path.as_deref().is_some_and(|p| same_file(p, path)))
+    }
+    // Adds a buffer after the current one and switches to it. An untouched empty buffer gets replaced instead
+    pub fn add(&mut self, mut editor: Editor) {
+        self.bookmarks.restore(&mut editor);
+        let current = self.current();
+        if current.path.is_none() && !current.dirty && current.buffer.is_empty() {
+            self.editors[self.active] = editor;
+            return;
+        }
+        self.active += 1;
+        self.editors.insert(self.active, editor);
+        let (active, focused) = (self.active, self.layout.focused);
+        for (i, pane) in self.layout.panes_mut().into_iter().enumerate() {
+            if i != focused && pane.buffer >= active {
+                pane.buffer += 1;
+            }
+        }
+    }
+    // Switches to the file if it's already open, otherwise opens it
+    pub fn open(&mut self, path: PathBuf) -> std::io::Result<()> {
+        if let Some(i) = self.find(&path) {
+            self.active = i;
+            return Ok(());
+        }
+        self.add(Editor::open(path)?);
+        Ok(())
+    }
+    // NOTE: Doesn't ask about unsaved changes, that's up to whoever calls it
+    pub fn close(&mut self, index: usize) {
+        if index >= self.editors.len() { return; }
+        self.editors.remove(index);
+        if self.editors.is_empty() {
+            self.editors.push(Editor::from_bytes(Vec::new()));
+        }
+        if self.active > index || self.active >= self.editors.len() {
+            self.active = self.active.saturating_sub(1);
+        }
+        // The other panes showing it go to whatever the focused one shows now
+        let (active, focused) = (self.active, self.layout.focused);
+        for (i, pane) in self.layout.panes_mut().into_iter().enumerate() {
+            if i == focused { continue; }
+            if pane.buffer == index {
+                *pane = Pane::new(active);
+            } else if pane.buffer > index {
+                pane.buffer -= 1;
+            }
+        }
+    }
+    // Goes to the next tab, or the previous one, wrapping around at the ends
+    pub fn cycle(&mut self, forward: bool) {
+        let n = self.editors.len();
+        self.active = if forward { (self.active + 1) % n } else { (self.active + n - 1) % n };
+    }
+    // Runs `f` on the buffer of pane `index` as that pane sees it, with its own cursors and scroll
+    pub fn with_pane<R>(&mut self, index: usize, f: impl FnOnce(&mut Editor) -> R) -> R {
+        if index == self.layout.focused {
+            return f(&mut self.editors[self.active]);
+        }
+        let mut panes = self.layout.panes_mut();
+        let pane = &mut panes[index];
+        let e = &mut self.editors[pane.buffer];
+        pane.state.clamp(e);
+        pane.state.swap(e);
+        // NOTE: What's being typed and the vim mode belong to the focused pane
+        let (preedit, mode) = (std::mem::take(&mut e.preedit), std::mem::take(&mut e.mode));
+        let res = f(e);
+        (e.preedit, e.mode) = (preedit, mode);
+        pane.state.swap(e);
+        res
+    }
+    // Moves the focus to pane `index`. The pane that had it keeps its view of its buffer
+    pub fn focus(&mut self, index: usize) {
+        let focused = self.layout.focused;
+        if index == focused || index >= self.layout.count() { return; }
+        let state = ViewState::of(&self.editors[self.active]);
+        let mut panes = self.layout.panes_mut();
+        panes[focused].buffer = self.active;
+        panes[focused].state = state;
+        self.enter(index);
+    }
+    // Gives pane `index` the focus, bringing its view into the editor of its buffer
+    fn enter(&mut self, index: usize) {
+        let mut panes = self.layout.panes_mut();
+        let pane = &mut panes[index];
+        let e = &mut self.editors[pane.buffer];
+        pane.state.clamp(e);
+        pane.state.swap(e);
+        self.active = pane.buffer;
+        self.layout.focused = index;
+    }
+    // Splits the focused pane, the new one showing the same part of the same buffer. It gets the focus
+    pub fn split(&mut self, direction: Direction) {
+        let pane = Pane { buffer: self.active, state: ViewState::of(self.current()) };
+        let index = self.layout.split(self.layout.focused, direction, pane);
+        self.focus(index);
+    }
+    // Closes the focused pane, unless it's the only one. The buffer stays open
+    pub fn close_pane(&mut self) {
+        let focused = self.layout.focused;
+        if self.layout.close(focused).is_none() { return; }
+        self.enter(focused.min(self.layout.count() - 1));
+    }
+    // Where each pane goes in `bound`, the part of the window all of them share
+    pub fn pane_bounds(&self, bound: &Boundary) -> Vec<Boundary> {
+        self.layout.bounds(bound)
+    }
+    pub fn focused_bound(&self, bound: &Boundary) -> Boundary {
+        self.layout.bounds(bound).swap_remove(self.layout.focused)
+    }
+    pub fn display(&mut self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        for divider in self.layout.dividers(bound) {
+            r.draw_rect(theme.status, divider.rect.pos, divider.rect.size);
+        }
+        let bounds = self.pane_bounds(bound);
+        for (i, b) in bounds.iter().enumerate() {
+            self.with_pane(i, |e| e.display(r, b.clone(), font, theme));
+        }
+        // Marks which pane the keys go to, when there's more than one
+        if bounds.len() > 1 {
+            let b = &bounds[self.layout.focused];
+            let top = b.top_left();
+            r.draw_rect(theme.cursor, Vector2f(top.0, top.1 - 2.0), Vector2f(b.size.0, 2.0));
+        }
+    }
+    // Remembers the bookmarks of the current buffer, for the next time it's opened
+    pub fn store_bookmarks(&mut self) -> std::io::Result<()> {
+        self.bookmarks.store(&self.editors[self.active])
+    }
+    pub fn any_dirty(&self) -> bool {
+        self.editors.iter().any(|e| e.dirty)
+    }
+    // The file name, with the directory it's in when another tab has a file with the same name
+    pub fn title(&self, index: usize) -> String {
+        let e = &self.editors[index];
+        let Some(path) = &e.path else { return "untitled".to_string(); };
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| path.display().to_string());
+        let clash = self.editors.iter().enumerate().any(|(i, other)| i != index && other.path.as_ref().is_some_and(|p| p.file_name() == path.file_name()));
+        let parent = path.parent().and_then(|p| p.file_name());
+        match parent {
+            Some(parent) if clash => format!("{}/{}", parent.to_string_lossy(), name),
+            _ => name,
+        }
+    }
+    pub fn tab_height(font: &Font) -> f32 {
+        (font.fontSize + 10) as f32
+    }
+    fn label(&self, index: usize) -> String {
+        if self.editors[index].dirty { format!("{}*", self.title(index)) } else { self.title(index) }
+    }
+    // Where each tab starts and how wide it is. When they don't all fit the row slides over
+    // far enough to keep the current one in view
+    fn tab_spans(&self, bound: &Boundary, font: &Font) -> Vec<(f32, f32)> {
+        let mut x = bound.pos.0;
+        let mut spans = Vec::new();
+        for i in 0..self.editors.len() {
+            let w = font.measure_text(&self.label(i), font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+            let w = (w + TAB_PADDING * 2.0).max(TAB_MIN_WIDTH);
+            spans.push((x, w));
+            x += w;
+        }
+        let (x, w) = spans[self.active];
+        let shift = (x + w - (bound.pos.0 + bound.size.0)).max(0.0);
+        spans.iter().map(|(x, w)| (x - shift, *w)).collect()
+    }
+    // Which tab is at `p`, if any
+    pub fn tab_at(&self, p: Vector2f, bound: &Boundary, font: &Font) -> Option<usize> {
+        if p.1 < bound.pos.1 || p.1 > bound.pos.1 + bound.size.1 { return None; }
+        self.tab_spans(bound, font).iter().position(|(x, w)| p.0 >= *x && p.0 < x + w)
+    }
+    pub fn display_tabs(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        r.scisorsBegin(bound);
+        r.draw_rect(theme.gutter, bound.pos, bound.size);
+        for (i, (x, w)) in self.tab_spans(bound, font).into_iter().enumerate() {
+            let color = if i == self.active {
+                r.draw_rect(theme.background, Vector2f(x, bound.pos.1), Vector2f(w, bound.size.1));
+                r.draw_rect(theme.cursor, Vector2f(x, bound.pos.1 + bound.size.1 - 2.0), Vector2f(w, 2.0));
+                theme.foreground
+            } else {
+                theme.gutter_foreground
+            };
+            r.draw_rect(theme.status, Vector2f(x + w - 1.0, bound.pos.1), Vector2f(1.0, bound.size.1));
+            r.draw_str(font, &self.label(i), Vector2f(x + TAB_PADDING, bound.pos.1 + 7.0), color);
+        }
+        r.scisorsEnd();
+    }
+}
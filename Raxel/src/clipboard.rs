@@ -0,0 +1,19 @@
+use std::ffi::{CStr, CString};
+
+use fermium::prelude::*;
+
+// beryllium doesn't wrap SDL's clipboard so we go through fermium for it
+pub fn set_text(text: &str) -> bool {
+    let Ok(c) = CString::new(text) else { return false; };
+    unsafe { SDL_SetClipboardText(c.as_ptr()) == 0 }
+}
+pub fn get_text() -> Option<String> {
+    unsafe {
+        if SDL_HasClipboardText() != SDL_TRUE { return None; }
+        let ptr = SDL_GetClipboardText();
+        if ptr.is_null() { return None; }
+        let text = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+        SDL_free(ptr.cast());
+        Some(text)
+    }
+}
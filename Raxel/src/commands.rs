@@ -0,0 +1,312 @@
+// Everything the editor can be told to do, by name. Keymaps and the command palette refer to commands
+// by these names, and anything that wants to add its own only has to register it. Some commands take
+// arguments after their name, like "goto 120" or "set tabwidth 2"
+use std::collections::BTreeMap;
+
+use crate::buffers::Buffers;
+use crate::clipboard;
+use crate::editor::{Editor, LineNumbers};
+use crate::encoding::Encoding;
+use crate::endings::{self, LineEnding};
+use crate::font::Font;
+use crate::history::EditKind;
+use crate::jumps::{Jump, JumpList};
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::panes::Direction;
+use crate::prompt::{Prompt, PromptKind};
+use crate::quickopen::QuickOpen;
+use crate::renderer::Boundary;
+use crate::searchbar::SearchBar;
+use crate::searchpanel::SearchPanel;
+use crate::theme::Theme;
+
+// What a command gets to work with
+pub struct Context<'a> {
+    pub buffers: &'a mut Buffers,
+    pub prompt: &'a mut Option<Prompt>,
+    pub search: &'a mut SearchBar,
+    pub project: &'a mut SearchPanel,
+    pub finder: &'a mut QuickOpen,
+    pub palette: &'a mut Palette,
+    pub jumps: &'a mut JumpList,
+    pub commands: &'a Commands,
+    pub keymap: &'a Keymap,
+    pub theme: &'a mut Theme,
+    pub font: &'a Font,
+    pub bound: Boundary, // Where the text of the focused pane is drawn, for moving by rows on screen
+    pub quit: bool, // Set to close the editor
+}
+impl Context<'_> {
+    // The buffer that has focus
+    pub fn editor(&mut self) -> &mut Editor {
+        self.buffers.current_mut()
+    }
+}
+// Gets what came after the name of the command. An error is for showing to whoever ran it
+pub type Run = Box<dyn Fn(&mut Context, &str) -> Result<(), String>>;
+pub struct Command {
+    pub description: String,
+    pub usage: String, // The arguments it takes, like "<line>[:<column>]". Empty if it takes none
+    pub run: Run,
+}
+pub struct Commands {
+    pub commands: BTreeMap<String, Command>,
+}
+impl Commands {
+    pub fn new() -> Self {
+        Self { commands: BTreeMap::new() }
+    }
+    // Registering a name again replaces the command that was there
+    pub fn register(&mut self, name: &str, description: &str, run: impl Fn(&mut Context) + 'static) {
+        self.register_fallible(name, description, move |ctx| {
+            run(ctx);
+            Ok(())
+        });
+    }
+    // A command without arguments that can still fail
+    pub fn register_fallible(&mut self, name: &str, description: &str, run: impl Fn(&mut Context) -> Result<(), String> + 'static) {
+        self.register_with_args(name, "", description, move |ctx, _| run(ctx));
+    }
+    pub fn register_with_args(&mut self, name: &str, usage: &str, description: &str, run: impl Fn(&mut Context, &str) -> Result<(), String> + 'static) {
+        self.commands.insert(name.to_string(), Command { description: description.to_string(), usage: usage.to_string(), run: Box::new(run) });
+    }
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+    // Runs `line`, the name of a command followed by its arguments
+    pub fn run(&self, line: &str, ctx: &mut Context) -> Result<(), String> {
+        let (name, args) = split_line(line);
+        let Some(command) = self.commands.get(name) else { return Err(format!("No command called '{}'", name)); };
+        if command.usage.is_empty() && !args.is_empty() {
+            return Err(format!("{} takes no arguments", name));
+        }
+        (command.run)(ctx, args)
+    }
+    pub fn builtin() -> Self {
+        let mut c = Self::new();
+        c.register("cursor.left", "Move left", |ctx| ctx.editor().move_each(false, |e, sel| if !sel.is_empty() { sel.start() } else { e.left(sel.head) }));
+        c.register("cursor.right", "Move right", |ctx| ctx.editor().move_each(false, |e, sel| if !sel.is_empty() { sel.end() } else { e.right(sel.head) }));
+        c.register("cursor.up", "Move up", |ctx| {
+            let (bound, font) = (ctx.bound.clone(), ctx.font);
+            ctx.editor().move_each(false, |e, sel| e.up_row(sel.head, &bound, font));
+        });
+        c.register("cursor.down", "Move down", |ctx| {
+            let (bound, font) = (ctx.bound.clone(), ctx.font);
+            ctx.editor().move_each(false, |e, sel| e.down_row(sel.head, &bound, font));
+        });
+        c.register("cursor.home", "Move to the start of the line", |ctx| ctx.editor().move_each(false, |e, sel| e.home(sel.head)));
+        c.register("cursor.end", "Move to the end of the line", |ctx| ctx.editor().move_each(false, |e, sel| e.end(sel.head)));
+        c.register("select.left", "Extend the selection left", |ctx| ctx.editor().move_each(true, |e, sel| e.left(sel.head)));
+        c.register("select.right", "Extend the selection right", |ctx| ctx.editor().move_each(true, |e, sel| e.right(sel.head)));
+        c.register("select.up", "Extend the selection up", |ctx| {
+            let (bound, font) = (ctx.bound.clone(), ctx.font);
+            ctx.editor().move_each(true, |e, sel| e.up_row(sel.head, &bound, font));
+        });
+        c.register("select.down", "Extend the selection down", |ctx| {
+            let (bound, font) = (ctx.bound.clone(), ctx.font);
+            ctx.editor().move_each(true, |e, sel| e.down_row(sel.head, &bound, font));
+        });
+        c.register("select.home", "Extend the selection to the start of the line", |ctx| ctx.editor().move_each(true, |e, sel| e.home(sel.head)));
+        c.register("select.end", "Extend the selection to the end of the line", |ctx| ctx.editor().move_each(true, |e, sel| e.end(sel.head)));
+        c.register("select.all", "Select everything", |ctx| ctx.editor().select_all());
+        c.register("select.next_occurrence", "Select the word, or add the next occurrence of the selection", |ctx| ctx.editor().select_next_occurrence());
+        c.register("cursor.add_above", "Add a cursor on the line above", |ctx| ctx.editor().add_cursor_vertical(false));
+        c.register("cursor.add_below", "Add a cursor on the line below", |ctx| ctx.editor().add_cursor_vertical(true));
+        c.register("cursor.clear_extra", "Keep only the primary cursor", |ctx| ctx.editor().clear_extra_cursors());
+        c.register("edit.backspace", "Delete the character before the cursor", |ctx| ctx.editor().backspace());
+        c.register("edit.delete", "Delete the character after the cursor", |ctx| ctx.editor().delete());
+        c.register("edit.newline", "Split the line", |ctx| ctx.editor().newline());
+        c.register("edit.tab", "Insert a tab", |ctx| ctx.editor().tab());
+        c.register("edit.undo", "Undo", |ctx| ctx.editor().undo());
+        c.register("edit.redo", "Redo", |ctx| ctx.editor().redo());
+        c.register("edit.copy", "Copy the selections", |ctx| copy(ctx.editor(), false));
+        c.register("edit.cut", "Cut the selections", |ctx| copy(ctx.editor(), true));
+        c.register("edit.paste", "Paste, one line per cursor if the counts match", |ctx| {
+            if let Some(text) = clipboard::get_text() {
+                // NOTE: Whatever put it on the clipboard may have used other line endings than the buffer
+                let texts: Vec<Vec<u8>> = endings::normalize(text.as_bytes()).split(|b| *b == b'\n').map(|l| l.to_vec()).collect();
+                ctx.editor().replace_selections_with(&texts, EditKind::Other);
+            }
+        });
+        c.register("file.save", "Save", |ctx| {
+            if ctx.editor().path.is_none() {
+                *ctx.prompt = Some(Prompt::new(PromptKind::SaveAs, "Save as: ", ""));
+                return;
+            }
+            match ctx.editor().save() {
+                Ok(()) => println!("Saved {}", ctx.editor().path.as_ref().unwrap().display()),
+                Err(e) => eprintln!("[ERROR] Failed to save: {}", e),
+            }
+            // NOTE: The lines the bookmarks are on only match the file once it's saved
+            if let Err(e) = ctx.buffers.store_bookmarks() {
+                eprintln!("[ERROR] Failed to save the bookmarks: {}", e);
+            }
+        });
+        c.register("file.save_as", "Save under a new name", |ctx| {
+            let current = ctx.editor().path.as_ref().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+            *ctx.prompt = Some(Prompt::new(PromptKind::SaveAs, "Save as: ", &current));
+        });
+        c.register("file.line_endings_lf", "Save every line with LF line endings", |ctx| ctx.editor().convert_line_endings(LineEnding::Lf));
+        c.register("file.line_endings_crlf", "Save every line with CRLF line endings", |ctx| ctx.editor().convert_line_endings(LineEnding::Crlf));
+        c.register("file.open", "Open a file in a new tab", |ctx| {
+            // NOTE: Starts out in the directory of the current file, that's usually where the next one is
+            let dir = ctx.editor().path.as_ref().and_then(|p| p.parent()).map(|p| p.to_string_lossy().into_owned()).filter(|d| !d.is_empty());
+            let dir = dir.map(|d| format!("{}/", d)).unwrap_or_default();
+            *ctx.prompt = Some(Prompt::new(PromptKind::Open, "Open: ", &dir));
+        });
+        c.register("app.command_palette", "List every command to pick one", |ctx| ctx.palette.show(ctx.commands, ctx.keymap, ""));
+        c.register("file.quick_open", "Find a file in the project by name", |ctx| ctx.finder.show());
+        c.register("buffer.new", "Open an empty buffer in a new tab", |ctx| ctx.buffers.add(Editor::from_bytes(Vec::new())));
+        c.register("buffer.close", "Close the current tab, asking first if there are unsaved changes", |ctx| {
+            if !ctx.editor().dirty {
+                let active = ctx.buffers.active;
+                ctx.buffers.close(active);
+                return;
+            }
+            *ctx.prompt = Some(Prompt::new(PromptKind::ConfirmClose, "Unsaved changes. Close anyway? (y/n): ", ""));
+        });
+        c.register("buffer.next", "Switch to the tab on the right", |ctx| ctx.buffers.cycle(true));
+        c.register("buffer.previous", "Switch to the tab on the left", |ctx| ctx.buffers.cycle(false));
+        c.register("view.split_right", "Split the pane, the new one going on the right", |ctx| ctx.buffers.split(Direction::Right));
+        c.register("view.split_down", "Split the pane, the new one going below", |ctx| ctx.buffers.split(Direction::Down));
+        c.register("view.close_pane", "Close the focused pane, keeping its buffer open", |ctx| ctx.buffers.close_pane());
+        c.register("view.focus_next_pane", "Move the focus to the next pane", |ctx| {
+            let n = ctx.buffers.layout.count();
+            ctx.buffers.focus((ctx.buffers.layout.focused + 1) % n);
+        });
+        c.register("view.focus_previous_pane", "Move the focus to the previous pane", |ctx| {
+            let n = ctx.buffers.layout.count();
+            ctx.buffers.focus((ctx.buffers.layout.focused + n - 1) % n);
+        });
+        c.register_with_args("goto", "<line>[:<column>]", "Go to a line, and a column on it if there's one", |ctx, args| {
+            let (line, col) = args.split_once(':').unwrap_or((args, "1"));
+            let number = |s: &str| s.trim().parse::<usize>().map_err(|_| format!("Expected a number, not '{}'", s.trim()));
+            let (line, col) = (number(line)?, number(col)?);
+            let e = ctx.editor();
+            e.move_to(e.cursor_at_position(line, col), false);
+            Ok(())
+        });
+        c.register("cursor.goto_line", "Ask for a line to go to", |ctx| ctx.palette.show(ctx.commands, ctx.keymap, "goto "));
+        c.register_fallible("jump.back", "Go back to where the cursor was before it jumped", |ctx| jump(ctx, false));
+        c.register_fallible("jump.forward", "Go forward again after going back", |ctx| jump(ctx, true));
+        c.register_fallible("bookmark.toggle", "Bookmark the line, or take its bookmark off", |ctx| {
+            ctx.editor().toggle_bookmark();
+            ctx.buffers.store_bookmarks().map_err(|e| format!("Failed to save the bookmarks: {}", e))
+        });
+        c.register_with_args("bookmark.set", "<name>", "Put a bookmark with that name on the line", |ctx, args| {
+            if args.is_empty() { return Err("Which bookmark?".to_string()); }
+            // NOTE: The bookmarks file has the names in quotes and nothing to escape them with
+            if args.contains('"') { return Err("Bookmark names can't have '\"' in them".to_string()); }
+            ctx.editor().set_bookmark(args);
+            ctx.buffers.store_bookmarks().map_err(|e| format!("Failed to save the bookmarks: {}", e))
+        });
+        c.register_with_args("bookmark.go", "<name>", "Go to the bookmark with that name", |ctx, args| {
+            let e = ctx.editor();
+            let c = e.bookmark(args).ok_or_else(|| format!("No bookmark called '{}'", args))?;
+            e.move_to(c, false);
+            Ok(())
+        });
+        c.register("bookmark.next", "Go to the next bookmark in the buffer", |ctx| {
+            let e = ctx.editor();
+            if let Some(c) = e.next_bookmark(true) { e.move_to(c, false); }
+        });
+        c.register("bookmark.previous", "Go to the previous bookmark in the buffer", |ctx| {
+            let e = ctx.editor();
+            if let Some(c) = e.next_bookmark(false) { e.move_to(c, false); }
+        });
+        c.register_fallible("bookmark.clear", "Take every bookmark off the buffer", |ctx| {
+            ctx.editor().bookmarks.clear();
+            ctx.buffers.store_bookmarks().map_err(|e| format!("Failed to save the bookmarks: {}", e))
+        });
+        c.register_with_args("set", "<option> <value>", "Change a setting: tabwidth, wrap, linenumbers, scrollmargin, theme or encoding", |ctx, args| {
+            let (option, value) = split_line(args);
+            let number = || value.parse::<usize>().map_err(|_| format!("Expected a number, not '{}'", value));
+            let on = || match value {
+                "on" | "true" | "yes" | "" => Ok(true),
+                "off" | "false" | "no" => Ok(false),
+                _ => Err(format!("Expected on or off, not '{}'", value)),
+            };
+            match option {
+                "tabwidth" => match number()? {
+                    n @ 1..=16 => ctx.editor().tab_width = n,
+                    _ => return Err("tabwidth goes from 1 to 16".to_string()),
+                },
+                "scrollmargin" => ctx.editor().scroll_margin = number()?,
+                "wrap" => {
+                    if on()? != ctx.editor().wrap {
+                        ctx.editor().toggle_wrap();
+                    }
+                }
+                "linenumbers" => {
+                    ctx.editor().line_numbers = match value {
+                        "absolute" => LineNumbers::Absolute,
+                        "relative" => LineNumbers::Relative,
+                        _ => return Err(format!("Expected absolute or relative, not '{}'", value)),
+                    }
+                }
+                "theme" => *ctx.theme = Theme::builtin(value).ok_or_else(|| format!("No theme called '{}'", value))?,
+                // NOTE: Only changes what the file gets saved in, the text stays as it is
+                "encoding" => {
+                    let e = ctx.editor();
+                    if e.read_only { return Err("The buffer is read only".to_string()); }
+                    e.encoding = Encoding::parse(value).ok_or_else(|| format!("No encoding called '{}'", value))?;
+                    e.mark_dirty();
+                }
+                "" => return Err("Which setting?".to_string()),
+                _ => return Err(format!("No setting called '{}'", option)),
+            }
+            Ok(())
+        });
+        c.register("app.quit", "Quit, asking first if there are unsaved changes", |ctx| {
+            if !ctx.buffers.any_dirty() {
+                ctx.quit = true;
+                return;
+            }
+            *ctx.prompt = Some(Prompt::new(PromptKind::ConfirmQuit, "Unsaved changes. Quit anyway? (y/n): ", ""));
+        });
+        c.register("find.open", "Search the buffer", |ctx| ctx.search.show(ctx.buffers.current_mut(), false));
+        c.register("find.replace", "Search and replace in the buffer", |ctx| ctx.search.show(ctx.buffers.current_mut(), true));
+        c.register("find.next", "Select the next match", |ctx| ctx.search.next(ctx.buffers.current_mut(), true));
+        c.register("find.previous", "Select the previous match", |ctx| ctx.search.next(ctx.buffers.current_mut(), false));
+        c.register("find.in_files", "Search every file in the project", |ctx| ctx.project.show(ctx.buffers.current()));
+        c.register("view.toggle_line_numbers", "Switch between absolute and relative line numbers", |ctx| ctx.editor().toggle_line_numbers());
+        c.register("view.toggle_wrap", "Turn soft wrapping on or off", |ctx| ctx.editor().toggle_wrap());
+        c
+    }
+}
+impl Default for Commands {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+// The name of the command in a command line, and its arguments
+pub fn split_line(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    (name, args.trim())
+}
+// Goes back (or forward) in the jump list, opening the file again if it got closed in the meantime
+fn jump(ctx: &mut Context, forward: bool) -> Result<(), String> {
+    let here = Jump::of(ctx.buffers.current());
+    let to = if forward { ctx.jumps.forward(here) } else { ctx.jumps.back(here) };
+    let Some(to) = to else { return Ok(()); };
+    let res = ctx.buffers.open(to.path.clone()).map_err(|e| format!("Failed to open {}: {}", to.path.display(), e));
+    if res.is_ok() {
+        let e = ctx.editor();
+        e.move_to(e.clamp(to.cursor), false);
+    }
+    ctx.jumps.skip(Jump::of(ctx.buffers.current()));
+    res
+}
+fn copy(editor: &mut Editor, cut: bool) {
+    if !editor.has_selection() { return; }
+    let texts = editor.selected_texts();
+    let text = String::from_utf8_lossy(&texts.join(&b'\n')).into_owned();
+    if !clipboard::set_text(&text) {
+        eprintln!("[WARN] Failed to set clipboard text");
+    }
+    if cut {
+        editor.replace_selections(b"", EditKind::Other);
+    }
+}
@@ -0,0 +1,940 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::bookmarks::{self, Bookmark};
+use crate::encoding::{self, Encoding};
+use crate::endings::{self, LineEnding, LineEndings};
+use crate::files;
+use crate::font::Font;
+use crate::highlight::{self, Highlighter, Span, TokenKind};
+use crate::history::{Edit, EditKind, History};
+use crate::renderer::{Boundary, Renderer};
+use crate::rope::Rope;
+use crate::search::Search;
+use crate::theme::Theme;
+use crate::unicode;
+use crate::Vector2f;
+
+const SCROLL_MARGIN: usize = 3;
+const TAB_WIDTH: usize = 4;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub line: usize,
+    pub chr: usize, // NOTE: In bytes from the start of the line
+}
+impl Cursor {
+    pub const fn new() -> Self {
+        Self { line: 0, chr: 0 }
+    }
+}
+// The anchor is where the selection was started from, the head is where the cursor is drawn
+// and what moves around. An empty selection is just a cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Cursor,
+    pub head: Cursor,
+}
+impl Selection {
+    pub const fn caret(c: Cursor) -> Self {
+        Self { anchor: c, head: c }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+    pub fn start(&self) -> Cursor {
+        self.anchor.min(self.head)
+    }
+    pub fn end(&self) -> Cursor {
+        self.anchor.max(self.head)
+    }
+    fn overlaps(&self, other: &Selection) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return self.start() <= other.end() && other.start() <= self.end();
+        }
+        self.start() < other.end() && other.start() < self.end()
+    }
+    fn merge(&self, other: &Selection) -> Selection {
+        let (start, end) = (self.start().min(other.start()), self.end().max(other.end()));
+        if self.head < self.anchor { Selection { anchor: end, head: start } } else { Selection { anchor: start, head: end } }
+    }
+}
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    Absolute,
+    Relative, // Distance from the primary cursor. Its own line still gets the absolute number
+}
+pub struct Editor {
+    pub view: Vector2f, // NOTE: x is in pixels, y is the line at the top
+    // NOTE: Always sorted by position and never overlapping. Use set_selections to keep it that way
+    pub selections: Vec<Selection>,
+    pub primary: usize, // The selection that was added last, the one Ctrl+D continues from
+    pub buffer: Rope,
+    pub history: History,
+    pub path: Option<PathBuf>,
+    pub dirty: bool,
+    // Text the IME is still composing. It's drawn at the primary cursor but isn't part of the buffer until committed
+    pub preedit: String,
+    pub preedit_cursor: usize, // NOTE: In chars from the start of preedit
+    pub wrap: bool, // Soft wraps lines to the width of the boundary
+    pub line_numbers: LineNumbers,
+    pub mode: String, // Shown at the start of the status bar, for input layers like vim to say what they're doing
+    pub scroll_margin: usize, // Lines kept visible above and below the cursor when scrolling it into view
+    pub tab_width: usize, // In spaces
+    follow_cursor: bool,
+    // NOTE: Lexing happens lazily while drawing, which only gets &self
+    pub highlighter: Option<RefCell<Highlighter>>,
+    pub search: Option<Search>, // Matches of it get highlighted in the visible lines
+    pub bookmarks: Vec<Bookmark>,
+    pub encoding: Encoding, // What the file gets saved in
+    pub read_only: bool, // For files that aren't text, they're shown as a hex dump instead
+    pub line_endings: LineEndings,
+}
+// How many bytes at the start and at the end of the buffer none of `edits` touch. They're (at, removed,
+// inserted) in the order they get applied, to a buffer that's `len` bytes long before the first one
+fn untouched(mut len: usize, edits: impl Iterator<Item = (usize, usize, usize)>) -> (usize, usize) {
+    let (mut prefix, mut suffix) = (len, len);
+    for (at, removed, inserted) in edits {
+        prefix = prefix.min(at);
+        suffix = suffix.min(len - at - removed);
+        len = len - removed + inserted;
+    }
+    (prefix.min(len), suffix.min(len))
+}
+impl Editor {
+    // Length of the line without its line ending, which is always just '\n' in the buffer
+    pub fn line_end(&self, line: usize) -> usize {
+        self.buffer.line(line).len
+    }
+    // The bytes of the line without its line ending
+    pub fn line_text(&self, line: usize) -> Vec<u8> {
+        let at = self.buffer.line(line).at;
+        self.buffer.slice(at..at + self.line_end(line))
+    }
+    pub fn offset(&self, c: Cursor) -> usize {
+        self.buffer.line(c.line).at + c.chr
+    }
+    pub fn cursor_at(&self, offset: usize) -> Cursor {
+        let line = self.buffer.byte_to_line(offset);
+        Cursor { line, chr: offset - self.buffer.line(line).at }
+    }
+    pub fn range_of(&self, sel: &Selection) -> Range<usize> {
+        self.offset(sel.start())..self.offset(sel.end())
+    }
+    // What backspace removes at `at`: the grapheme before it, or the line ending before it
+    // when `at` is at the start of the line
+    fn prev_char_range(&self, at: Cursor) -> Range<usize> {
+        let l = self.buffer.line(at.line);
+        let chr = at.chr.min(self.line_end(at.line));
+        if chr == 0 {
+            if at.line == 0 { return l.at..l.at; }
+            let prev = self.buffer.line(at.line - 1);
+            return prev.at + self.line_end(at.line - 1)..l.at;
+        }
+        l.at + unicode::prev_boundary(&self.line_text(at.line), chr)..l.at + chr
+    }
+    // What the delete key removes at `at`: the grapheme after it, or the line ending after it
+    fn next_char_range(&self, at: Cursor) -> Range<usize> {
+        let l = self.buffer.line(at.line);
+        let end = self.line_end(at.line);
+        let i = l.at + at.chr.min(end);
+        if at.chr >= end {
+            if at.line + 1 >= self.buffer.len_lines() { return i..i; }
+            return i..self.buffer.line(at.line + 1).at;
+        }
+        i..l.at + unicode::next_boundary(&self.line_text(at.line), at.chr)
+    }
+
+    // Applies one edit per selection, as a single step in the history. `f` gets each selection
+    // and gives back the byte range to replace and what to put there. Every selection ends up
+    // as a cursor right after its replacement
+    pub fn edit_each(&mut self, kind: EditKind, mut f: impl FnMut(&Self, usize, &Selection) -> (Range<usize>, Vec<u8>)) {
+        if self.read_only { return; }
+        let before = self.selections.clone();
+        let mut changes: Vec<(Range<usize>, Vec<u8>)> = Vec::with_capacity(before.len());
+        for (i, sel) in before.iter().enumerate() {
+            let (range, text) = f(self, i, sel);
+            // Two cursors right next to each other may want the same bytes (backspacing over a
+            // line ending for example). Whoever comes first gets them
+            let start = match changes.last() {
+                Some((prev, _)) => prev.end.max(range.start),
+                None => range.start,
+            };
+            changes.push((start..range.end.max(start), text));
+        }
+        // Apply back to front so the offsets of the earlier changes stay valid
+        let mut edits = Vec::with_capacity(changes.len());
+        let len = self.buffer.len();
+        let touched = changes.iter().filter(|(range, text)| !range.is_empty() || !text.is_empty());
+        let prefix = touched.clone().map(|(range, _)| range.start).min().unwrap_or(len);
+        let suffix = len - touched.map(|(range, _)| range.end).max().unwrap_or(len);
+        self.changed(prefix, suffix, |e| {
+            for (range, text) in changes.iter().rev() {
+                if range.is_empty() && text.is_empty() { continue; }
+                let removed = e.buffer.slice(range.clone());
+                bookmarks::shift(&mut e.bookmarks, range.start, removed.len(), text.len());
+                let removed_endings = e.line_endings.shift(range.start, removed.len(), text.len());
+                let edit = Edit { at: range.start, removed, inserted: text.clone(), removed_endings };
+                edit.apply(&mut e.buffer);
+                edits.push(edit);
+            }
+        });
+        let mut delta: isize = 0;
+        let mut after = Vec::with_capacity(changes.len());
+        for (range, text) in changes.iter() {
+            let offset = (range.start as isize + delta) as usize + text.len();
+            after.push(Selection::caret(self.cursor_at(offset)));
+            delta += text.len() as isize - range.len() as isize;
+        }
+        if !edits.is_empty() {
+            self.dirty = true;
+        }
+        self.set_selections(after, self.primary);
+        self.history.record(edits, kind, before, self.selections.clone());
+    }
+    // Drops every cursor but one and replaces `range` with `text`. The cursor ends up right after it
+    pub fn replace_range(&mut self, range: Range<usize>, text: &[u8]) {
+        let c = self.cursor_at(range.start);
+        self.set_selections(vec![Selection::caret(c)], 0);
+        self.edit_each(EditKind::Other, |_, _, _| (range.clone(), text.to_vec()));
+    }
+    // Replaces whatever is selected with `text` (which may span multiple lines)
+    pub fn replace_selections(&mut self, text: &[u8], kind: EditKind) {
+        self.edit_each(kind, |e, _, sel| (e.range_of(sel), text.to_vec()));
+    }
+    // Same as replace_selections, but when there's exactly one piece of text per selection
+    // each one gets its own. That's what pasting a multi cursor copy does
+    pub fn replace_selections_with(&mut self, texts: &[Vec<u8>], kind: EditKind) {
+        if texts.len() != self.selections.len() {
+            return self.replace_selections(&texts.join(&b'\n'), kind);
+        }
+        self.edit_each(kind, |e, i, sel| (e.range_of(sel), texts[i].clone()));
+    }
+    pub fn selected_texts(&self) -> Vec<Vec<u8>> {
+        self.selections.iter().map(|s| self.buffer.slice(self.range_of(s))).collect()
+    }
+    pub fn has_selection(&self) -> bool {
+        self.selections.iter().any(|s| !s.is_empty())
+    }
+    pub fn type_char(&mut self, c: char) {
+        let mut buf: [u8; 4] = [0; 4];
+        self.type_text(c.encode_utf8(&mut buf));
+    }
+    // Text committed by SDL_TEXTINPUT. Can be more than one char when it comes from an IME
+    pub fn type_text(&mut self, text: &str) {
+        let kind = if self.has_selection() { EditKind::Other } else { EditKind::Typing };
+        self.replace_selections(text.as_bytes(), kind);
+    }
+    // Splits the line at every cursor. The new line gets the line ending of the file when it's saved
+    pub fn newline(&mut self) {
+        let kind = if self.has_selection() { EditKind::Other } else { EditKind::Typing };
+        self.edit_each(kind, |e, _, sel| (e.range_of(sel), b"\n".to_vec()));
+    }
+    pub fn tab(&mut self) {
+        let kind = if self.has_selection() { EditKind::Other } else { EditKind::Typing };
+        self.edit_each(kind, |e, _, sel| (e.range_of(sel), b"\t".to_vec()));
+    }
+    pub fn backspace(&mut self) {
+        let kind = if self.has_selection() { EditKind::Other } else { EditKind::Deleting };
+        self.edit_each(kind, |e, _, sel| {
+            if sel.is_empty() { (e.prev_char_range(sel.head), Vec::new()) } else { (e.range_of(sel), Vec::new()) }
+        });
+    }
+    pub fn delete(&mut self) {
+        let kind = if self.has_selection() { EditKind::Other } else { EditKind::Deleting };
+        self.edit_each(kind, |e, _, sel| {
+            if sel.is_empty() { (e.next_char_range(sel.head), Vec::new()) } else { (e.range_of(sel), Vec::new()) }
+        });
+    }
+
+    pub fn left(&self, c: Cursor) -> Cursor {
+        if c.chr == 0 {
+            if c.line == 0 { return c; }
+            return Cursor { line: c.line - 1, chr: self.line_end(c.line - 1) };
+        }
+        let chr = unicode::prev_boundary(&self.line_text(c.line), c.chr.min(self.line_end(c.line)));
+        Cursor { line: c.line, chr }
+    }
+    pub fn right(&self, c: Cursor) -> Cursor {
+        let end = self.line_end(c.line);
+        if c.chr >= end {
+            if c.line + 1 >= self.buffer.len_lines() { return c; }
+            return Cursor { line: c.line + 1, chr: 0 };
+        }
+        Cursor { line: c.line, chr: unicode::next_boundary(&self.line_text(c.line), c.chr) }
+    }
+    // Up and down stay in the same column on screen, which isn't the same byte once there are tabs or wide characters
+    pub fn up(&self, c: Cursor) -> Cursor {
+        if c.line == 0 { return c; }
+        self.cursor_at_column(c.line - 1, self.column(c))
+    }
+    pub fn down(&self, c: Cursor) -> Cursor {
+        if c.line + 1 >= self.buffer.len_lines() { return c; }
+        self.cursor_at_column(c.line + 1, self.column(c))
+    }
+    pub fn home(&self, c: Cursor) -> Cursor {
+        Cursor { line: c.line, chr: 0 }
+    }
+    pub fn end(&self, c: Cursor) -> Cursor {
+        Cursor { line: c.line, chr: self.line_end(c.line) }
+    }
+    // Makes sure the cursor is inside the buffer and at the start of a grapheme
+    pub fn clamp(&self, c: Cursor) -> Cursor {
+        let line = c.line.min(self.buffer.len_lines() - 1);
+        Cursor { line, chr: unicode::floor_boundary(&self.line_text(line), c.chr) }
+    }
+    // The column on screen `c` is in, counting from 0
+    pub fn column(&self, c: Cursor) -> usize {
+        unicode::byte_to_column(&self.line_text(c.line), c.chr, self.tab_width)
+    }
+    // The cursor on `line` closest to column `col`. Inside a tab or a wide character that's in front of it
+    pub fn cursor_at_column(&self, line: usize, col: usize) -> Cursor {
+        Cursor { line, chr: unicode::column_to_byte(&self.line_text(line), col, self.tab_width) }
+    }
+
+    pub fn primary(&self) -> Selection {
+        self.selections[self.primary]
+    }
+    // Sorts the selections and merges the ones that overlap. `primary` is an index into `sels`
+    pub fn set_selections(&mut self, mut sels: Vec<Selection>, primary: usize) {
+        let primary_sel = sels.get(primary).copied();
+        sels.sort_by_key(|s| s.start());
+        let mut merged: Vec<Selection> = Vec::with_capacity(sels.len());
+        let mut new_primary = 0;
+        for sel in sels {
+            match merged.last_mut() {
+                Some(last) if last.overlaps(&sel) => *last = last.merge(&sel),
+                _ => merged.push(sel),
+            }
+            if Some(sel) == primary_sel {
+                new_primary = merged.len() - 1;
+            }
+        }
+        if merged.is_empty() {
+            merged.push(Selection::caret(Cursor::new()));
+        }
+        self.selections = merged;
+        self.primary = new_primary;
+        self.follow_cursor = true;
+    }
+    // Moves the head of every selection. Without `extend` each selection collapses onto it
+    pub fn move_each(&mut self, extend: bool, f: impl Fn(&Self, &Selection) -> Cursor) {
+        self.history.seal();
+        let sels = self.selections.iter().map(|sel| {
+            let c = f(self, sel);
+            if extend { Selection { anchor: sel.anchor, head: c } } else { Selection::caret(c) }
+        }).collect();
+        self.set_selections(sels, self.primary);
+    }
+    // Drops every cursor but the primary one and moves it to `c`
+    pub fn move_to(&mut self, c: Cursor, extend: bool) {
+        self.history.seal();
+        let mut sel = self.primary();
+        sel.head = c;
+        if !extend {
+            sel.anchor = c;
+        }
+        self.set_selections(vec![sel], 0);
+    }
+    // Drags the head of the primary selection to `c`, leaving the other cursors alone
+    pub fn extend_primary(&mut self, c: Cursor) {
+        self.history.seal();
+        let mut sels = self.selections.clone();
+        sels[self.primary].head = c;
+        self.set_selections(sels, self.primary);
+    }
+    pub fn add_cursor(&mut self, c: Cursor) {
+        self.history.seal();
+        let mut sels = self.selections.clone();
+        sels.push(Selection::caret(c));
+        let primary = sels.len() - 1;
+        self.set_selections(sels, primary);
+    }
+    // Alt+Shift+Up/Down. Adds a cursor on the line above the topmost (or below the bottommost) one
+    pub fn add_cursor_vertical(&mut self, down: bool) {
+        let edge = if down { self.selections[self.selections.len() - 1].head } else { self.selections[0].head };
+        let c = if down { self.down(edge) } else { self.up(edge) };
+        if c.line != edge.line {
+            self.add_cursor(c);
+        }
+    }
+    pub fn clear_extra_cursors(&mut self) {
+        let sel = self.primary();
+        self.set_selections(vec![sel], 0);
+    }
+    pub fn word_at(&self, c: Cursor) -> Range<usize> {
+        let l = self.buffer.line(c.line);
+        let end = l.at + self.line_end(c.line);
+        let mut start = self.offset(c);
+        let mut stop = start;
+        while start > l.at && is_word_byte(self.buffer.byte(start - 1).unwrap()) {
+            start -= 1;
+        }
+        while stop < end && is_word_byte(self.buffer.byte(stop).unwrap()) {
+            stop += 1;
+        }
+        start..stop
+    }
+    // Ctrl+D. Selects the word under the cursor, or if there already is a selection adds the
+    // next occurrence of it after the primary one (wrapping around the end of the buffer)
+    pub fn select_next_occurrence(&mut self) {
+        self.history.seal();
+        let sel = self.primary();
+        if sel.is_empty() {
+            let word = self.word_at(sel.head);
+            if word.is_empty() { return; }
+            let mut sels = self.selections.clone();
+            sels[self.primary] = Selection { anchor: self.cursor_at(word.start), head: self.cursor_at(word.end) };
+            self.set_selections(sels, self.primary);
+            return;
+        }
+        let needle = self.buffer.slice(self.range_of(&sel));
+        let from = self.offset(sel.end());
+        let found = self.buffer.find(&needle, from).or_else(|| self.buffer.find(&needle, 0));
+        let Some(at) = found else { return; };
+        let new = Selection { anchor: self.cursor_at(at), head: self.cursor_at(at + needle.len()) };
+        if self.selections.contains(&new) { return; }
+        let mut sels = self.selections.clone();
+        sels.push(new);
+        let primary = sels.len() - 1;
+        self.set_selections(sels, primary);
+    }
+    pub fn bookmark_line(&self, m: &Bookmark) -> usize {
+        self.buffer.byte_to_line(m.at)
+    }
+    // Takes the bookmarks off the line of the primary cursor, or puts one there numbered with the first free number
+    pub fn toggle_bookmark(&mut self) {
+        let line = self.primary().head.line;
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|m| self.buffer.byte_to_line(m.at) != line);
+        if self.bookmarks.len() < before { return; }
+        let n = (1..).find(|n| !self.bookmarks.iter().any(|m| m.name == n.to_string())).unwrap();
+        self.bookmarks.push(Bookmark { name: n.to_string(), at: self.buffer.line(line).at });
+    }
+    // Puts bookmark `name` on the line of the primary cursor, taking it from wherever it was
+    pub fn set_bookmark(&mut self, name: &str) {
+        let at = self.buffer.line(self.primary().head.line).at;
+        self.bookmarks.retain(|m| m.name != name);
+        self.bookmarks.push(Bookmark { name: name.to_string(), at });
+    }
+    pub fn bookmark(&self, name: &str) -> Option<Cursor> {
+        self.bookmarks.iter().find(|m| m.name == name).map(|m| Cursor { line: self.bookmark_line(m), chr: 0 })
+    }
+    // The closest bookmarked line below the primary cursor (or above it), wrapping around the end of the buffer
+    pub fn next_bookmark(&self, forward: bool) -> Option<Cursor> {
+        let line = self.primary().head.line;
+        let mut lines: Vec<usize> = self.bookmarks.iter().map(|m| self.bookmark_line(m)).collect();
+        lines.sort();
+        let next = if forward {
+            lines.iter().find(|l| **l > line).or(lines.first())
+        } else {
+            lines.iter().rev().find(|l| **l < line).or(lines.last())
+        };
+        next.map(|l| Cursor { line: *l, chr: 0 })
+    }
+    pub fn select_all(&mut self) {
+        self.history.seal();
+        let last = self.buffer.len_lines() - 1;
+        self.set_selections(vec![Selection { anchor: Cursor::new(), head: self.end(Cursor { line: last, chr: 0 }) }], 0);
+    }
+
+    pub fn undo(&mut self) {
+        let Some(group) = self.history.undo.last() else { return; };
+        let (prefix, suffix) = untouched(self.buffer.len(), group.edits.iter().rev().map(|e| (e.at, e.inserted.len(), e.removed.len())));
+        for edit in self.history.undo.last().map(|g| g.edits.as_slice()).unwrap_or_default().iter().rev() {
+            bookmarks::shift(&mut self.bookmarks, edit.at, edit.inserted.len(), edit.removed.len());
+            self.line_endings.shift(edit.at, edit.inserted.len(), edit.removed.len());
+            self.line_endings.restore(edit.at, &edit.removed_endings);
+        }
+        if let Some(sels) = self.changed(prefix, suffix, |e| e.history.undo(&mut e.buffer)) {
+            self.set_selections(sels, 0);
+            self.dirty = !self.history.is_saved();
+        }
+    }
+    pub fn redo(&mut self) {
+        let Some(group) = self.history.redo.last() else { return; };
+        let (prefix, suffix) = untouched(self.buffer.len(), group.edits.iter().map(|e| (e.at, e.removed.len(), e.inserted.len())));
+        for edit in self.history.redo.last().map(|g| g.edits.as_slice()).unwrap_or_default() {
+            bookmarks::shift(&mut self.bookmarks, edit.at, edit.removed.len(), edit.inserted.len());
+            self.line_endings.shift(edit.at, edit.removed.len(), edit.inserted.len());
+        }
+        if let Some(sels) = self.changed(prefix, suffix, |e| e.history.redo(&mut e.buffer)) {
+            self.set_selections(sels, 0);
+            self.dirty = !self.history.is_saved();
+        }
+    }
+    // The buffer as it goes into the file
+    fn encoded(&self) -> std::io::Result<Vec<u8>> {
+        if self.read_only { return Err(std::io::Error::other("The buffer is read only")); }
+        let text = endings::join(&self.buffer.to_bytes(), &self.line_endings);
+        encoding::encode(&text, self.encoding).map_err(std::io::Error::other)
+    }
+    // NOTE: Only what gets saved changes, so there's nothing to undo
+    pub fn convert_line_endings(&mut self, to: LineEnding) {
+        if self.read_only || (self.line_endings.default == to && !self.line_endings.is_mixed()) { return; }
+        self.line_endings.convert(to);
+        self.mark_dirty();
+    }
+    // For changes to how the file gets saved. Undo can't take those back, so it stays dirty until the next save
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.history.mark_unsaved();
+    }
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let path = self.path.as_ref().ok_or_else(|| std::io::Error::other("Buffer has no path"))?;
+        files::write_atomic(path, &self.encoded()?)?;
+        self.dirty = false;
+        self.history.mark_saved();
+        Ok(())
+    }
+    pub fn save_as(&mut self, path: PathBuf) -> std::io::Result<()> {
+        files::write_atomic(&path, &self.encoded()?)?;
+        self.set_path(path);
+        self.dirty = false;
+        self.history.mark_saved();
+        Ok(())
+    }
+    // The highlighting follows the extension of the file
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.highlighter = highlight::lexer_for(&path).map(|l| RefCell::new(Highlighter::new(l)));
+        self.path = Some(path);
+    }
+    // Runs `change` and lets everything that caches something about the text know which lines it changed.
+    // The change can't touch the first `prefix` and the last `suffix` bytes of the buffer
+    fn changed<R>(&mut self, prefix: usize, suffix: usize, change: impl FnOnce(&mut Self) -> R) -> R {
+        let lines = |b: &Rope| b.byte_to_line(b.len() - suffix) - b.byte_to_line(prefix);
+        let removed = lines(&self.buffer);
+        let res = change(self);
+        let inserted = lines(&self.buffer);
+        let line = self.buffer.byte_to_line(prefix);
+        if let Some(h) = &mut self.highlighter {
+            h.get_mut().edited(line, removed, inserted);
+        }
+        res
+    }
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { selections: vec![Selection::caret(Cursor::new())], primary: 0, buffer: Rope::from_bytes(&bytes), history: History::new(), path: None, dirty: false, preedit: String::new(), preedit_cursor: 0, wrap: false, line_numbers: LineNumbers::Absolute, mode: String::new(), scroll_margin: SCROLL_MARGIN, tab_width: TAB_WIDTH, follow_cursor: false, highlighter: None, search: None, bookmarks: Vec::new(), encoding: Encoding::Utf8 { bom: false }, read_only: false, line_endings: LineEndings::new(LineEnding::Lf), view: Vector2f::ZERO()}
+    }
+    // Works out what the file is in. One that isn't text at all opens as a hex dump that can't be edited
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        let bytes = files::read_or_new(&path)?;
+        let Some(encoding) = encoding::detect(&bytes) else {
+            let mut editor = Self::from_bytes(encoding::hex_dump(&bytes));
+            editor.path = Some(path);
+            editor.read_only = true;
+            return Ok(editor);
+        };
+        let (text, line_endings) = endings::split(&encoding::decode(&bytes, encoding));
+        let mut editor = Self::from_bytes(text);
+        editor.encoding = encoding;
+        editor.line_endings = line_endings;
+        editor.set_path(path);
+        Ok(editor)
+    }
+    // How wide a column is. The font is monospaced, so everything on screen is some number of columns wide
+    fn cell(font: &Font) -> f32 {
+        font.get_spacing_char().map(|g| g.advance_x as f32).unwrap_or(8.0)
+    }
+    fn line_advance(font: &Font) -> f32 {
+        // Gets you the glyth step
+        let glythH = font.fontSize + 1;
+        (glythH + 4) as f32
+    }
+    // Splits a line into the rows it takes up on screen. The rows cover 0..line_end and, with wrapping on,
+    // break after whitespace when a word doesn't fit into the boundary anymore
+    pub fn rows(&self, line: usize, bound: &Boundary, font: &Font) -> Vec<Range<usize>> {
+        let end = self.line_end(line);
+        let whole = 0..end;
+        if !self.wrap { return vec![whole]; }
+        let bytes = self.line_text(line);
+        let cell = Self::cell(font);
+        let mut rows = Vec::new();
+        let mut start = 0;
+        let mut startCol = 0;
+        // Where the last word began and the column it began in
+        let mut brk: Option<(usize, usize)> = None;
+        for c in unicode::layout(&bytes, self.tab_width) {
+            if (c.col + c.width - startCol) as f32 * cell > bound.size.0 && c.range.start > start {
+                let (at, atCol) = match brk {
+                    Some((b, bc)) if b > start => (b, bc),
+                    _ => (c.range.start, c.col),
+                };
+                rows.push(start..at);
+                start = at;
+                startCol = atCol;
+                brk = None;
+            }
+            if c.text.chars().next().is_some_and(char::is_whitespace) {
+                brk = Some((c.range.end, c.col + c.width));
+            }
+        }
+        rows.push(start..end);
+        rows
+    }
+    // The row a cursor at `chr` is shown on. A cursor right at a wrap goes to the start of the next row
+    fn row_of(rows: &[Range<usize>], chr: usize) -> usize {
+        rows.iter().rposition(|r| r.start <= chr).unwrap_or(0)
+    }
+    // Horizontal offset of the text, wrapped text never scrolls sideways
+    fn scroll_x_offset(&self) -> f32 {
+        if self.wrap { 0.0 } else { self.view.0 }
+    }
+    fn width_of(&self, line: usize, range: Range<usize>, font: &Font) -> f32 {
+        let bytes = self.line_text(line);
+        let cols = unicode::byte_to_column(&bytes, range.end, self.tab_width) - unicode::byte_to_column(&bytes, range.start, self.tab_width);
+        cols as f32 * Self::cell(font)
+    }
+    // The cursor in a row of `line` that is closest to `x` pixels from the start of that row
+    fn cursor_in_row(&self, line: usize, rows: &[Range<usize>], row: usize, x: f32, font: &Font) -> Cursor {
+        let range = rows[row].clone();
+        let bytes = self.line_text(line);
+        let cell = Self::cell(font);
+        let mut startCol = None;
+        let mut last = range.start;
+        for c in unicode::layout(&bytes, self.tab_width).into_iter().filter(|c| range.contains(&c.range.start)) {
+            let cx = (c.col - *startCol.get_or_insert(c.col)) as f32 * cell;
+            if x < cx + c.width as f32 * cell / 2.0 {
+                return Cursor { line, chr: c.range.start };
+            }
+            last = c.range.start;
+        }
+        // NOTE: The end of a wrapped row is the start of the next one, so stay in front of its last grapheme
+        if row + 1 < rows.len() {
+            return Cursor { line, chr: last };
+        }
+        Cursor { line, chr: range.end }
+    }
+    // Maps a point in window space onto the cursor position closest to it
+    pub fn point_to_cursor(&self, p: Vector2f, bound: &Boundary, font: &Font) -> Cursor {
+        let lineBegin = (self.view.1.floor().max(0.0) as usize).min(self.buffer.len_lines() - 1);
+        let target = ((bound.top_left().1 - p.1) / Self::line_advance(font)).floor().max(0.0) as usize;
+        let x = p.0 - bound.pos.0 + self.scroll_x_offset();
+        let mut row = 0;
+        for line in lineBegin..self.buffer.len_lines() {
+            let rows = self.rows(line, bound, font);
+            if target < row + rows.len() || line + 1 == self.buffer.len_lines() {
+                let k = (target - row.min(target)).min(rows.len() - 1);
+                return self.cursor_in_row(line, &rows, k, x, font);
+            }
+            row += rows.len();
+        }
+        unreachable!()
+    }
+    // Moves up or down by one row on screen. Without wrapping that's the same as going by lines
+    pub fn up_row(&self, c: Cursor, bound: &Boundary, font: &Font) -> Cursor {
+        if !self.wrap { return self.up(c); }
+        let rows = self.rows(c.line, bound, font);
+        let k = Self::row_of(&rows, c.chr);
+        let x = self.width_of(c.line, rows[k].start..c.chr, font);
+        if k > 0 {
+            return self.cursor_in_row(c.line, &rows, k - 1, x, font);
+        }
+        if c.line == 0 { return c; }
+        let rows = self.rows(c.line - 1, bound, font);
+        self.cursor_in_row(c.line - 1, &rows, rows.len() - 1, x, font)
+    }
+    pub fn down_row(&self, c: Cursor, bound: &Boundary, font: &Font) -> Cursor {
+        if !self.wrap { return self.down(c); }
+        let rows = self.rows(c.line, bound, font);
+        let k = Self::row_of(&rows, c.chr);
+        let x = self.width_of(c.line, rows[k].start..c.chr, font);
+        if k + 1 < rows.len() {
+            return self.cursor_in_row(c.line, &rows, k + 1, x, font);
+        }
+        if c.line + 1 >= self.buffer.len_lines() { return c; }
+        let rows = self.rows(c.line + 1, bound, font);
+        self.cursor_in_row(c.line + 1, &rows, 0, x, font)
+    }
+    pub fn scroll(&mut self, lines: f32) {
+        self.view.1 = (self.view.1 + lines).clamp(0.0, (self.buffer.len_lines() - 1) as f32);
+    }
+    pub fn scroll_x(&mut self, px: f32) {
+        if self.wrap { return; }
+        self.view.0 = (self.view.0 + px).max(0.0);
+    }
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.view.0 = 0.0;
+        self.follow_cursor = true;
+    }
+    // Adjusts the view so that the primary cursor is on screen, if it moved since the last call.
+    // Scrolling with the wheel doesn't count as moving, so the cursor is free to go off screen then
+    // NOTE: The view always starts at a line, so a wrapped line taller than the screen can't be scrolled through
+    pub fn scroll_to_cursor(&mut self, bound: &Boundary, font: &Font) {
+        if !self.follow_cursor { return; }
+        self.follow_cursor = false;
+        let screenRows = ((bound.size.1 / Self::line_advance(font)).floor() as usize).max(1);
+        let margin = self.scroll_margin.min((screenRows - 1) / 2);
+        let low = screenRows - 1 - margin;
+        let head = self.primary().head;
+        let rows = self.rows(head.line, bound, font);
+        let k = Self::row_of(&rows, head.chr);
+        let top = self.view.1.floor().max(0.0) as usize;
+        // Row of the cursor counted from the top of the screen, if it's below the top at all
+        let below = if head.line < top {
+            None
+        } else if head.line - top > screenRows {
+            Some(usize::MAX)
+        } else {
+            Some((top..head.line).map(|l| self.rows(l, bound, font).len()).sum::<usize>() + k)
+        };
+        match below {
+            Some(row) if row > low => {
+                let (mut l, mut count) = (head.line, k);
+                while l > 0 && count + self.rows(l - 1, bound, font).len() <= low {
+                    l -= 1;
+                    count += self.rows(l, bound, font).len();
+                }
+                self.view.1 = l as f32;
+            }
+            Some(row) if row >= margin => {}
+            _ => {
+                let (mut l, mut count) = (head.line, k);
+                while l > 0 && count < margin {
+                    l -= 1;
+                    count += self.rows(l, bound, font).len();
+                }
+                self.view.1 = l as f32;
+            }
+        }
+        if !self.wrap {
+            let x = self.width_of(head.line, 0..head.chr, font);
+            let m = (self.scroll_margin as f32 * Self::cell(font)).min(bound.size.0 / 3.0);
+            if x < self.view.0 + m {
+                self.view.0 = (x - m).max(0.0);
+            } else if x > self.view.0 + bound.size.0 - m {
+                self.view.0 = x - bound.size.0 + m;
+            }
+        }
+    }
+    // Inverse of point_to_cursor. Gives the pen position the character at `c` gets drawn at
+    pub fn cursor_to_point(&self, c: Cursor, bound: &Boundary, font: &Font) -> Vector2f {
+        let lineBegin = self.view.1.floor().max(0.0) as usize;
+        let screenRows = (bound.size.1 / Self::line_advance(font)).ceil() as usize;
+        let rows = self.rows(c.line, bound, font);
+        let k = Self::row_of(&rows, c.chr);
+        // NOTE: Far off screen it doesn't matter exactly where, so don't bother laying out everything in between
+        let row = if c.line < lineBegin || c.line > lineBegin + screenRows {
+            c.line as f32 - lineBegin as f32
+        } else {
+            ((lineBegin..c.line).map(|l| self.rows(l, bound, font).len()).sum::<usize>() + k) as f32
+        };
+        let x = self.width_of(c.line, rows[k].start..c.chr, font);
+        Vector2f(bound.pos.0 + x - self.scroll_x_offset(), bound.top_left().1 - (row + 1.0) * Self::line_advance(font))
+    }
+    // Draws the preedit string with an underline and its own caret. Returns where the rest of the line continues
+    fn draw_preedit(&self, r: &mut Renderer, font: &Font, theme: &Theme, mut pos: Vector2f) -> Vector2f {
+        let start = pos;
+        for (i, chr) in self.preedit.chars().enumerate() {
+            if i == self.preedit_cursor {
+                Self::draw_cursor(r, font, theme, pos);
+            }
+            r.draw_char(font, chr, pos, theme.foreground);
+            pos.0 += unicode::char_width(chr) as f32 * Self::cell(font);
+        }
+        if self.preedit_cursor >= self.preedit.chars().count() {
+            Self::draw_cursor(r, font, theme, pos);
+        }
+        r.draw_rect(theme.foreground, Vector2f(start.0, start.1 - 3.0), Vector2f(pos.0 - start.0, 1.0));
+        pos
+    }
+    fn draw_cursor(r: &mut Renderer, font: &Font, theme: &Theme, pos: Vector2f) {
+        let yoff = 2.0;
+        let h = font.fontSize as f32;
+        let w = 3.0;
+        let x = pos.0;
+        let y = pos.1 - yoff;
+        r.draw_rect(theme.cursor, Vector2f(x, y), Vector2f(w, h))
+    }
+    fn gutter_width(&self, font: &Font) -> f32 {
+        let digits = self.buffer.len_lines().to_string().len().max(3);
+        (digits + 2) as f32 * font.get_char('0').advance_x as f32
+    }
+    fn status_height(font: &Font) -> f32 {
+        (font.fontSize + 8) as f32
+    }
+    // Splits the boundary given to display into the gutter, the text area and the status bar at the bottom
+    pub fn layout(&self, bound: &Boundary, font: &Font) -> (Boundary, Boundary, Boundary) {
+        let statusH = Self::status_height(font).min(bound.size.1);
+        let gutterW = self.gutter_width(font).min(bound.size.0);
+        let status = Boundary { pos: bound.pos, size: Vector2f(bound.size.0, statusH) };
+        let rest = bound.size.1 - statusH;
+        let gutter = Boundary { pos: Vector2f(bound.pos.0, bound.pos.1 + statusH), size: Vector2f(gutterW, rest) };
+        let text = Boundary { pos: Vector2f(bound.pos.0 + gutterW, bound.pos.1 + statusH), size: Vector2f(bound.size.0 - gutterW, rest) };
+        (gutter, text, status)
+    }
+    // The part of the boundary the text goes into. Everything that maps between the screen and cursors works in it
+    pub fn text_bound(&self, bound: &Boundary, font: &Font) -> Boundary {
+        self.layout(bound, font).1
+    }
+    pub fn toggle_line_numbers(&mut self) {
+        self.line_numbers = match self.line_numbers {
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Absolute,
+        };
+    }
+    // Line and column of the primary cursor, both counting from 1. The column is the one on screen
+    pub fn position(&self) -> (usize, usize) {
+        let head = self.primary().head;
+        (head.line + 1, self.column(head) + 1)
+    }
+    // The other way around: where line and column `col` are, both counting from 1. Past the end goes to the end
+    pub fn cursor_at_position(&self, line: usize, col: usize) -> Cursor {
+        let line = line.clamp(1, self.buffer.len_lines()) - 1;
+        self.cursor_at_column(line, col.max(1) - 1)
+    }
+    fn display_gutter(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme, numbers: &[(usize, f32)]) {
+        r.scisorsBegin(bound);
+        r.draw_rect(theme.gutter, bound.pos, bound.size);
+        let advanceY = Self::line_advance(font);
+        let current = self.primary().head.line;
+        let digit = font.get_char('0').advance_x as f32;
+        let marks: Vec<(usize, &str)> = self.bookmarks.iter().map(|m| (self.bookmark_line(m), m.name.as_str())).collect();
+        for &(line, y) in numbers {
+            let n = match self.line_numbers {
+                LineNumbers::Relative if line != current => line.abs_diff(current),
+                _ => line + 1,
+            };
+            let text = n.to_string();
+            let color = if self.selections.iter().any(|sel| sel.head.line == line) {
+                r.draw_rect(theme.current_line, Vector2f(bound.pos.0, y - 4.0), Vector2f(bound.size.0, advanceY));
+                theme.current_line_foreground
+            } else {
+                theme.gutter_foreground
+            };
+            let x = bound.pos.0 + bound.size.0 - digit * (text.len() + 1) as f32;
+            r.draw_str(font, &text, Vector2f(x, y), color);
+            // NOTE: A bookmark takes the column left of the numbers that's always free, with the first letter of its name in it
+            if let Some((_, name)) = marks.iter().find(|(l, _)| *l == line) {
+                r.draw_rect(theme.bookmark, Vector2f(bound.pos.0, y - 4.0), Vector2f(digit, advanceY));
+                if let Some(c) = name.chars().next() {
+                    r.draw_char(font, c, Vector2f(bound.pos.0, y), theme.background);
+                }
+            }
+        }
+        r.scisorsEnd();
+    }
+    fn display_status(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        r.scisorsBegin(bound);
+        r.draw_rect(theme.status, bound.pos, bound.size);
+        let pos = bound.pos + Vector2f(4.0, 6.0);
+        let path = self.path.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "[No Name]".to_string());
+        let mut left = if self.dirty { format!("{} [+]", path) } else { path };
+        if !self.mode.is_empty() {
+            left = format!("{}  {}", self.mode, left);
+        }
+        r.draw_str(font, &left, pos, theme.status_foreground);
+        let (line, col) = self.position();
+        let mut right = format!("Ln {}, Col {}", line, col);
+        if self.selections.len() > 1 {
+            right = format!("{} ({} cursors)", right, self.selections.len());
+        }
+        let right = if self.read_only { format!("{}   Hex, read only", right) } else { format!("{}   {}   {}", right, self.encoding, self.line_endings.default) };
+        let w = font.measure_text(&right, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+        let at = bound.pos.0 + bound.size.0 - w - 8.0;
+        r.draw_str(font, &right, Vector2f(at, pos.1), theme.status_foreground);
+        // NOTE: Saving keeps them mixed, but new lines only get the one that's shown
+        if self.line_endings.is_mixed() {
+            let warning = "Mixed line endings";
+            let w = font.measure_text(warning, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+            r.draw_str(font, warning, Vector2f(at - w - 24.0, pos.1), theme.warning);
+        }
+        r.scisorsEnd();
+    }
+    pub fn display(&self, r: &mut Renderer, bound: Boundary, font: &Font, theme: &Theme) {
+        let (gutter, text, status) = self.layout(&bound, font);
+        let numbers = self.display_text(r, text, font, theme);
+        self.display_gutter(r, &gutter, font, theme, &numbers);
+        self.display_status(r, &status, font, theme);
+    }
+    // Draws the text and gives back which lines ended up where, so the gutter can be drawn next to them
+    fn display_text(&self, r: &mut Renderer, bound: Boundary, font: &Font, theme: &Theme) -> Vec<(usize, f32)> {
+        r.scisorsBegin(&bound);
+        //println!("bound: {:?}",bound);
+        let mut numbers = Vec::new();
+        let mut highlighter = self.highlighter.as_ref().map(|h| h.borrow_mut());
+        let advanceY = Self::line_advance(font);
+        let cell = Self::cell(font);
+        let lineBegin = self.view.1.floor().max(0.0) as usize;
+        let right = bound.pos.0 + bound.size.0;
+        let mut pos = bound.top_left();
+        for i in lineBegin..self.buffer.len_lines() {
+            if pos.1 < bound.pos.1 {
+                break;
+            }
+            let bytes = self.line_text(i);
+            let clusters = unicode::layout(&bytes, self.tab_width);
+            // Only the selections touching this line matter for it
+            let first = self.selections.partition_point(|sel| sel.end().line < i);
+            let sels: Vec<&Selection> = self.selections[first..].iter().take_while(|sel| sel.start().line <= i).collect();
+            let composing = if self.preedit.is_empty() { None } else { Some(self.primary().head) };
+            let spans: Vec<Span> = highlighter.as_mut().map(|h| h.spans(&self.buffer, i).to_vec()).unwrap_or_default();
+            let found = self.search.as_ref().map(|search| search.line_matches(&bytes)).unwrap_or_default();
+            let mut span = 0;
+            let rows = self.rows(i, &bound, font);
+            for (k, row) in rows.iter().enumerate() {
+                pos.1 -= advanceY;
+                if pos.1 < bound.pos.1 - advanceY {
+                    break;
+                }
+                if k == 0 {
+                    numbers.push((i, pos.1));
+                }
+                let last = k + 1 == rows.len();
+                let mut pos = Vector2f(bound.pos.0 - self.scroll_x_offset(), pos.1);
+                for c in clusters.iter().skip_while(|c| c.range.start < row.start).take_while(|c| c.range.start < row.end) {
+                    if pos.0 > right {
+                        break;
+                    }
+                    let adv = c.width as f32 * cell;
+                    let here = Cursor { line: i, chr: c.range.start };
+                    if composing == Some(here) {
+                        pos = self.draw_preedit(r, font, theme, pos);
+                    }
+                    if found.iter().any(|m| m.contains(&here.chr)) {
+                        r.draw_rect(theme.search_match, Vector2f(pos.0, pos.1 - 4.0), Vector2f(adv, advanceY));
+                    }
+                    if sels.iter().any(|sel| here >= sel.start() && here < sel.end()) {
+                        r.draw_rect(theme.selection, Vector2f(pos.0, pos.1 - 4.0), Vector2f(adv, advanceY));
+                    }
+                    while span < spans.len() && spans[span].end <= here.chr {
+                        span += 1;
+                    }
+                    let kind = spans.get(span).filter(|s| s.start <= here.chr).map(|s| s.kind).unwrap_or(TokenKind::Normal);
+                    if c.text.is_empty() {
+                        r.draw_str(font, &format!("\\x{:02X}", bytes[c.range.start]), pos, theme.gutter_foreground);
+                    } else {
+                        // NOTE: What the font can't join into one glyph gets squeezed into the columns the cluster has
+                        let (glyphs, width) = unicode::glyphs(c.text);
+                        let scale = (c.width as f32 / width.max(1) as f32).min(1.0);
+                        for (chr, col) in glyphs {
+                            r.draw_char_scale(font, chr, Vector2f(pos.0 + col as f32 * cell * scale, pos.1), theme.token(kind), scale);
+                        }
+                    }
+                    if sels.iter().any(|sel| sel.head == here) && composing != Some(here) {
+                        Self::draw_cursor(r, font, theme, pos);
+                    }
+                    pos.0 += adv;
+                }
+                if !last {
+                    continue;
+                }
+                // Show that the line ending is selected too
+                let eol = Cursor { line: i, chr: bytes.len() };
+                if sels.iter().any(|sel| i < sel.end().line && eol >= sel.start()) {
+                    r.draw_rect(theme.selection, Vector2f(pos.0, pos.1 - 4.0), Vector2f(cell, advanceY));
+                }
+                if composing == Some(eol) {
+                    self.draw_preedit(r, font, theme, pos);
+                } else if sels.iter().any(|sel| sel.head == eol) {
+                    Self::draw_cursor(r, font, theme, pos);
+                }
+            }
+        }
+        r.scisorsEnd();
+        numbers
+    }
+}
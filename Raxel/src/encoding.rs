@@ -0,0 +1,211 @@
+// Files on disk can be in other encodings than UTF-8. The buffer always holds UTF-8, so a file gets
+// decoded when it's opened and encoded back into whatever it was in when it's saved. Bytes that aren't
+// valid UTF-8 in an otherwise UTF-8 file are kept as they are and shown as \xNN, so they survive a save
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8 { bom: bool },
+    Utf16Le { bom: bool },
+    Utf16Be { bom: bool },
+    Windows1252, // Latin-1 with the typographic characters in 0x80..0xA0
+}
+// 0x80..0xA0 in Windows-1252. The five bytes it leaves undefined map to the C1 controls, like Latin-1
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const SAMPLE: usize = 8192; // How much of the file is looked at to tell what it is
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 { bom: false } => "UTF-8",
+            Encoding::Utf8 { bom: true } => "UTF-8 BOM",
+            Encoding::Utf16Le { .. } => "UTF-16 LE",
+            Encoding::Utf16Be { .. } => "UTF-16 BE",
+            Encoding::Windows1252 => "Windows-1252",
+        }
+    }
+    // For `set encoding`. UTF-16 gets a byte order mark, it's hard to tell apart from binary without one
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Encoding::Utf8 { bom: false },
+            "utf-8-bom" | "utf8bom" => Encoding::Utf8 { bom: true },
+            "utf-16le" | "utf16le" => Encoding::Utf16Le { bom: true },
+            "utf-16be" | "utf16be" => Encoding::Utf16Be { bom: true },
+            "windows-1252" | "cp1252" | "latin1" | "latin-1" => Encoding::Windows1252,
+            _ => return None,
+        })
+    }
+}
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+// UTF-16 without a byte order mark, going by how ASCII looks in it: every other byte is zero.
+// Binary files have zeros all over the place, but rarely two of them in every other pair
+fn utf16_without_bom(bytes: &[u8]) -> Option<Encoding> {
+    let pairs = bytes[..bytes.len().min(SAMPLE)].chunks_exact(2);
+    let n = pairs.len();
+    if n < 2 { return None; }
+    let (mut evenZeros, mut oddZeros) = (0, 0);
+    for pair in pairs {
+        if pair == [0, 0] { return None; }
+        if pair[0] == 0 { evenZeros += 1; }
+        if pair[1] == 0 { oddZeros += 1; }
+    }
+    if oddZeros * 10 > n * 3 && evenZeros * 20 < n { return Some(Encoding::Utf16Le { bom: false }); }
+    if evenZeros * 10 > n * 3 && oddZeros * 20 < n { return Some(Encoding::Utf16Be { bom: false }); }
+    None
+}
+// NUL bytes, or lots of control characters, don't show up in text
+fn is_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(SAMPLE)];
+    let controls = sample.iter().filter(|b| **b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B)).count();
+    sample.contains(&0) || controls * 10 > sample.len()
+}
+// What the file is in, or None if it's clearly not text at all
+pub fn detect(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.starts_with(UTF8_BOM) { return Some(Encoding::Utf8 { bom: true }); }
+    if bytes.starts_with(&[0xFF, 0xFE]) { return Some(Encoding::Utf16Le { bom: true }); }
+    if bytes.starts_with(&[0xFE, 0xFF]) { return Some(Encoding::Utf16Be { bom: true }); }
+    if let Some(e) = utf16_without_bom(bytes) { return Some(e); }
+    if is_binary(bytes) { return None; }
+    // NOTE: A few broken bytes in between plenty of UTF-8 are more likely than Windows-1252 that happens to look like UTF-8
+    let (mut multibyte, mut invalid) = (0, 0);
+    for chunk in bytes.utf8_chunks() {
+        multibyte += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+        invalid += chunk.invalid().len();
+    }
+    if invalid == 0 || multibyte > invalid { Some(Encoding::Utf8 { bom: false }) } else { Some(Encoding::Windows1252) }
+}
+// The file as UTF-8, for the buffer
+pub fn decode(bytes: &[u8], encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Utf8 { .. } => bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes).to_vec(),
+        Encoding::Utf16Le { bom } | Encoding::Utf16Be { bom } => {
+            let bytes = if bom { &bytes[2..] } else { bytes };
+            let le = matches!(encoding, Encoding::Utf16Le { .. });
+            let units = bytes.chunks(2).map(|p| match p {
+                [a, b] if le => u16::from_le_bytes([*a, *b]),
+                [a, b] => u16::from_be_bytes([*a, *b]),
+                // NOTE: A byte left over at the end can't be anything, it becomes a replacement character
+                _ => 0xFFFD,
+            });
+            char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect::<String>().into_bytes()
+        }
+        Encoding::Windows1252 => bytes.iter().map(|b| match b {
+            0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+            _ => *b as char,
+        }).collect::<String>().into_bytes(),
+    }
+}
+// The other way around, for saving. Fails on text the encoding has no way of writing down
+pub fn encode(text: &[u8], encoding: Encoding) -> Result<Vec<u8>, String> {
+    // NOTE: Broken bytes only survive in UTF-8, anywhere else they'd have to be made up into something
+    let chars = || std::str::from_utf8(text).map_err(|e| format!("The byte \\x{:02X} on line {} can't be saved as {}", text[e.valid_up_to()], text[..e.valid_up_to()].iter().filter(|b| **b == b'\n').count() + 1, encoding));
+    match encoding {
+        Encoding::Utf8 { bom } => Ok(if bom { [UTF8_BOM, text].concat() } else { text.to_vec() }),
+        Encoding::Utf16Le { bom } | Encoding::Utf16Be { bom } => {
+            let le = matches!(encoding, Encoding::Utf16Le { .. });
+            let mut out = Vec::with_capacity(text.len() * 2 + 2);
+            let units = if bom { Some(0xFEFF) } else { None }.into_iter().chain(chars()?.encode_utf16());
+            for unit in units {
+                out.extend(if le { unit.to_le_bytes() } else { unit.to_be_bytes() });
+            }
+            Ok(out)
+        }
+        Encoding::Windows1252 => chars()?.chars().map(|c| match c as u32 {
+            0x80..=0x9F | 0x100.. => WINDOWS_1252.iter().position(|w| *w == c).map(|i| 0x80 + i as u8)
+                .ok_or_else(|| format!("'{}' can't be saved as Windows-1252", c)),
+            n => Ok(n as u8),
+        }).collect(),
+    }
+}
+// What a file that isn't text gets shown as: 16 bytes to a line, in hex and as ASCII
+//
+//     00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|
+pub fn hex_dump(bytes: &[u8]) -> Vec<u8> {
+    let mut out = String::with_capacity(bytes.len() * 4 + 80);
+    for (i, row) in bytes.chunks(16).enumerate() {
+        out += &format!("{:08x} ", i * 16);
+        for k in 0..16 {
+            if k % 8 == 0 { out.push(' '); }
+            match row.get(k) {
+                Some(b) => out += &format!("{:02x} ", b),
+                None => out += "   ",
+            }
+        }
+        out.push_str(" |");
+        out.extend(row.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_utf16(s: &str, le: bool, bom: bool) -> Vec<u8> {
+        let units = if bom { Some(0xFEFF) } else { None }.into_iter().chain(s.encode_utf16());
+        units.flat_map(|u| if le { u.to_le_bytes() } else { u.to_be_bytes() }).collect()
+    }
+    // Detects `bytes` as `encoding`, decodes them into `text` and encodes that back into the same bytes
+    fn round_trip(bytes: &[u8], encoding: Encoding, text: &[u8]) {
+        assert_eq!(detect(bytes), Some(encoding), "{:?}", bytes);
+        assert_eq!(decode(bytes, encoding), text);
+        assert_eq!(encode(text, encoding).as_deref(), Ok(bytes));
+    }
+
+    #[test]
+    fn utf8() {
+        round_trip(b"plain\nascii\n", Encoding::Utf8 { bom: false }, b"plain\nascii\n");
+        round_trip("héllo wörld 日本 🦀".as_bytes(), Encoding::Utf8 { bom: false }, "héllo wörld 日本 🦀".as_bytes());
+        round_trip(b"\xEF\xBB\xBFwith a bom", Encoding::Utf8 { bom: true }, b"with a bom");
+        round_trip(b"", Encoding::Utf8 { bom: false }, b"");
+        // NOTE: A stray byte in plenty of UTF-8 stays UTF-8, the byte is kept as it is
+        round_trip(b"\xC3\xA9 \xC3\xA9 \xFF", Encoding::Utf8 { bom: false }, b"\xC3\xA9 \xC3\xA9 \xFF");
+    }
+    #[test]
+    fn utf16() {
+        let text = "héllo\r\n日本 🦀\n";
+        round_trip(&to_utf16(text, true, true), Encoding::Utf16Le { bom: true }, text.as_bytes());
+        round_trip(&to_utf16(text, false, true), Encoding::Utf16Be { bom: true }, text.as_bytes());
+        round_trip(&to_utf16("mostly ascii text\n", true, false), Encoding::Utf16Le { bom: false }, b"mostly ascii text\n");
+        round_trip(&to_utf16("mostly ascii text\n", false, false), Encoding::Utf16Be { bom: false }, b"mostly ascii text\n");
+        // A lone surrogate and a byte left over can't be anything
+        assert_eq!(decode(&[0xFF, 0xFE, 0x3D, 0xD8, b'a', 0, b'b'], Encoding::Utf16Le { bom: true }), "\u{FFFD}a\u{FFFD}".as_bytes());
+    }
+    #[test]
+    fn windows_1252() {
+        round_trip(b"caf\xE9 \x80 \x93quoted\x94 na\xEFve", Encoding::Windows1252, "café € “quoted” naïve".as_bytes());
+        assert_eq!(decode(b"\x81\x8D", Encoding::Windows1252), "\u{81}\u{8D}".as_bytes());
+        assert_eq!(encode("\u{81}\u{8D}".as_bytes(), Encoding::Windows1252), Ok(b"\x81\x8D".to_vec()));
+        assert!(encode("日本".as_bytes(), Encoding::Windows1252).is_err());
+    }
+    #[test]
+    fn broken_bytes_only_save_as_utf8() {
+        let text = b"fine\nbroken \xFF here";
+        assert_eq!(encode(text, Encoding::Utf8 { bom: false }), Ok(text.to_vec()));
+        assert_eq!(encode(text, Encoding::Utf16Le { bom: true }), Err("The byte \\xFF on line 2 can't be saved as UTF-16 LE".to_string()));
+        assert!(encode(text, Encoding::Utf16Be { bom: false }).is_err());
+        assert!(encode(text, Encoding::Windows1252).is_err());
+    }
+    #[test]
+    fn binary() {
+        assert_eq!(detect(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00"), None);
+        assert_eq!(detect(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, b'a']), None);
+        assert_eq!(detect(b"tabs\tand\x1b[1mescapes\x1b[0m\x0c"), Some(Encoding::Utf8 { bom: false }));
+        let dump = hex_dump(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00hi\n");
+        assert_eq!(String::from_utf8(dump).unwrap(), concat!(
+            "00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|\n",
+            "00000010  68 69 0a                                          |hi.|\n",
+        ));
+        assert_eq!(hex_dump(b""), b"");
+    }
+}
@@ -0,0 +1,208 @@
+// Line endings. The buffer only ever has '\n' in it, whatever the file had. What each line really
+// ended in is kept on the side and put back when saving, so a file comes out the way it went in,
+// even one with more than one kind of line ending in it
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr, // Old Macs
+}
+impl LineEnding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+    pub fn bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+            LineEnding::Cr => b"\r",
+        }
+    }
+}
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEndings {
+    pub default: LineEnding, // What most lines of the file end in, and what new lines get
+    // NOTE: The '\n's that stand for something else, by their offset in the buffer. Sorted, and they
+    // move along with the text like bookmarks do
+    pub others: Vec<(usize, LineEnding)>,
+}
+impl LineEndings {
+    pub fn new(default: LineEnding) -> Self {
+        Self { default, others: Vec::new() }
+    }
+    pub fn is_mixed(&self) -> bool {
+        !self.others.is_empty()
+    }
+    // Every line gets `to` from now on
+    pub fn convert(&mut self, to: LineEnding) {
+        self.default = to;
+        self.others.clear();
+    }
+    // Moves the line endings along after `removed` bytes at `at` got replaced with `inserted` ones. The
+    // lines that got inserted end in the default. The ones that got removed are handed back, by their
+    // offset from `at`, so that `restore` can put them back on undo
+    pub fn shift(&mut self, at: usize, removed: usize, inserted: usize) -> Vec<(usize, LineEnding)> {
+        let start = self.others.partition_point(|(p, _)| *p < at);
+        let end = self.others.partition_point(|(p, _)| *p < at + removed);
+        let gone = self.others.drain(start..end).map(|(p, e)| (p - at, e)).collect();
+        for (p, _) in self.others[start..].iter_mut() {
+            *p = *p + inserted - removed;
+        }
+        gone
+    }
+    // Puts back what `shift` handed back, once the text they were in is back at `at`
+    pub fn restore(&mut self, at: usize, endings: &[(usize, LineEnding)]) {
+        let default = self.default;
+        let start = self.others.partition_point(|(p, _)| *p < at);
+        self.others.splice(start..start, endings.iter().filter(|(_, e)| *e != default).map(|(p, e)| (at + p, *e)));
+    }
+}
+// Turns every line ending in `bytes` into '\n' and works out what they were
+pub fn split(bytes: &[u8]) -> (Vec<u8>, LineEndings) {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let ending = match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => { i += 1; LineEnding::Crlf }
+            b'\r' => LineEnding::Cr,
+            b'\n' => LineEnding::Lf,
+            b => { out.push(b); i += 1; continue; }
+        };
+        found.push((out.len(), ending));
+        out.push(b'\n');
+        i += 1;
+    }
+    // NOTE: A file without any line endings gets LF
+    let count = |e: LineEnding| found.iter().filter(|(_, f)| *f == e).count();
+    let default = [LineEnding::Crlf, LineEnding::Cr].into_iter().fold(LineEnding::Lf, |best, e| if count(e) > count(best) { e } else { best });
+    let others = found.into_iter().filter(|(_, e)| *e != default).collect();
+    (out, LineEndings { default, others })
+}
+// Just the text, for things like the clipboard where what the line endings were doesn't matter
+pub fn normalize(bytes: &[u8]) -> Vec<u8> {
+    if !bytes.contains(&b'\r') { return bytes.to_vec(); }
+    split(bytes).0
+}
+// The other way around: the text with the line endings it's meant to have, for saving
+pub fn join(text: &[u8], endings: &LineEndings) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len() + text.len() / 32);
+    let mut others = endings.others.iter().peekable();
+    for (i, b) in text.iter().enumerate() {
+        if *b != b'\n' {
+            out.push(*b);
+            continue;
+        }
+        while others.peek().is_some_and(|(p, _)| *p < i) {
+            others.next();
+        }
+        let ending = match others.peek() {
+            Some((p, e)) if *p == i => { others.next(); *e }
+            _ => endings.default,
+        };
+        out.extend_from_slice(ending.bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::{Cursor, Editor};
+
+    fn opened(bytes: &[u8]) -> Editor {
+        let (text, endings) = split(bytes);
+        let mut e = Editor::from_bytes(text);
+        e.line_endings = endings;
+        e
+    }
+    fn saved(e: &Editor) -> Vec<u8> {
+        join(&e.buffer.to_bytes(), &e.line_endings)
+    }
+
+    #[test]
+    fn split_then_join() {
+        let files: [&[u8]; 10] = [
+            b"", b"no line ending", b"a\nb\n", b"a\r\nb\r\n", b"a\rb\r", b"a\r\nb\nc\rd\r\ne\r\nf",
+            b"\r\n\n\r\r\n", b"\n\r", b"\r\r\n\n", b"trailing\r",
+        ];
+        for bytes in files {
+            let (text, endings) = split(bytes);
+            assert!(!text.contains(&b'\r'), "{:?}", bytes);
+            assert_eq!(join(&text, &endings), bytes);
+        }
+        let (text, endings) = split(b"a\r\nb\nc\rd\r\ne\r\nf");
+        assert_eq!(text, b"a\nb\nc\nd\ne\nf");
+        assert_eq!(endings, LineEndings { default: LineEnding::Crlf, others: vec![(3, LineEnding::Lf), (5, LineEnding::Cr)] });
+        // NOTE: "\n\r" is a LF and then a CR, not one line ending
+        assert_eq!(split(b"\n\r").0, b"\n\n");
+        assert_eq!(split(b"x\ry\r").1, LineEndings::new(LineEnding::Cr));
+        assert_eq!(split(b"").1, LineEndings::new(LineEnding::Lf));
+    }
+    #[test]
+    fn shift_and_restore() {
+        let (_, mut endings) = split(b"a\r\nb\nc\rd\r\ne");
+        let gone = endings.shift(2, 4, 1);
+        assert_eq!(gone, vec![(1, LineEnding::Lf), (3, LineEnding::Cr)]);
+        assert!(endings.others.is_empty());
+        endings.shift(2, 1, 4);
+        endings.restore(2, &gone);
+        assert_eq!(endings.others, vec![(3, LineEnding::Lf), (5, LineEnding::Cr)]);
+        // What's the default by now doesn't need to be kept track of
+        endings.convert(LineEnding::Lf);
+        endings.restore(0, &[(0, LineEnding::Lf), (1, LineEnding::Cr)]);
+        assert_eq!(endings.others, vec![(1, LineEnding::Cr)]);
+    }
+    #[test]
+    fn undo_brings_them_back() {
+        let file = b"a\r\nb\nc\rd\r\ne\r\nf";
+        let mut e = opened(file);
+        e.replace_range(2..8, b"");
+        assert_eq!(saved(&e), b"a\r\ne\r\nf");
+        assert!(!e.line_endings.is_mixed());
+        e.replace_range(2..2, b"x\n");
+        assert_eq!(saved(&e), b"a\r\nx\r\ne\r\nf");
+        e.undo();
+        assert_eq!(saved(&e), b"a\r\ne\r\nf");
+        e.undo();
+        assert_eq!(saved(&e), file);
+        e.redo();
+        assert_eq!(saved(&e), b"a\r\ne\r\nf");
+        e.undo();
+        assert_eq!(saved(&e), file);
+
+        // Backspacing over a line ending, and deleting with more than one cursor
+        let file = b"one\rtwo\nthree\r\nfour\r\nfive";
+        let mut e = opened(file);
+        e.move_to(Cursor { line: 1, chr: 0 }, false);
+        e.backspace();
+        assert_eq!(saved(&e), b"onetwo\nthree\r\nfour\r\nfive");
+        e.undo();
+        assert_eq!(saved(&e), file);
+        e.select_all();
+        e.backspace();
+        assert_eq!(saved(&e), b"");
+        e.undo();
+        assert_eq!(saved(&e), file);
+        e.move_to(Cursor { line: 0, chr: 3 }, false);
+        e.add_cursor(Cursor { line: 1, chr: 3 });
+        e.delete();
+        assert_eq!(saved(&e), b"onetwothree\r\nfour\r\nfive");
+        e.undo();
+        assert_eq!(saved(&e), file);
+        e.redo();
+        e.undo();
+        assert_eq!(saved(&e), file);
+    }
+}
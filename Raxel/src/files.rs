@@ -0,0 +1,60 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.raxel-{}.tmp", name, std::process::id()))
+}
+// Writes `bytes` next to `path` and then renames over it, so a crash half way through
+// never leaves you with a truncated file. Keeps the permissions of the file being replaced.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    // NOTE: Renaming over a symlink would put a file where the link was, so write to what it points to
+    let path = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let tmp = temp_path(path);
+    let res = (|| -> io::Result<()> {
+        let mut f = std::fs::File::create(&tmp)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+        if let Ok(meta) = std::fs::metadata(path) {
+            std::fs::set_permissions(&tmp, meta.permissions())?;
+        }
+        std::fs::rename(&tmp, path)
+    })();
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res
+}
+// Like std::fs::read but a file that doesn't exist yet is just empty
+pub fn read_or_new(path: &Path) -> io::Result<Vec<u8>> {
+    match std::fs::read(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_stay_links() {
+        let dir = std::env::temp_dir().join(format!("raxel-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        std::fs::write(&target, b"old").unwrap();
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        write_atomic(&link, b"new").unwrap();
+        assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(&target).unwrap(), b"new");
+        // A file that isn't there yet gets made
+        let fresh = dir.join("fresh.txt");
+        write_atomic(&fresh, b"x").unwrap();
+        assert_eq!(read_or_new(&fresh).unwrap(), b"x");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+// Fuzzy matching for pickers like quick open: the query has to show up in the text in order but
+// not necessarily next to each other, ignoring case. Matches that are close together, at the start
+// of words or in the file name score higher
+const MATCH: i32 = 16;
+const CONSECUTIVE: i32 = 15;
+const WORD_START: i32 = 10;
+const PATH_START: i32 = 12; // Right after a '/'
+const GAP_START: i32 = -3;
+const GAP_EXTEND: i32 = -1;
+const SAME_CASE: i32 = 1;
+const IN_FILE_NAME: i32 = 20;
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+fn is_separator(c: char) -> bool {
+    matches!(c, '/' | '\\' | '_' | '-' | '.' | ' ')
+}
+// The tightest window of `text[from..]` the query fits in: the first place it can end, and the
+// latest it can start for that end
+fn window(query: &[char], text: &[char], from: usize) -> Option<(usize, usize)> {
+    let mut q = 0;
+    let mut end = None;
+    for (i, c) in text.iter().enumerate().skip(from) {
+        if lower(*c) == query[q] {
+            q += 1;
+            if q == query.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut q = query.len();
+    for i in (from..=end).rev() {
+        if lower(text[i]) == query[q - 1] {
+            q -= 1;
+            if q == 0 { return Some((i, end)); }
+        }
+    }
+    None
+}
+// How well `query` matches `text`, or None if it doesn't at all. Also gives back which chars of
+// `text` matched, counting chars and not bytes, so they can be highlighted. Whitespace in the query is ignored
+pub fn score(query: &str, text: &str) -> Option<(i32, Vec<usize>)> {
+    let original: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).collect();
+    let q: Vec<char> = original.iter().map(|c| lower(*c)).collect();
+    if q.is_empty() { return Some((0, Vec::new())); }
+    let t: Vec<char> = text.chars().collect();
+    // NOTE: The file name is tried first, that's what people usually type
+    let name = t.iter().rposition(|c| *c == '/' || *c == '\\').map(|i| i + 1).unwrap_or(0);
+    let (inName, (start, end)) = match window(&q, &t, name) {
+        Some(w) => (true, w),
+        None => (false, window(&q, &t, 0)?),
+    };
+    let mut score = if inName && name > 0 { IN_FILE_NAME } else { 0 };
+    let mut positions = Vec::with_capacity(q.len());
+    let mut last: Option<usize> = None;
+    for i in start..=end {
+        if positions.len() == q.len() { break; }
+        let c = t[i];
+        if lower(c) != q[positions.len()] { continue; }
+        score += MATCH;
+        let prev = if i > 0 { Some(t[i - 1]) } else { None };
+        match prev {
+            None => score += PATH_START,
+            Some('/') | Some('\\') => score += PATH_START,
+            Some(p) if is_separator(p) || (p.is_lowercase() && c.is_uppercase()) => score += WORD_START,
+            _ => {}
+        }
+        match last {
+            Some(l) if l + 1 == i => score += CONSECUTIVE,
+            Some(l) => score += GAP_START + GAP_EXTEND * (i - l - 2) as i32,
+            None => {}
+        }
+        if original[positions.len()] == c {
+            score += SAME_CASE;
+        }
+        positions.push(i);
+        last = Some(i);
+    }
+    Some((score, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+    fn points(query: &str, text: &str) -> i32 {
+        score(query, text).unwrap().0
+    }
+
+    #[test]
+    fn tightest_window() {
+        assert_eq!(window(&chars("ab"), &chars("a_a_b_b"), 0), Some((2, 4)));
+        assert_eq!(window(&chars("ab"), &chars("ab_ab"), 1), Some((3, 4)));
+        assert_eq!(window(&chars("abc"), &chars("ab"), 0), None);
+        assert_eq!(window(&chars("ab"), &chars("ba"), 0), None);
+    }
+    #[test]
+    fn file_name_wins() {
+        assert!(points("main", "src/main.rs") > points("main", "my/app/init/n.rs"));
+        assert!(points("rs", "lib/rope.rs") > points("rs", "rope/src.c"));
+        // The whole path is looked at when the file name alone doesn't have the query in it
+        assert_eq!(score("srcmain", "src/main.rs").unwrap().1, vec![0, 1, 2, 4, 5, 6, 7]);
+    }
+    #[test]
+    fn close_together_and_word_starts_win() {
+        assert!(points("edit", "editor.rs") > points("edit", "eddit.rs"));
+        assert!(points("ab", "ab.rs") > points("ab", "a_xb.rs"));
+        assert!(points("fb", "foo_bar.rs") > points("fb", "fxbar.rs"));
+        assert!(points("fb", "fooBar.rs") > points("fb", "foobar.rs"));
+        assert!(points("Main", "Main.rs") > points("Main", "main.rs"));
+    }
+    #[test]
+    fn positions_count_chars() {
+        assert_eq!(score("ü", "dir/ü.txt").unwrap().1, vec![4]);
+        assert_eq!(score("日t", "src/日本/t.rs").unwrap().1, vec![4, 7]);
+        assert_eq!(score("ÉT", "été.rs").unwrap().1, vec![0, 1]);
+    }
+    #[test]
+    fn no_match() {
+        assert_eq!(score("xyz", "src/main.rs"), None);
+        assert_eq!(score("nm", "src/main.rs"), None);
+        assert_eq!(score(" ", "src/main.rs"), Some((0, Vec::new())));
+    }
+}
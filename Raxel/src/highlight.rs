@@ -0,0 +1,198 @@
+use std::path::Path;
+
+use crate::lexers::{CLexer, RustLexer};
+use crate::rope::Rope;
+use crate::syntax;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Normal,
+    Keyword,
+    Type,
+    String,
+    Number,
+    Comment,
+    Preprocessor, // #include in C, attributes and macros in Rust
+}
+impl TokenKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "normal"       => TokenKind::Normal,
+            "keyword"      => TokenKind::Keyword,
+            "type"         => TokenKind::Type,
+            "string"       => TokenKind::String,
+            "number"       => TokenKind::Number,
+            "comment"      => TokenKind::Comment,
+            "preprocessor" => TokenKind::Preprocessor,
+            _ => return None,
+        })
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize, // NOTE: In bytes from the start of the line
+    pub end: usize,
+    pub kind: TokenKind,
+}
+// What a line leaves open for the next one to continue
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LexState {
+    #[default]
+    Normal,
+    BlockComment(u32), // How deep, since Rust's block comments nest
+    String,
+    RawString(u32),    // How many '#'s close it
+    Contexts(Vec<u16>), // The contexts a syntax file left open, innermost last
+}
+pub trait Lexer {
+    // Splits a line (without its line ending) into spans. Bytes no span covers are Normal
+    fn lex_line(&self, line: &[u8], state: LexState) -> (Vec<Span>, LexState);
+}
+// The built in lexers come first, then whatever syntax files got loaded
+pub fn lexer_for(path: &Path) -> Option<Box<dyn Lexer>> {
+    match path.extension()?.to_str()? {
+        "c" | "h" => Some(Box::new(CLexer)),
+        "rs" => Some(Box::new(RustLexer)),
+        _ => syntax::lexer_for(path),
+    }
+}
+// Caches the spans of every line it has lexed so far. Lines get lexed lazily from the top,
+// since every line depends on the state the one before it left. After an edit only the lines it
+// touched get lexed again, and the ones after them only until they start in the same state as before
+pub struct Highlighter {
+    lexer: Box<dyn Lexer>,
+    // NOTE: states[i] is the state line i starts in, so there's always one more of them than of spans
+    states: Vec<LexState>,
+    spans: Vec<Vec<Span>>,
+    valid: usize, // Lines before this are up to date, the cached ones from here on may not be
+    edited: usize, // Lines before this have to be lexed again no matter what state they start in
+}
+impl Highlighter {
+    pub fn new(lexer: Box<dyn Lexer>) -> Self {
+        Self { lexer, states: vec![LexState::Normal], spans: Vec::new(), valid: 0, edited: 0 }
+    }
+    // Lines `line..=line + removed` got replaced with `line..=line + inserted`
+    pub fn edited(&mut self, line: usize, removed: usize, inserted: usize) {
+        if line >= self.spans.len() { return; }
+        // NOTE: Without the last line it touched there's nothing to compare to, so everything after has to go
+        if line + removed >= self.spans.len() {
+            self.spans.truncate(line);
+            self.states.truncate(line + 1);
+            self.valid = self.valid.min(line);
+            self.edited = self.edited.min(line);
+            return;
+        }
+        self.spans.splice(line..=line + removed, (0..=inserted).map(|_| Vec::new()));
+        // The state after the last line stays, it's what the new last line gets compared to
+        self.states.splice(line + 1..line + 1 + removed, (0..inserted).map(|_| LexState::Normal));
+        if self.edited > line + removed {
+            self.edited = self.edited + inserted - removed;
+        }
+        self.edited = self.edited.max(line + inserted + 1);
+        self.valid = self.valid.min(line);
+    }
+    pub fn spans(&mut self, buffer: &Rope, line: usize) -> &[Span] {
+        let line = line.min(buffer.len_lines() - 1);
+        while self.valid <= line {
+            let i = self.valid;
+            let bytes = buffer.line_bytes(i);
+            let (spans, state) = self.lexer.lex_line(&bytes, self.states[i].clone());
+            if i < self.spans.len() {
+                let same = i + 1 >= self.edited && self.states[i + 1] == state;
+                self.spans[i] = spans;
+                self.states[i + 1] = state;
+                self.valid = if same { self.spans.len() } else { i + 1 };
+            } else {
+                self.spans.push(spans);
+                self.states.push(state);
+                self.valid = i + 1;
+            }
+            if self.valid >= self.edited {
+                self.edited = 0;
+            }
+        }
+        &self.spans[line]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Counts the lines that get lexed
+    struct Counting(Rc<Cell<usize>>);
+    impl Lexer for Counting {
+        fn lex_line(&self, line: &[u8], state: LexState) -> (Vec<Span>, LexState) {
+            self.0.set(self.0.get() + 1);
+            RustLexer.lex_line(line, state)
+        }
+    }
+    fn all(h: &mut Highlighter, buffer: &Rope) -> Vec<Vec<Span>> {
+        (0..buffer.len_lines()).map(|i| h.spans(buffer, i).to_vec()).collect()
+    }
+    // Replaces `range` in the buffer and tells the highlighter, the way the editor does
+    fn replace(h: &mut Highlighter, buffer: &mut Rope, range: std::ops::Range<usize>, text: &str) {
+        let line = buffer.byte_to_line(range.start);
+        let removed = buffer.slice(range.clone()).iter().filter(|b| **b == b'\n').count();
+        buffer.replace(range, text.as_bytes());
+        h.edited(line, removed, text.matches('\n').count());
+    }
+
+    #[test]
+    fn relexes_only_what_changed() {
+        let src: String = (0..100).map(|i| format!("let x{} = {};\n", i, i)).collect();
+        let mut buffer = Rope::from_bytes(src.as_bytes());
+        let count = Rc::new(Cell::new(0));
+        let mut h = Highlighter::new(Box::new(Counting(count.clone())));
+        all(&mut h, &buffer);
+        assert_eq!(count.get(), 101);
+        let fresh = |buffer: &Rope| all(&mut Highlighter::new(Box::new(RustLexer)), buffer);
+
+        // Typing on a line only lexes that line again
+        count.set(0);
+        let at = buffer.line_to_byte(50);
+        replace(&mut h, &mut buffer, at..at, "fn ");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+        assert_eq!(count.get(), 1);
+
+        // Opening a comment changes everything after it. Taking it out again only lexes until the lines start
+        // in the same state as before
+        count.set(0);
+        let at = buffer.line_to_byte(10);
+        replace(&mut h, &mut buffer, at..at, "/*");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+        assert_eq!(count.get(), 91);
+        count.set(0);
+        let at = buffer.line_to_byte(20);
+        replace(&mut h, &mut buffer, at..at, "*/");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+        assert_eq!(count.get(), 81);
+        count.set(0);
+        let at = buffer.line_to_byte(10);
+        replace(&mut h, &mut buffer, at..at + 2, "");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+        assert_eq!(count.get(), 11);
+
+        // Lines coming and going keep the lines after them
+        count.set(0);
+        let at = buffer.line_to_byte(30);
+        replace(&mut h, &mut buffer, at..at, "a\nb\nc\n");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+        assert_eq!(count.get(), 4);
+        count.set(0);
+        let (from, to) = (buffer.line_to_byte(60), buffer.line_to_byte(70));
+        replace(&mut h, &mut buffer, from + 2..to + 2, "");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+        assert_eq!(count.get(), 1);
+
+        // Two edits before anything gets drawn again
+        count.set(0);
+        let at = buffer.line_to_byte(80);
+        replace(&mut h, &mut buffer, at..at, "x\n");
+        let at = buffer.line_to_byte(5);
+        replace(&mut h, &mut buffer, at..at + 3, "");
+        assert_eq!(all(&mut h, &buffer), fresh(&buffer));
+    }
+}
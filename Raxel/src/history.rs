@@ -0,0 +1,270 @@
+use crate::rope::Rope;
+use crate::editor::Selection;
+use crate::endings::LineEnding;
+
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub at: usize, // NOTE: In bytes
+    pub removed: Vec<u8>,
+    pub inserted: Vec<u8>,
+    // NOTE: What the '\n's in `removed` really ended in, where that wasn't the default. The buffer
+    // doesn't have it, so undo needs it from here to put them back the way they were
+    pub removed_endings: Vec<(usize, LineEnding)>,
+}
+impl Edit {
+    pub fn apply(&self, buffer: &mut Rope) {
+        buffer.replace(self.at..self.at + self.removed.len(), &self.inserted);
+    }
+    pub fn revert(&self, buffer: &mut Rope) {
+        buffer.replace(self.at..self.at + self.inserted.len(), &self.removed);
+    }
+}
+// What caused an edit. Only used to decide whether it gets merged into the previous group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Typing,
+    Deleting,
+    Other,
+}
+// A run of edits that gets undone in one go. The edits are applied in order, so each one's
+// `at` is relative to the buffer as the previous ones left it
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub edits: Vec<Edit>,
+    pub kind: EditKind,
+    pub before: Vec<Selection>,
+    pub after: Vec<Selection>,
+}
+impl Group {
+    // Whether `edits` directly continue this group, so that a word typed (or backspaced)
+    // in one go comes back with a single undo. That's the case as long as none of the cursors
+    // moved in between
+    fn continues(&self, edits: &[Edit], kind: EditKind, before: &[Selection]) -> bool {
+        if kind != self.kind || kind == EditKind::Other { return false; }
+        if self.after != before { return false; }
+        match kind {
+            EditKind::Typing => edits.iter().all(|e| e.removed.is_empty() && !e.inserted.contains(&b'\n')),
+            EditKind::Deleting => edits.iter().all(|e| e.inserted.is_empty()),
+            EditKind::Other => false,
+        }
+    }
+}
+#[derive(Debug, Default)]
+pub struct History {
+    pub undo: Vec<Group>,
+    pub redo: Vec<Group>,
+    sealed: bool,
+    group_start: Option<usize>, // Where the group begin() started is in the undo stack
+    // How many groups there were in the undo stack when the file was saved. None once there's no going back to that
+    saved: Option<usize>,
+}
+impl History {
+    pub fn new() -> Self {
+        Self { undo: Vec::new(), redo: Vec::new(), sealed: false, group_start: None, saved: Some(0) }
+    }
+    // NOTE: Seals too, so that typing right after saving doesn't go into the group from before it
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+        self.seal();
+    }
+    // For changes that don't go through the history, like what the file gets saved in
+    pub fn mark_unsaved(&mut self) {
+        self.saved = None;
+    }
+    // Whether undoing and redoing got back to what's in the file
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+    // Records edits that have already been applied to the buffer
+    pub fn record(&mut self, edits: Vec<Edit>, kind: EditKind, before: Vec<Selection>, after: Vec<Selection>) {
+        if edits.is_empty() { return; }
+        // NOTE: The saved state may have been undone, in which case it's gone with the redo stack
+        if self.saved.is_some_and(|s| s > self.undo.len()) {
+            self.saved = None;
+        }
+        self.redo.clear();
+        if !self.sealed {
+            if let Some(group) = self.undo.last_mut() {
+                if group.continues(&edits, kind, &before) {
+                    group.edits.extend(edits);
+                    group.after = after;
+                    return;
+                }
+            }
+        }
+        self.sealed = false;
+        self.undo.push(Group { edits, kind, before, after });
+    }
+    // Stops the next edit from being merged into the current group. Call it whenever the
+    // cursor jumps somewhere on its own
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+    // Everything recorded between begin and end gets undone as one group, no matter what kind it was.
+    // For commands that are made of multiple edits
+    pub fn begin(&mut self) {
+        self.seal();
+        self.group_start = Some(self.undo.len());
+    }
+    pub fn end(&mut self) {
+        let Some(start) = self.group_start.take() else { return; };
+        if self.undo.len() > start + 1 {
+            // Saving in between the groups leaves a state that can't be undone to on its own anymore
+            if let Some(s) = self.saved.filter(|s| *s > start) {
+                self.saved = if s == self.undo.len() { Some(start + 1) } else { None };
+            }
+            let rest: Vec<Group> = self.undo.drain(start + 1..).collect();
+            let first = &mut self.undo[start];
+            for group in rest {
+                first.edits.extend(group.edits);
+                first.after = group.after;
+            }
+            first.kind = EditKind::Other;
+        }
+        self.seal();
+    }
+    // Reverts the last group and gives back the cursors from before it
+    pub fn undo(&mut self, buffer: &mut Rope) -> Option<Vec<Selection>> {
+        let group = self.undo.pop()?;
+        for edit in group.edits.iter().rev() {
+            edit.revert(buffer);
+        }
+        let cursors = group.before.clone();
+        self.redo.push(group);
+        self.sealed = true;
+        Some(cursors)
+    }
+    pub fn redo(&mut self, buffer: &mut Rope) -> Option<Vec<Selection>> {
+        let group = self.redo.pop()?;
+        for edit in group.edits.iter() {
+            edit.apply(buffer);
+        }
+        let cursors = group.after.clone();
+        self.undo.push(group);
+        self.sealed = true;
+        Some(cursors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Cursor;
+
+    fn caret(chr: usize) -> Vec<Selection> {
+        vec![Selection::caret(Cursor { line: 0, chr })]
+    }
+    // Applies the edits the way the editor does and records them, with the cursor at `from` before and `to` after
+    fn edit(buffer: &mut Rope, history: &mut History, edits: Vec<Edit>, kind: EditKind, from: usize, to: usize) {
+        for e in edits.iter() {
+            e.apply(buffer);
+        }
+        history.record(edits, kind, caret(from), caret(to));
+    }
+    fn typed(at: usize, text: &str) -> Vec<Edit> {
+        vec![Edit { at, removed: Vec::new(), inserted: text.as_bytes().to_vec(), removed_endings: Vec::new() }]
+    }
+    fn text(buffer: &Rope) -> String {
+        String::from_utf8(buffer.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn typing_coalesces() {
+        let mut b = Rope::new();
+        let mut h = History::new();
+        for (i, c) in "word".chars().enumerate() {
+            edit(&mut b, &mut h, typed(i, &c.to_string()), EditKind::Typing, i, i + 1);
+        }
+        assert_eq!(h.undo.len(), 1);
+        assert_eq!(h.undo(&mut b), Some(caret(0)));
+        assert_eq!(text(&b), "");
+        assert_eq!(h.redo(&mut b), Some(caret(4)));
+        assert_eq!(text(&b), "word");
+    }
+    #[test]
+    fn moving_or_changing_kind_seals() {
+        let mut b = Rope::new();
+        let mut h = History::new();
+        edit(&mut b, &mut h, typed(0, "ab"), EditKind::Typing, 0, 2);
+        // The cursor went somewhere else in between
+        edit(&mut b, &mut h, typed(0, "x"), EditKind::Typing, 0, 1);
+        assert_eq!(h.undo.len(), 2);
+        edit(&mut b, &mut h, vec![Edit { at: 0, removed: b"x".to_vec(), inserted: Vec::new(), removed_endings: Vec::new() }], EditKind::Deleting, 1, 0);
+        assert_eq!(h.undo.len(), 3);
+        edit(&mut b, &mut h, typed(2, "c"), EditKind::Typing, 0, 3);
+        assert_eq!(h.undo.len(), 4);
+        // A line ending never gets merged into the word before it
+        edit(&mut b, &mut h, typed(3, "\n"), EditKind::Typing, 3, 4);
+        assert_eq!(h.undo.len(), 5);
+        h.seal();
+        edit(&mut b, &mut h, typed(4, "d"), EditKind::Typing, 4, 5);
+        assert_eq!(h.undo.len(), 6);
+        assert_eq!(text(&b), "abc\nd");
+    }
+    #[test]
+    fn backspacing_coalesces() {
+        let mut b = Rope::from_bytes(b"hello");
+        let mut h = History::new();
+        for i in (2..5).rev() {
+            edit(&mut b, &mut h, vec![Edit { at: i, removed: vec![b"hello"[i]], inserted: Vec::new(), removed_endings: Vec::new() }], EditKind::Deleting, i + 1, i);
+        }
+        assert_eq!(text(&b), "he");
+        assert_eq!(h.undo.len(), 1);
+        assert_eq!(h.undo(&mut b), Some(caret(5)));
+        assert_eq!(text(&b), "hello");
+    }
+    #[test]
+    fn begin_end_groups_cursors() {
+        let mut b = Rope::from_bytes(b"a\nb\nc");
+        let mut h = History::new();
+        h.begin();
+        // Three cursors, applied back to front like edit_each does
+        edit(&mut b, &mut h, vec![Edit { at: 4, removed: Vec::new(), inserted: b"-".to_vec(), removed_endings: Vec::new() }, Edit { at: 2, removed: Vec::new(), inserted: b"-".to_vec(), removed_endings: Vec::new() }, Edit { at: 0, removed: Vec::new(), inserted: b"-".to_vec(), removed_endings: Vec::new() }], EditKind::Typing, 0, 1);
+        edit(&mut b, &mut h, vec![Edit { at: 1, removed: b"a".to_vec(), inserted: b"A".to_vec(), removed_endings: Vec::new() }], EditKind::Other, 1, 2);
+        h.end();
+        assert_eq!(text(&b), "-A\n-b\n-c");
+        assert_eq!(h.undo.len(), 1);
+        assert_eq!(h.undo(&mut b), Some(caret(0)));
+        assert_eq!(text(&b), "a\nb\nc");
+        assert_eq!(h.redo(&mut b), Some(caret(2)));
+        assert_eq!(text(&b), "-A\n-b\n-c");
+    }
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut b = Rope::new();
+        let mut h = History::new();
+        edit(&mut b, &mut h, typed(0, "one"), EditKind::Other, 0, 3);
+        edit(&mut b, &mut h, typed(3, " two"), EditKind::Other, 3, 7);
+        h.undo(&mut b);
+        assert_eq!(h.redo.len(), 1);
+        edit(&mut b, &mut h, typed(3, "!"), EditKind::Other, 3, 4);
+        assert!(h.redo.is_empty());
+        assert_eq!(h.redo(&mut b), None);
+        assert_eq!(text(&b), "one!");
+    }
+    #[test]
+    fn saved_state() {
+        let mut b = Rope::new();
+        let mut h = History::new();
+        assert!(h.is_saved());
+        edit(&mut b, &mut h, typed(0, "ab"), EditKind::Typing, 0, 2);
+        h.mark_saved();
+        // Typing on right after saving starts a new group, so it can be undone back to what was saved
+        edit(&mut b, &mut h, typed(2, "c"), EditKind::Typing, 2, 3);
+        assert!(!h.is_saved());
+        h.undo(&mut b);
+        assert!(h.is_saved());
+        h.undo(&mut b);
+        assert!(!h.is_saved());
+        h.redo(&mut b);
+        assert!(h.is_saved());
+        // Undoing past what was saved and editing from there means it can't be gotten back to
+        h.undo(&mut b);
+        edit(&mut b, &mut h, typed(0, "x"), EditKind::Other, 0, 1);
+        h.undo(&mut b);
+        assert!(!h.is_saved());
+        h.mark_saved();
+        h.mark_unsaved();
+        assert!(!h.is_saved());
+    }
+}
@@ -0,0 +1,36 @@
+use beryllium::events::Event;
+use fermium::prelude::*;
+
+// beryllium drops any event it doesn't know about while polling, SDL_TEXTEDITING included.
+// So we poll SDL ourselves and only hand the rest over to beryllium
+#[derive(Debug)]
+pub enum Input {
+    Event(Event),
+    // The IME's current composition. `start` is where its caret is, in chars
+    TextEditing { text: String, start: usize },
+}
+pub fn poll() -> Option<Input> {
+    let mut sdl_event = SDL_Event::default();
+    while unsafe { SDL_PollEvent(&mut sdl_event) } != 0 {
+        if unsafe { sdl_event.type_ } == SDL_TEXTEDITING {
+            let v = unsafe { sdl_event.edit };
+            let bytes: &[u8] = bytemuck::cast_slice(v.text.as_slice());
+            let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            let text = String::from_utf8_lossy(&bytes[..len]).into_owned();
+            return Some(Input::TextEditing { text, start: v.start.max(0) as usize });
+        }
+        if let Ok(event) = Event::try_from(sdl_event) {
+            return Some(Input::Event(event));
+        }
+    }
+    None
+}
+// Without this SDL doesn't send any SDL_TEXTINPUT/SDL_TEXTEDITING events on some platforms
+pub fn start_text_input() {
+    unsafe { SDL_StartTextInput() }
+}
+// Tells the IME where to put its candidate window. Takes window coordinates (top left origin)
+pub fn set_text_input_rect(x: i32, y: i32, w: i32, h: i32) {
+    let mut rect = SDL_Rect { x, y, w, h };
+    unsafe { SDL_SetTextInputRect(&mut rect) }
+}
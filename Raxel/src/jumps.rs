@@ -0,0 +1,115 @@
+// Where the cursor was before it jumped somewhere, for going back there with Alt+Left and forward
+// again with Alt+Right. A jump is any move to another buffer or over more than JUMP_LINES lines,
+// so walking through the text with the arrow keys doesn't fill up the list
+use std::path::PathBuf;
+
+use crate::editor::{Cursor, Editor};
+
+const JUMP_LINES: usize = 10;
+const MAX_JUMPS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub path: PathBuf,
+    pub cursor: Cursor,
+}
+impl Jump {
+    // NOTE: A buffer without a file can't be found again once it's closed, so it's never jumped back to
+    pub fn of(e: &Editor) -> Option<Self> {
+        Some(Self { path: e.path.clone()?, cursor: e.primary().head })
+    }
+    fn is_far(&self, other: &Jump) -> bool {
+        self.path != other.path || self.cursor.line.abs_diff(other.cursor.line) > JUMP_LINES
+    }
+}
+pub struct JumpList {
+    back: Vec<Jump>,
+    forward: Vec<Jump>,
+    last: Option<Jump>, // Where the cursor was the last time it was looked at
+}
+impl JumpList {
+    pub fn new() -> Self {
+        Self { back: Vec::new(), forward: Vec::new(), last: None }
+    }
+    // Looks at where the cursor is now, once a frame. If it jumped there, where it came from gets remembered
+    pub fn track(&mut self, here: Option<Jump>) {
+        if let (Some(last), Some(now)) = (&self.last, &here) {
+            if last.is_far(now) {
+                self.back.push(last.clone());
+                if self.back.len() > MAX_JUMPS {
+                    self.back.remove(0);
+                }
+                self.forward.clear();
+            }
+        }
+        self.last = here;
+    }
+    // Where to go back to from `here`, which is where going forward again leads
+    pub fn back(&mut self, here: Option<Jump>) -> Option<Jump> {
+        let to = self.back.pop()?;
+        self.forward.extend(here);
+        Some(to)
+    }
+    pub fn forward(&mut self, here: Option<Jump>) -> Option<Jump> {
+        let to = self.forward.pop()?;
+        self.back.extend(here);
+        Some(to)
+    }
+    // Takes `here` as where the cursor was all along. Going back and forth isn't a jump of its own
+    pub fn skip(&mut self, here: Option<Jump>) {
+        self.last = here;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(path: &str, line: usize) -> Option<Jump> {
+        Some(Jump { path: PathBuf::from(path), cursor: Cursor { line, chr: 0 } })
+    }
+
+    #[test]
+    fn only_far_moves_are_jumps() {
+        let mut jumps = JumpList::new();
+        jumps.track(at("a", 0));
+        jumps.track(at("a", 5));
+        jumps.track(at("a", 5 + JUMP_LINES));
+        assert!(jumps.back.is_empty());
+        jumps.track(at("a", 30));
+        jumps.track(at("b", 30));
+        assert_eq!(jumps.back, vec![at("a", 15).unwrap(), at("a", 30).unwrap()]);
+        // Buffers without a file don't count
+        jumps.track(None);
+        jumps.track(at("b", 100));
+        assert_eq!(jumps.back.len(), 2);
+        for line in 0..MAX_JUMPS + 5 {
+            jumps.track(at("c", line * 100));
+        }
+        assert_eq!(jumps.back.len(), MAX_JUMPS);
+    }
+    #[test]
+    fn back_and_forward() {
+        let mut jumps = JumpList::new();
+        jumps.track(at("a", 0));
+        jumps.track(at("a", 50));
+        jumps.track(at("b", 0));
+        assert_eq!(jumps.back(at("b", 0)), at("a", 50));
+        jumps.skip(at("a", 50));
+        jumps.track(at("a", 50));
+        assert_eq!(jumps.back(at("a", 50)), at("a", 0));
+        jumps.skip(at("a", 0));
+        assert_eq!(jumps.back(at("a", 0)), None);
+        assert_eq!(jumps.forward(at("a", 0)), at("a", 50));
+        jumps.skip(at("a", 50));
+        assert_eq!(jumps.forward(at("a", 50)), at("b", 0));
+        jumps.skip(at("b", 0));
+        assert_eq!(jumps.forward(at("b", 0)), None);
+        assert_eq!(jumps.back(at("b", 0)), at("a", 50));
+        jumps.skip(at("a", 50));
+        // Jumping somewhere new drops what going forward would have led to
+        jumps.track(at("c", 0));
+        assert_eq!(jumps.forward(at("c", 0)), None);
+        assert_eq!(jumps.back(at("c", 0)), at("a", 50));
+    }
+}
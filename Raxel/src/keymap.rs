@@ -0,0 +1,320 @@
+// Which keys run which commands. A keymap file has one binding per line, TOML-like:
+//
+//     # A chord is any of Ctrl, Shift, Alt and Super followed by a key, joined with '+'
+//     "Ctrl+S" = "file.save"
+//     # Chords separated by spaces have to be pressed one after the other
+//     "Ctrl+K Ctrl+W" = "view.toggle_wrap"
+//     # An empty command takes the default binding away
+//     "Alt+Z" = ""
+//
+// The keys are letters, digits, punctuation and names like Up, PageDown, Enter, Escape, Tab, Space
+// or F1 to F12. Nothing in quotes gets escaped, "Ctrl+\" is Ctrl and the backslash key. Bindings from
+// the file go on top of the default ones
+use std::fmt;
+
+use beryllium::events::*;
+
+use crate::theme::strip_comment;
+
+pub const DEFAULT: &str = r#"
+"Left" = "cursor.left"
+"Right" = "cursor.right"
+"Up" = "cursor.up"
+"Down" = "cursor.down"
+"Home" = "cursor.home"
+"End" = "cursor.end"
+"Shift+Left" = "select.left"
+"Shift+Right" = "select.right"
+"Shift+Up" = "select.up"
+"Shift+Down" = "select.down"
+"Shift+Home" = "select.home"
+"Shift+End" = "select.end"
+"Alt+Shift+Up" = "cursor.add_above"
+"Alt+Shift+Down" = "cursor.add_below"
+"Escape" = "cursor.clear_extra"
+"Backspace" = "edit.backspace"
+"Shift+Backspace" = "edit.backspace"
+"Delete" = "edit.delete"
+"Enter" = "edit.newline"
+"Shift+Enter" = "edit.newline"
+"Tab" = "edit.tab"
+"Ctrl+S" = "file.save"
+"Ctrl+Shift+S" = "file.save_as"
+"Ctrl+Q" = "app.quit"
+"Ctrl+O" = "file.open"
+"Ctrl+P" = "file.quick_open"
+"Ctrl+Shift+P" = "app.command_palette"
+"Ctrl+N" = "buffer.new"
+"Ctrl+W" = "buffer.close"
+"Ctrl+Tab" = "buffer.next"
+"Ctrl+Shift+Tab" = "buffer.previous"
+"Ctrl+PageDown" = "buffer.next"
+"Ctrl+PageUp" = "buffer.previous"
+"Ctrl+\" = "view.split_right"
+"Ctrl+K Ctrl+\" = "view.split_down"
+"Ctrl+K Ctrl+X" = "view.close_pane"
+"Ctrl+K Ctrl+Right" = "view.focus_next_pane"
+"Ctrl+K Ctrl+Left" = "view.focus_previous_pane"
+"Alt+N" = "view.toggle_line_numbers"
+"Alt+Z" = "view.toggle_wrap"
+"Ctrl+K Ctrl+N" = "view.toggle_line_numbers"
+"Ctrl+K Ctrl+W" = "view.toggle_wrap"
+"Ctrl+Z" = "edit.undo"
+"Ctrl+Shift+Z" = "edit.redo"
+"Ctrl+Y" = "edit.redo"
+"Ctrl+A" = "select.all"
+"Ctrl+D" = "select.next_occurrence"
+"Ctrl+C" = "edit.copy"
+"Ctrl+X" = "edit.cut"
+"Ctrl+V" = "edit.paste"
+"Ctrl+F" = "find.open"
+"Ctrl+H" = "find.replace"
+"F3" = "find.next"
+"Shift+F3" = "find.previous"
+"Ctrl+Shift+F" = "find.in_files"
+"Ctrl+G" = "cursor.goto_line"
+"Alt+Left" = "jump.back"
+"Alt+Right" = "jump.forward"
+"Ctrl+F2" = "bookmark.toggle"
+"F2" = "bookmark.next"
+"Shift+F2" = "bookmark.previous"
+"Ctrl+1" = "bookmark.go 1"
+"Ctrl+Shift+1" = "bookmark.set 1"
+"Ctrl+2" = "bookmark.go 2"
+"Ctrl+Shift+2" = "bookmark.set 2"
+"Ctrl+3" = "bookmark.go 3"
+"Ctrl+Shift+3" = "bookmark.set 3"
+"Ctrl+4" = "bookmark.go 4"
+"Ctrl+Shift+4" = "bookmark.set 4"
+"Ctrl+5" = "bookmark.go 5"
+"Ctrl+Shift+5" = "bookmark.set 5"
+"Ctrl+6" = "bookmark.go 6"
+"Ctrl+Shift+6" = "bookmark.set 6"
+"Ctrl+7" = "bookmark.go 7"
+"Ctrl+Shift+7" = "bookmark.set 7"
+"Ctrl+8" = "bookmark.go 8"
+"Ctrl+Shift+8" = "bookmark.set 8"
+"Ctrl+9" = "bookmark.go 9"
+"Ctrl+Shift+9" = "bookmark.set 9"
+"#;
+
+const NAMED: &[(&str, SDL_Keycode)] = &[
+    ("Up", SDLK_UP), ("Down", SDLK_DOWN), ("Left", SDLK_LEFT), ("Right", SDLK_RIGHT),
+    ("Home", SDLK_HOME), ("End", SDLK_END), ("PageUp", SDLK_PAGEUP), ("PageDown", SDLK_PAGEDOWN),
+    ("Enter", SDLK_RETURN), ("Return", SDLK_RETURN), ("KpEnter", SDLK_KP_ENTER), ("Escape", SDLK_ESCAPE), ("Esc", SDLK_ESCAPE),
+    ("Backspace", SDLK_BACKSPACE), ("Delete", SDLK_DELETE), ("Insert", SDLK_INSERT), ("Tab", SDLK_TAB), ("Space", SDLK_SPACE),
+    ("F1", SDLK_F1), ("F2", SDLK_F2), ("F3", SDLK_F3), ("F4", SDLK_F4), ("F5", SDLK_F5), ("F6", SDLK_F6),
+    ("F7", SDLK_F7), ("F8", SDLK_F8), ("F9", SDLK_F9), ("F10", SDLK_F10), ("F11", SDLK_F11), ("F12", SDLK_F12),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub key: SDL_Keycode,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub gui: bool,
+}
+impl Chord {
+    pub fn new(key: SDL_Keycode, modifiers: SDL_Keymod) -> Self {
+        Self {
+            key,
+            ctrl: modifiers.0 & KMOD_CTRL.0 != 0,
+            shift: modifiers.0 & KMOD_SHIFT.0 != 0,
+            alt: modifiers.0 & KMOD_ALT.0 != 0,
+            gui: modifiers.0 & KMOD_GUI.0 != 0,
+        }
+    }
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut chord = Self { key: SDLK_UNKNOWN, ctrl: false, shift: false, alt: false, gui: false };
+        // NOTE: "Ctrl++" is Ctrl and the '+' key, so the key is whatever comes after the last modifier
+        let mut rest = s;
+        while let Some((modifier, after)) = rest.split_once('+').filter(|(_, after)| !after.is_empty()) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => chord.ctrl = true,
+                "shift" => chord.shift = true,
+                "alt" => chord.alt = true,
+                "super" | "gui" | "cmd" => chord.gui = true,
+                _ => return Err(format!("Unknown modifier '{}' in '{}'", modifier, s)),
+            }
+            rest = after;
+        }
+        chord.key = if let Some((_, key)) = NAMED.iter().find(|(name, _)| name.eq_ignore_ascii_case(rest)) {
+            *key
+        } else {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                // NOTE: SDL names printable keys by the lowercase character they type
+                (Some(c), None) if c.is_ascii_graphic() => SDL_Keycode(c.to_ascii_lowercase() as i32),
+                _ => return Err(format!("Unknown key '{}'", rest)),
+            }
+        };
+        Ok(chord)
+    }
+}
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl { write!(f, "Ctrl+")?; }
+        if self.alt { write!(f, "Alt+")?; }
+        if self.shift { write!(f, "Shift+")?; }
+        if self.gui { write!(f, "Super+")?; }
+        match NAMED.iter().find(|(_, key)| *key == self.key) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "{}", char::from_u32(self.key.0 as u32).unwrap_or('?').to_ascii_uppercase()),
+        }
+    }
+}
+pub fn parse_sequence(s: &str) -> Result<Vec<Chord>, String> {
+    let chords = s.split_whitespace().map(Chord::parse).collect::<Result<Vec<_>, _>>()?;
+    if chords.is_empty() { return Err("Empty key sequence".to_string()); }
+    Ok(chords)
+}
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyResult {
+    Unbound,
+    Pending, // The chord starts a longer sequence, wait for the next one
+    Run(String),
+}
+pub struct Binding {
+    pub keys: Vec<Chord>,
+    pub command: String,
+}
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+    pending: Vec<Chord>,
+}
+impl Keymap {
+    pub fn new() -> Self {
+        Self { bindings: Vec::new(), pending: Vec::new() }
+    }
+    pub fn default_keymap() -> Self {
+        let mut keymap = Self::new();
+        keymap.load(DEFAULT).expect("The default keymap should parse");
+        keymap
+    }
+    // Binds `keys` to `command`, replacing whatever they were bound to. An empty command unbinds them
+    pub fn bind(&mut self, keys: Vec<Chord>, command: &str) {
+        self.bindings.retain(|b| b.keys != keys);
+        if !command.is_empty() {
+            self.bindings.push(Binding { keys, command: command.to_string() });
+        }
+    }
+    // Adds the bindings in `src` on top of the ones already there
+    pub fn load(&mut self, src: &str) -> Result<(), String> {
+        for (n, line) in src.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() || (line.starts_with('[') && line.ends_with(']')) { continue; }
+            let err = |e: String| format!("line {}: {}", n + 1, e);
+            // NOTE: The keys can have '=' in them, so split at the one right before the command
+            let (keys, command) = line.rsplit_once('=').ok_or_else(|| err("Expected `\"keys\" = \"command\"`".to_string()))?;
+            let unquote = |s: &str| { let s = s.trim(); s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s).to_string() };
+            let keys = parse_sequence(&unquote(keys)).map_err(err)?;
+            self.bind(keys, &unquote(command));
+        }
+        Ok(())
+    }
+    // The first sequence bound to `command`, for showing next to it
+    pub fn keys_for(&self, command: &str) -> Option<&[Chord]> {
+        self.bindings.iter().find(|b| b.command == command).map(|b| b.keys.as_slice())
+    }
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+    pub fn press(&mut self, chord: Chord) -> KeyResult {
+        // NOTE: Holding Ctrl down between Ctrl+K and Ctrl+C sends its own key events, those don't count
+        const MODIFIERS: &[SDL_Keycode] = &[SDLK_LCTRL, SDLK_RCTRL, SDLK_LSHIFT, SDLK_RSHIFT, SDLK_LALT, SDLK_RALT, SDLK_LGUI, SDLK_RGUI];
+        if MODIFIERS.contains(&chord.key) {
+            return if self.is_pending() { KeyResult::Pending } else { KeyResult::Unbound };
+        }
+        self.pending.push(chord);
+        let mut longer = false;
+        for binding in self.bindings.iter() {
+            if binding.keys == self.pending {
+                self.pending.clear();
+                return KeyResult::Run(binding.command.clone());
+            }
+            longer |= binding.keys.starts_with(&self.pending);
+        }
+        if longer { return KeyResult::Pending; }
+        // NOTE: A chord that doesn't continue the sequence cancels it, and isn't tried on its own
+        self.pending.clear();
+        KeyResult::Unbound
+    }
+}
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::default_keymap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(s: &str) -> Chord {
+        Chord::parse(s).unwrap()
+    }
+    fn run(command: &str) -> KeyResult {
+        KeyResult::Run(command.to_string())
+    }
+
+    #[test]
+    fn parse_chords() {
+        let plus = chord("Ctrl++");
+        assert!(plus.ctrl && !plus.shift && !plus.alt && !plus.gui);
+        assert_eq!(plus.key, SDL_Keycode('+' as i32));
+        assert_eq!(chord("+").key, SDL_Keycode('+' as i32));
+        assert_eq!(chord("Ctrl+\\").key, SDL_Keycode('\\' as i32));
+        assert_eq!(chord("PageDown").key, SDLK_PAGEDOWN);
+        assert_eq!(chord("esc").key, SDLK_ESCAPE);
+        assert_eq!(chord("Alt+F12"), Chord::new(SDLK_F12, KMOD_LALT));
+        // Modifiers come in any order and letters in either case, left and right Shift are the same
+        let save_as = chord("Ctrl+Shift+S");
+        assert_eq!(save_as.key, SDLK_s);
+        assert_eq!(chord("shift+ctrl+s"), save_as);
+        assert_eq!(Chord::new(SDLK_s, SDL_Keymod(KMOD_LCTRL.0 | KMOD_RSHIFT.0)), save_as);
+        assert_eq!(Chord::new(SDLK_s, SDL_Keymod(KMOD_RCTRL.0 | KMOD_LSHIFT.0)), save_as);
+        assert_eq!(save_as.to_string(), "Ctrl+Shift+S");
+        assert!(Chord::parse("Hyper+A").is_err());
+        assert!(Chord::parse("Ctrl+Nope").is_err());
+        assert!(parse_sequence(" ").is_err());
+    }
+    #[test]
+    fn sequences() {
+        let mut keymap = Keymap::default_keymap();
+        keymap.load("\"Ctrl+K Ctrl+C\" = \"edit.comment\"").unwrap();
+        assert_eq!(keymap.press(chord("Ctrl+K")), KeyResult::Pending);
+        // Ctrl held down in between
+        assert_eq!(keymap.press(Chord::new(SDLK_LCTRL, KMOD_LCTRL)), KeyResult::Pending);
+        assert_eq!(keymap.press(chord("Ctrl+C")), run("edit.comment"));
+        assert!(!keymap.is_pending());
+        assert_eq!(keymap.press(chord("Ctrl+C")), run("edit.copy"));
+        // A chord that doesn't go on with the sequence cancels it without running on its own
+        assert_eq!(keymap.press(chord("Ctrl+K")), KeyResult::Pending);
+        assert_eq!(keymap.press(chord("Ctrl+V")), KeyResult::Unbound);
+        assert!(!keymap.is_pending());
+        assert_eq!(keymap.press(chord("Ctrl+V")), run("edit.paste"));
+        assert_eq!(keymap.press(Chord::new(SDLK_LSHIFT, KMOD_LSHIFT)), KeyResult::Unbound);
+    }
+    #[test]
+    fn user_file() {
+        let mut keymap = Keymap::default_keymap();
+        let src = r#"
+            # Comments and section headers are skipped
+            [keys]
+            "Ctrl+S" = "file.save_as" # Even after a binding
+            "Alt+Z" = ""
+            "Ctrl+=" = "view.zoom_in"
+        "#;
+        keymap.load(src).unwrap();
+        assert_eq!(keymap.press(chord("Ctrl+S")), run("file.save_as"));
+        assert_eq!(keymap.press(chord("Alt+Z")), KeyResult::Unbound);
+        assert_eq!(keymap.press(chord("Ctrl+=")), run("view.zoom_in"));
+        // Only the binding that was taken away is gone
+        assert_eq!(keymap.keys_for("view.toggle_wrap"), Some(&[chord("Ctrl+K"), chord("Ctrl+W")][..]));
+        assert_eq!(keymap.keys_for("file.save"), None);
+        let err = keymap.load("\n\"Ctrl+Nope\" = \"file.save\"").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        assert!(keymap.load("\"Ctrl+S\" file.save").is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::highlight::{LexState, Lexer, Span, TokenKind};
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_' || b >= 0x80
+}
+fn is_ident(b: u8) -> bool {
+    is_ident_start(b) || b.is_ascii_digit()
+}
+fn ident_end(line: &[u8], mut i: usize) -> usize {
+    while i < line.len() && is_ident(line[i]) {
+        i += 1;
+    }
+    i
+}
+// 0x1F, 1_000u32, 1.5e-3 and friends. Stops at `..` so ranges stay ranges
+fn number_end(line: &[u8], mut i: usize) -> usize {
+    let hex = line[i..].starts_with(b"0x") || line[i..].starts_with(b"0X");
+    while i < line.len() {
+        let b = line[i];
+        let exponent = !hex && (b == b'+' || b == b'-') && i > 0 && (line[i - 1] == b'e' || line[i - 1] == b'E');
+        let fraction = b == b'.' && line.get(i + 1).is_some_and(|b| b.is_ascii_digit());
+        if !(b.is_ascii_alphanumeric() || b == b'_' || exponent || fraction) {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+// Scans a string from right after its opening quote. Gives back where it ends and whether it got closed
+fn string_end(line: &[u8], mut i: usize, quote: u8) -> (usize, bool) {
+    while i < line.len() {
+        match line[i] {
+            b'\\' => i += 2,
+            b if b == quote => return (i + 1, true),
+            _ => i += 1,
+        }
+    }
+    (line.len(), false)
+}
+// Scans a block comment from right after its opening. Gives back where it ends and how deep it still is there
+fn block_comment_end(line: &[u8], mut i: usize, mut depth: u32, nested: bool) -> (usize, u32) {
+    while i < line.len() {
+        if nested && line[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        } else if line[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 { return (i, 0); }
+        } else {
+            i += 1;
+        }
+    }
+    (line.len(), depth)
+}
+fn push(spans: &mut Vec<Span>, start: usize, end: usize, kind: TokenKind) {
+    if start < end {
+        spans.push(Span { start, end, kind });
+    }
+}
+
+pub struct CLexer;
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for", "goto", "if",
+    "inline", "register", "restrict", "return", "sizeof", "static", "struct", "switch", "typedef", "union",
+    "volatile", "while", "true", "false", "NULL",
+];
+const C_TYPES: &[&str] = &[
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "bool", "_Bool", "FILE",
+];
+impl Lexer for CLexer {
+    fn lex_line(&self, line: &[u8], state: LexState) -> (Vec<Span>, LexState) {
+        let mut spans = Vec::new();
+        let mut i = 0;
+        match state {
+            LexState::BlockComment(depth) => {
+                let (end, depth) = block_comment_end(line, 0, depth, false);
+                push(&mut spans, 0, end, TokenKind::Comment);
+                if depth > 0 { return (spans, LexState::BlockComment(depth)); }
+                i = end;
+            }
+            LexState::String => {
+                let (end, closed) = string_end(line, 0, b'"');
+                push(&mut spans, 0, end, TokenKind::String);
+                if !closed && line.ends_with(b"\\") { return (spans, LexState::String); }
+                i = end;
+            }
+            _ => {}
+        }
+        // Preprocessor directives are only a thing at the start of a line
+        let indent = line.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        if i == 0 && line.get(indent) == Some(&b'#') {
+            let mut j = indent + 1;
+            while j < line.len() && line[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            let end = ident_end(line, j);
+            push(&mut spans, indent, end, TokenKind::Preprocessor);
+            i = end;
+            if &line[j..end] == b"include" {
+                let start = i + line[i..].iter().take_while(|b| b.is_ascii_whitespace()).count();
+                if line.get(start) == Some(&b'<') {
+                    let close = line[start..].iter().position(|b| *b == b'>').map(|p| start + p + 1).unwrap_or(line.len());
+                    push(&mut spans, start, close, TokenKind::String);
+                    i = close;
+                }
+            }
+        }
+        while i < line.len() {
+            let b = line[i];
+            if line[i..].starts_with(b"//") {
+                push(&mut spans, i, line.len(), TokenKind::Comment);
+                break;
+            } else if line[i..].starts_with(b"/*") {
+                let (end, depth) = block_comment_end(line, i + 2, 1, false);
+                push(&mut spans, i, end, TokenKind::Comment);
+                if depth > 0 { return (spans, LexState::BlockComment(depth)); }
+                i = end;
+            } else if b == b'"' || b == b'\'' {
+                let (end, closed) = string_end(line, i + 1, b);
+                push(&mut spans, i, end, TokenKind::String);
+                // NOTE: Only a backslash right before the newline lets a string go on
+                if !closed && b == b'"' && line.ends_with(b"\\") { return (spans, LexState::String); }
+                i = end;
+            } else if b.is_ascii_digit() {
+                let end = number_end(line, i);
+                push(&mut spans, i, end, TokenKind::Number);
+                i = end;
+            } else if is_ident_start(b) {
+                let end = ident_end(line, i);
+                let word = std::str::from_utf8(&line[i..end]).unwrap_or("");
+                if C_KEYWORDS.contains(&word) {
+                    push(&mut spans, i, end, TokenKind::Keyword);
+                } else if C_TYPES.contains(&word) || word.ends_with("_t") {
+                    push(&mut spans, i, end, TokenKind::Type);
+                }
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+        (spans, LexState::Normal)
+    }
+}
+
+pub struct RustLexer;
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+    "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self",
+    "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while", "yield",
+];
+const RUST_TYPES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize", "f32", "f64", "bool",
+    "char", "str", "Self",
+];
+impl RustLexer {
+    // r"..", r#".."#, br"..". Gives back how many '#'s there are and where the string body starts
+    fn raw_string_start(line: &[u8], i: usize) -> Option<(u32, usize)> {
+        let mut j = i;
+        if line[j] == b'b' { j += 1; }
+        if line.get(j) != Some(&b'r') { return None; }
+        j += 1;
+        let hashes = line[j..].iter().take_while(|b| **b == b'#').count();
+        if line.get(j + hashes) != Some(&b'"') { return None; }
+        Some((hashes as u32, j + hashes + 1))
+    }
+    fn raw_string_end(line: &[u8], mut i: usize, hashes: u32) -> (usize, bool) {
+        while i < line.len() {
+            if line[i] == b'"' && line[i + 1..].iter().take(hashes as usize).filter(|b| **b == b'#').count() == hashes as usize {
+                return (i + 1 + hashes as usize, true);
+            }
+            i += 1;
+        }
+        (line.len(), false)
+    }
+    // 'a' and '\n' are chars, 'a on its own is a lifetime
+    fn quote_end(line: &[u8], i: usize) -> (usize, TokenKind) {
+        if line.get(i + 1) == Some(&b'\\') {
+            let end = line[i + 2..].iter().position(|b| *b == b'\'').map(|p| i + 2 + p + 1).unwrap_or(line.len());
+            return (end, TokenKind::String);
+        }
+        let mut j = i + 2;
+        while j < line.len() && crate::rope::is_continuation(line[j]) {
+            j += 1;
+        }
+        if line.get(j) == Some(&b'\'') {
+            return (j + 1, TokenKind::String);
+        }
+        (ident_end(line, i + 1).min(line.len()), TokenKind::Keyword)
+    }
+}
+impl Lexer for RustLexer {
+    fn lex_line(&self, line: &[u8], state: LexState) -> (Vec<Span>, LexState) {
+        let mut spans = Vec::new();
+        let mut i = 0;
+        match state {
+            LexState::BlockComment(depth) => {
+                let (end, depth) = block_comment_end(line, 0, depth, true);
+                push(&mut spans, 0, end, TokenKind::Comment);
+                if depth > 0 { return (spans, LexState::BlockComment(depth)); }
+                i = end;
+            }
+            LexState::String => {
+                let (end, closed) = string_end(line, 0, b'"');
+                push(&mut spans, 0, end, TokenKind::String);
+                if !closed { return (spans, LexState::String); }
+                i = end;
+            }
+            LexState::RawString(hashes) => {
+                let (end, closed) = Self::raw_string_end(line, 0, hashes);
+                push(&mut spans, 0, end, TokenKind::String);
+                if !closed { return (spans, LexState::RawString(hashes)); }
+                i = end;
+            }
+            _ => {}
+        }
+        while i < line.len() {
+            let b = line[i];
+            if line[i..].starts_with(b"//") {
+                push(&mut spans, i, line.len(), TokenKind::Comment);
+                break;
+            } else if line[i..].starts_with(b"/*") {
+                let (end, depth) = block_comment_end(line, i + 2, 1, true);
+                push(&mut spans, i, end, TokenKind::Comment);
+                if depth > 0 { return (spans, LexState::BlockComment(depth)); }
+                i = end;
+            } else if let Some((hashes, body)) = Self::raw_string_start(line, i) {
+                let (end, closed) = Self::raw_string_end(line, body, hashes);
+                push(&mut spans, i, end, TokenKind::String);
+                if !closed { return (spans, LexState::RawString(hashes)); }
+                i = end;
+            } else if b == b'"' || line[i..].starts_with(b"b\"") {
+                let body = if b == b'"' { i + 1 } else { i + 2 };
+                let (end, closed) = string_end(line, body, b'"');
+                push(&mut spans, i, end, TokenKind::String);
+                if !closed { return (spans, LexState::String); }
+                i = end;
+            } else if b == b'\'' || line[i..].starts_with(b"b'") {
+                let at = if b == b'\'' { i } else { i + 1 };
+                let (end, kind) = Self::quote_end(line, at);
+                push(&mut spans, i, end, kind);
+                i = end;
+            } else if b == b'#' && (line[i..].starts_with(b"#[") || line[i..].starts_with(b"#![")) {
+                // Attributes go on until their bracket closes
+                let mut depth = 0;
+                let mut end = line.len();
+                for (j, c) in line.iter().enumerate().skip(i) {
+                    match c {
+                        b'[' => depth += 1,
+                        b']' => {
+                            depth -= 1;
+                            if depth == 0 { end = j + 1; break; }
+                        }
+                        _ => {}
+                    }
+                }
+                push(&mut spans, i, end, TokenKind::Preprocessor);
+                i = end;
+            } else if b.is_ascii_digit() {
+                let end = number_end(line, i);
+                push(&mut spans, i, end, TokenKind::Number);
+                i = end;
+            } else if is_ident_start(b) {
+                let end = ident_end(line, i);
+                let word = std::str::from_utf8(&line[i..end]).unwrap_or("");
+                if line.get(end) == Some(&b'!') && line.get(end + 1) != Some(&b'=') {
+                    push(&mut spans, i, end + 1, TokenKind::Preprocessor);
+                    i = end + 1;
+                    continue;
+                }
+                if RUST_KEYWORDS.contains(&word) {
+                    push(&mut spans, i, end, TokenKind::Keyword);
+                } else if RUST_TYPES.contains(&word) || word.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    push(&mut spans, i, end, TokenKind::Type);
+                }
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+        (spans, LexState::Normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Line = (Vec<(usize, usize, TokenKind)>, LexState);
+    // Lexes the lines one after the other, like the highlighter does
+    fn lex(lexer: &dyn Lexer, lines: &[&str]) -> Vec<Line> {
+        let mut state = LexState::Normal;
+        lines.iter().map(|line| {
+            let (spans, next) = lexer.lex_line(line.as_bytes(), state.clone());
+            state = next.clone();
+            (spans.iter().map(|s| (s.start, s.end, s.kind)).collect(), next)
+        }).collect()
+    }
+    use TokenKind::*;
+
+    #[test]
+    fn c_include() {
+        let lines = lex(&CLexer, &["#include <stdio.h>", "  # include \"a.h\"", "int x = a < b;"]);
+        assert_eq!(lines[0].0, vec![(0, 8, Preprocessor), (9, 18, String)]);
+        assert_eq!(lines[1].0, vec![(2, 11, Preprocessor), (12, 17, String)]);
+        assert_eq!(lines[2].0, vec![(0, 3, Type)]);
+    }
+    #[test]
+    fn c_block_comments_dont_nest() {
+        let lines = lex(&CLexer, &["int a; /* one /* two", "three */ int b; */", "return 0;"]);
+        assert_eq!(lines[0], (vec![(0, 3, Type), (7, 20, Comment)], LexState::BlockComment(1)));
+        assert_eq!(lines[1], (vec![(0, 8, Comment), (9, 12, Type)], LexState::Normal));
+        assert_eq!(lines[2].0, vec![(0, 6, Keyword), (7, 8, Number)]);
+    }
+    #[test]
+    fn rust_nested_block_comments() {
+        let lines = lex(&RustLexer, &["x /* a /* b", "c */ still", "*/ fn", "fn"]);
+        assert_eq!(lines[0], (vec![(2, 11, Comment)], LexState::BlockComment(2)));
+        assert_eq!(lines[1], (vec![(0, 10, Comment)], LexState::BlockComment(1)));
+        assert_eq!(lines[2], (vec![(0, 2, Comment), (3, 5, Keyword)], LexState::Normal));
+        assert_eq!(lines[3].0, vec![(0, 2, Keyword)]);
+    }
+    #[test]
+    fn rust_raw_strings_across_lines() {
+        let lines = lex(&RustLexer, &["let s = r#\"one \"quoted\"", "two\"#; let", "br##\"x\"#\"##"]);
+        assert_eq!(lines[0], (vec![(0, 3, Keyword), (8, 23, String)], LexState::RawString(1)));
+        assert_eq!(lines[1], (vec![(0, 5, String), (7, 10, Keyword)], LexState::Normal));
+        assert_eq!(lines[2], (vec![(0, 11, String)], LexState::Normal));
+    }
+    #[test]
+    fn rust_chars_and_lifetimes() {
+        let lines = lex(&RustLexer, &["let c = 'a';", "fn f<'a>(x: &'a str) -> char { '\\n' }", "'é'"]);
+        assert_eq!(lines[0].0, vec![(0, 3, Keyword), (8, 11, String)]);
+        assert_eq!(lines[1].0, vec![(0, 2, Keyword), (5, 7, Keyword), (13, 15, Keyword), (16, 19, Type), (24, 28, Type), (31, 35, String)]);
+        assert_eq!(lines[2].0, vec![(0, 4, String)]);
+    }
+}
@@ -2,6 +2,7 @@
 #![allow(unused_imports)]
 #![allow(unused_assignments)]
 #![allow(dead_code)]
+#![allow(non_upper_case_globals)]
 mod image;
 mod texture;
 mod shader;
@@ -9,16 +10,50 @@ mod renderer;
 mod batch;
 mod font;
 mod time;
+mod rope;
+mod history;
+mod files;
+mod prompt;
+mod editor;
+mod clipboard;
+mod input;
+mod highlight;
+mod lexers;
+mod syntax;
+mod theme;
+mod vim;
+mod keymap;
+mod commands;
+mod search;
+mod searchbar;
+mod walk;
+mod projectsearch;
+mod searchpanel;
+mod buffers;
+mod panes;
+mod fuzzy;
+mod quickopen;
+mod picker;
+mod palette;
+mod jumps;
+mod bookmarks;
+mod unicode;
+mod encoding;
+mod endings;
 use font::*;
-use std::{io::Read, process::exit, time::{Duration, SystemTime}};
+use std::{io::Read, path::PathBuf, process::exit, time::{Duration, SystemTime}};
 
-use beryllium::{events::{self, SDL_Keycode, SDLK_BACKSPACE, SDLK_DOWN, SDLK_END, SDLK_HOME, SDLK_LEFT, SDLK_LSHIFT, SDLK_RIGHT, SDLK_RSHIFT, SDLK_SPACE, SDLK_UP}, video, Sdl};
+use beryllium::{events::{self, SDL_Keycode, KMOD_ALT, KMOD_CTRL, KMOD_NONE, KMOD_SHIFT, SDLK_r, SDLK_BACKSPACE, SDLK_DELETE, SDLK_ESCAPE, SDLK_KP_ENTER, SDLK_RETURN, SDLK_TAB, SDLK_DOWN, SDLK_END, SDLK_HOME, SDLK_LEFT, SDLK_LSHIFT, SDLK_RIGHT, SDLK_RSHIFT, SDLK_UP}, video, Sdl};
 use batch::Batch;
 use freetype::face::LoadFlag;
 use image::Image;
 use shader::Shader;
 use texture::Texture;
 use renderer::{Boundary, Renderer};
+use history::EditKind;
+use editor::{Cursor, Editor, Selection};
+use prompt::{Prompt, PromptKind, PromptResult};
+use input::Input;
 use gl33::{global_loader::{self, *}, *};
 
 const VERT_SHADER: &str = r#"#version 330 core
@@ -74,6 +109,19 @@ impl Color {
             a: ((color>>0) & 0xff) as f32 / 0xff as f32,
         }
     }
+    // Same as from_hex but for text like "#212121", "#212121ff" or "0x212121ff". Without alpha it's opaque
+    fn from_hex_str(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let digits = s.strip_prefix('#').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+        // NOTE: from_str_radix would take a sign in front too
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) { return None; }
+        let color = u32::from_str_radix(digits, 16).ok()?;
+        match digits.len() {
+            6 => Some(Self::from_hex((color << 8) | 0xff)),
+            8 => Some(Self::from_hex(color)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -116,146 +164,15 @@ const H_RATIO: i32 = 9;
 const WIDTH: i32 = W_RATIO * SCALAR;
 const HEIGHT: i32 = H_RATIO * SCALAR;
 
-#[derive(Debug)]
-struct Line {
-    at: usize, // NOTE: In bytes
-    len: usize // NOTE: In bytes
-}
-#[derive(Debug)]
-struct Lines {
-    inner: Vec<Line> 
-}
-impl Lines {
-    fn parse_bytes(bytes: &[u8]) -> Self {
-        let mut inner: Vec<Line> = Vec::new();
-        inner.push(Line { at: 0, len: 0 });
-        let mut last: usize = 0;
-        for (mut at, _) in bytes.iter().enumerate().filter(|(_, b)| **b == '\n' as u8) {
-           at += 1;
-           if inner.len() > 0 {
-             let len = inner.len();
-             inner[len-1].len = at-last-1;
-           }
-           inner.push(Line { at, len: 0});
-           last = at
-        }
-        if inner.len() > 0 {
-            let len = inner.len();
-            inner[len-1].len = bytes.len()-last;
-        }
-        Self { inner }
-    }
-}
-#[derive(Clone, Copy)]
-struct Cursor {
-    line: usize,
-    chr: usize,
-}
-impl Cursor {
-    const fn new() -> Self {
-        Self { line: 0, chr: 0 }
-    }
-}
-struct Editor {
-    view: Vector2f, // Coordinates from top left of text
-    cursor: Cursor,
-    lines: Lines,
-    bytes: Vec<u8>,
+// The part of the window the editor gets. The tab bar is above it and the search bar takes the bottom while it's open
+fn editor_bound(size: Vector2f, search: &searchbar::SearchBar, font: &Font) -> Boundary {
+    let h = search.height(font);
+    let tabs = buffers::Buffers::tab_height(font);
+    Boundary { pos: Vector2f(0.0, h), size: Vector2f(size.0, size.1 - h - tabs) }
 }
-impl Editor {
-    fn delete_char(&mut self, at: Cursor) -> Option<usize> { // None means we deleted the line
-        if at.line >= self.lines.inner.len() { return Some(0); }
-        if at.chr == 0 && self.lines.inner[at.chr].len == 1 {
-            self.lines.inner.remove(at.line);
-            return None;
-        }
-        if at.chr == 0 {
-            todo!("unite lines");
-        }
-        let extra = {
-            let l = &mut self.lines.inner[at.line];
-            assert!(at.chr < l.len);
-            let len = || -> usize {
-                let s = std::str::from_utf8(&self.bytes[l.at .. l.at+l.len]).expect("Could not delete char from utf8");
-                let mut off: usize = 0;
-                for chr in s.chars() {
-                    let cl = chr.len_utf8();
-                    if off == at.chr { return cl; }
-                    off += cl 
-                }
-                unreachable!("This should be unreachable")
-            }();
-            let i = l.at + at.chr;
-            self.bytes.drain(i-len..i);
-            l.len -= len;
-            len
-        };
-        let len = self.lines.inner.len();
-        for line in &mut self.lines.inner[at.line+1..len] {
-            line.at -= extra;
-        }
-        Some(extra)
-    }
-    fn insert_char(&mut self, c: char, at: Cursor) {
-        if at.line >= self.lines.inner.len() { return; }
-        let extra = {
-            let l = &mut self.lines.inner[at.line];
-            assert!(at.chr < l.len);
-            let i = l.at + at.chr;
-            let mut buf: [u8; 4] = [0; 4];
-            let len = c.encode_utf8(&mut buf).len();
-            self.bytes.splice(i..i, buf[..len].iter().copied());
-            l.len += len;
-            len
-        };
-        let len = self.lines.inner.len();
-        for line in &mut self.lines.inner[at.line+1..len] {
-            line.at += extra;
-        }
-    }
-    fn from_bytes(bytes: Vec<u8>) -> Self {
-        Self { cursor: Cursor::new(), lines: Lines::parse_bytes(&bytes), bytes, view: Vector2f::ZERO()}
-    }
-    fn display(&self, r: &mut Renderer, bound: Boundary, font: &Font) {
-        r.scisorsBegin(&bound);
-        //println!("bound: {:?}",bound);
-        assert!(self.view.0 == 0.0, "X coordinate not considered yet.");
-        // Gets you the glyth step
-        let glythH = font.fontSize + 1;
-        let advanceY = glythH + 4;
-        let lineBegin = self.view.1.floor().max(0.0) as usize;
-        let mut pos = bound.top_left();
-        for i in lineBegin..self.lines.inner.len() {
-            let line = &self.lines.inner[i];
-            let bytes = &self.bytes[line.at .. line.at+line.len];
-            let s = std::str::from_utf8(bytes).expect("TODO: Implement parsing of non-utf8 text. It should be simple. Check notes");
-            let m = font.measure_text(s, font.fontSize as f32).unwrap_or(Vector2f(0.0, font.fontSize as f32));
-            if pos.1 > bound.pos.1 + bound.size.1 + m.1 || pos.1 < bound.pos.1 - m.1 {
-                break;
-            }
-            pos.1 -= advanceY as f32;
-            {
-                let mut pos = pos;
-                for (ic, chr) in s.chars().enumerate() {
-                    if chr != '\t' && chr != '\r' {
-                        r.draw_char(font, chr, pos, Color::WHITE());
-                    }
-                    if i == self.cursor.line && ic == self.cursor.chr {
-                        let yoff = 2.0;
-                        let h = font.fontSize as f32;        
-                        let w = 3.0;
-                        let x = pos.0;
-                        let y = pos.1 - yoff;
-                        r.draw_rect(Color::WHITE(), Vector2f(x, y), Vector2f(w, h))
-                    }
-                    pos.0 += font.get_char(chr).advance_x as f32;
-                }
-            }
-            //println!("{}> fp: {:?}. string {:?}",i, fp,s);
-            // r.draw_str(font, s, fp, Color::WHITE());
-        }
-        r.scisorsEnd();
-    }
+fn tab_bound(size: Vector2f, font: &Font) -> Boundary {
+    let h = buffers::Buffers::tab_height(font);
+    Boundary { pos: Vector2f(0.0, size.1 - h), size: Vector2f(size.0, h) }
 }
 fn main() {
     let sdl = Sdl::init(beryllium::init::InitFlags::EVERYTHING);
@@ -373,91 +290,350 @@ fn main() {
     let mut mpos = Vector2f::ZERO();
     let mut args = std::env::args();
     let _program = args.next().expect("program");
-    let path = args.next().expect("path");
-    let f = std::fs::read(&path).expect("main.rs");
+    // NOTE: --vim turns on the modal input layer
+    let mut useVim = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        if arg == "--vim" {
+            useVim = true;
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
     // let msg = f.as_str();
     println!("font.texture: {:?}",font.texture);
     // #[allow(unused_mut)]
     // let mut view = Vector2f::ZERO();
 
-    let mut editor = Editor::from_bytes(f);
+    syntax::register(syntax::load_dir(std::path::Path::new("syntax")));
+    // NOTE: A theme.toml in the working directory replaces the dark theme, and gets picked up again whenever it's saved
+    let mut theme = theme::Theme::dark();
+    let mut themeFile = theme::ThemeFile::new(PathBuf::from("theme.toml"));
+    // NOTE: Every file given gets a tab. A directory becomes the project root instead,
+    // and launched on just a directory there's no file yet, so it starts with finding one in it
+    let mut root = None;
+    let mut editors = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            root.get_or_insert(path);
+            continue;
+        }
+        match Editor::open(path.clone()) {
+            Ok(e) => editors.push(e),
+            Err(e) => eprintln!("[ERROR] Could not open {}: {}", path.display(), e),
+        }
+    }
+    let mut buffers = buffers::Buffers::new(editors);
+    // NOTE: Bookmarks go into a bookmarks.toml in the working directory. One that can't be read is left alone
+    match bookmarks::BookmarkFile::load(PathBuf::from("bookmarks.toml")) {
+        Ok(file) => buffers.bookmarks = file,
+        Err(e) => eprintln!("[ERROR] Failed to load bookmarks.toml: {}", e),
+    }
+    for editor in buffers.editors.iter_mut() {
+        buffers.bookmarks.restore(editor);
+    }
+    let mut jumps = jumps::JumpList::new();
+    let showPanel = root.is_some() && buffers.current().path.is_none();
+    let mut projectPanel = searchpanel::SearchPanel::new(root.unwrap_or_else(|| std::env::current_dir().unwrap_or_default()));
+    if showPanel {
+        projectPanel.show(buffers.current());
+    }
+    let mut finder = quickopen::QuickOpen::new(projectPanel.root.clone());
+    let mut palette = palette::Palette::new();
+    let mut prompt: Option<Prompt> = None;
+    let mut searchBar = searchbar::SearchBar::new();
+    let commands = commands::Commands::builtin();
+    // NOTE: A keymap.toml in the working directory goes on top of the default bindings
+    let mut keymap = keymap::Keymap::default_keymap();
+    if let Ok(src) = std::fs::read_to_string("keymap.toml") {
+        if let Err(e) = keymap.load(&src) {
+            eprintln!("[ERROR] Failed to load keymap.toml: {}", e);
+        }
+    }
+    for binding in keymap.bindings.iter().filter(|b| commands.get(commands::split_line(&b.command).0).is_none()) {
+        eprintln!("[WARN] keymap.toml: No command called '{}'", binding.command);
+    }
+    let mut vim = if useVim { Some(vim::Vim::new()) } else { None };
+    let mut dragging = false;
+    let mut divider: Option<panes::Divider> = None; // The one being dragged
+    let mut keymods = KMOD_NONE;
+    let mut keyTaken = false; // The last key ran something, the text it makes isn't for typing
+    let mut imeRect = (0, 0, 0, 0);
+    input::start_text_input();
     const FPS: u32 = 60;
     const DESIRED_TIME: f64 = 1.0 / FPS as f64;
     'game_loop: loop {
         let mut scroll: f32 = 0.0;
+        let mut scrollX: f32 = 0.0;
         // TODO: move this into update
-        while let Some((event, _)) = renderer.sdl.poll_events() {
+        while let Some(input) = input::poll() {
+            let event = match input {
+                Input::TextEditing { text, start } => {
+                    if let Some(p) = &mut prompt {
+                        p.preedit = text;
+                    } else if palette.open {
+                        palette.preedit = text;
+                    } else if finder.open {
+                        finder.preedit = text;
+                    } else if projectPanel.open {
+                        projectPanel.preedit = text;
+                    } else if searchBar.open {
+                        searchBar.preedit = text;
+                    } else {
+                        let editor = buffers.current_mut();
+                        editor.preedit = text;
+                        editor.preedit_cursor = start;
+                    }
+                    continue;
+                }
+                Input::Event(event) => event,
+            };
             match event {
-                events::Event::Quit => break 'game_loop,
+                events::Event::Quit => {
+                    if !buffers.any_dirty() { break 'game_loop; }
+                    prompt = Some(Prompt::new(PromptKind::ConfirmQuit, "Unsaved changes. Quit anyway? (y/n): ", ""));
+                }
                 events::Event::WindowResized { win_id: _, width, height }  => {
                     unsafe {
                     glViewport(0, 0, width, height);
                     }
                 }
                 #[allow(unused_variables)]
+                events::Event::TextInput { win_id, text } => {
+                    // NOTE: Some platforms still send text for chords, Ctrl ones and on X11 Alt ones too. Those keys are commands
+                    if std::mem::take(&mut keyTaken) || keymods.0 & KMOD_CTRL.0 != 0 && keymods.0 & KMOD_ALT.0 == 0 { continue; }
+                    let editor = buffers.current_mut();
+                    if let Some(p) = &mut prompt {
+                        p.preedit.clear();
+                        p.handle_text(&text);
+                    } else if palette.open {
+                        palette.preedit.clear();
+                        palette.handle_text(&text);
+                    } else if finder.open {
+                        finder.preedit.clear();
+                        finder.handle_text(&text);
+                    } else if projectPanel.open {
+                        projectPanel.preedit.clear();
+                        projectPanel.handle_text(&text);
+                    } else if searchBar.open {
+                        searchBar.preedit.clear();
+                        searchBar.handle_text(editor, &text);
+                    } else if let Some(v) = &mut vim {
+                        editor.preedit.clear();
+                        for c in text.chars() {
+                            if let vim::Action::Cmdline = v.handle(editor, vim::Key::Char(c)) {
+                                prompt = Some(Prompt::new(PromptKind::Ex, ":", ""));
+                                break;
+                            }
+                        }
+                    } else {
+                        editor.preedit.clear();
+                        editor.type_text(&text);
+                    }
+                }
+                #[allow(unused_variables)]
                 events::Event::Key { win_id, pressed, repeat, scancode, keycode, modifiers } => {
-                    match keycode {
-                        SDLK_RIGHT => {
-                            if pressed {
-                                if editor.cursor.chr < editor.lines.inner.get(editor.cursor.line).unwrap().len-1 {
-                                    editor.cursor.chr += 1;
+                    keymods = modifiers;
+                    if pressed { keyTaken = false; }
+                    // NOTE: While the IME is composing the keys are for it, not for us
+                    if !buffers.current().preedit.is_empty() || !searchBar.preedit.is_empty() || !projectPanel.preedit.is_empty() || !finder.preedit.is_empty() || !palette.preedit.is_empty() || prompt.as_ref().is_some_and(|p| !p.preedit.is_empty()) { continue; }
+                    if let Some(p) = &mut prompt {
+                        if !pressed { continue; }
+                        match p.handle_key(keycode) {
+                            PromptResult::Pending => {}
+                            PromptResult::Cancel => prompt = None,
+                            PromptResult::Submit(input) => {
+                                let kind = p.kind;
+                                prompt = None;
+                                match kind {
+                                    PromptKind::SaveAs => {
+                                        if input.is_empty() { continue; }
+                                        match buffers.current_mut().save_as(PathBuf::from(&input)) {
+                                            Ok(()) => println!("Saved {}", input),
+                                            Err(e) => eprintln!("[ERROR] Failed to save {}: {}", input, e),
+                                        }
+                                    }
+                                    PromptKind::Open => {
+                                        if input.is_empty() { continue; }
+                                        if let Err(e) = buffers.open(PathBuf::from(&input)) {
+                                            eprintln!("[ERROR] Failed to open {}: {}", input, e);
+                                        }
+                                    }
+                                    PromptKind::ConfirmClose => {
+                                        if input.trim().eq_ignore_ascii_case("y") {
+                                            buffers.close(buffers.active);
+                                        }
+                                    }
+                                    PromptKind::ConfirmQuit => {
+                                        if input.trim().eq_ignore_ascii_case("y") { break 'game_loop; }
+                                    }
+                                    PromptKind::Ex => {
+                                        let Some(v) = &mut vim else { continue; };
+                                        match v.execute_ex(buffers.current_mut(), &input) {
+                                            vim::ExResult::Done => {}
+                                            // NOTE: Like in vim a split goes first, then the buffer
+                                            vim::ExResult::Quit if buffers.layout.count() > 1 => buffers.close_pane(),
+                                            vim::ExResult::Quit if buffers.editors.len() == 1 => break 'game_loop,
+                                            vim::ExResult::Quit => buffers.close(buffers.active),
+                                            vim::ExResult::QuitAll { force } => {
+                                                if force || !buffers.any_dirty() { break 'game_loop; }
+                                                v.message = Some("No write since last change in another buffer (add ! to override)".to_string());
+                                            }
+                                            vim::ExResult::Edit(path) => {
+                                                if let Err(e) = buffers.open(path.clone()) {
+                                                    v.message = Some(format!("Failed to open {}: {}", path.display(), e));
+                                                }
+                                            }
+                                            vim::ExResult::Cycle(forward) => buffers.cycle(forward),
+                                            vim::ExResult::Split(direction) => buffers.split(direction),
+                                            vim::ExResult::Error(e) => v.message = Some(e),
+                                        }
+                                    }
                                 }
                             }
                         }
-                        SDLK_LEFT => {
-                            if pressed {
-                                if editor.cursor.chr > 0 {
-                                    editor.cursor.chr -= 1;
+                        continue;
+                    }
+                    let ctrl = modifiers.0 & KMOD_CTRL.0 != 0;
+                    let shift = modifiers.0 & KMOD_SHIFT.0 != 0;
+                    let alt = modifiers.0 & KMOD_ALT.0 != 0;
+                    if palette.open && pressed {
+                        match palette.handle_key(keycode, modifiers) {
+                            palette::PaletteAction::Ignored => {}
+                            palette::PaletteAction::Handled => continue,
+                            palette::PaletteAction::Run(line) => {
+                                palette.close();
+                                let bound = buffers.current().text_bound(&buffers.focused_bound(&editor_bound(renderer.window_size(), &searchBar, &font)), &font);
+                                let mut ctx = commands::Context { buffers: &mut buffers, prompt: &mut prompt, search: &mut searchBar, project: &mut projectPanel, finder: &mut finder, palette: &mut palette, jumps: &mut jumps, commands: &commands, keymap: &keymap, theme: &mut theme, font: &font, bound, quit: false };
+                                // NOTE: It stays open on errors so that the command can be fixed
+                                let res = commands.run(&line, &mut ctx);
+                                if ctx.quit { break 'game_loop; }
+                                if let Err(e) = res {
+                                    palette.open = true;
+                                    palette.message = e;
                                 }
+                                continue;
                             }
                         }
-                        SDLK_UP => {
-                            if pressed {
-                                if editor.cursor.line > 0 {
-                                    editor.cursor.line -= 1;
-                                    editor.cursor.chr = editor.cursor.chr.min(editor.lines.inner.get(editor.cursor.line).unwrap().len-1)
+                    }
+                    if finder.open && pressed {
+                        match finder.handle_key(keycode, modifiers) {
+                            quickopen::FinderAction::Ignored => {}
+                            quickopen::FinderAction::Handled => continue,
+                            quickopen::FinderAction::Open(path) => {
+                                if let Err(e) = buffers.open(path.clone()) {
+                                    eprintln!("[ERROR] Failed to open {}: {}", path.display(), e);
                                 }
+                                continue;
                             }
                         }
-                        SDLK_DOWN => {
-                            if pressed {
-                                if editor.cursor.line < editor.lines.inner.len() {
-                                    editor.cursor.line += 1;
-                                    editor.cursor.chr = editor.cursor.chr.min(editor.lines.inner.get(editor.cursor.line).unwrap().len-1)
+                    }
+                    if projectPanel.open && pressed {
+                        match projectPanel.handle_key(keycode, modifiers) {
+                            searchpanel::PanelAction::Ignored => {}
+                            searchpanel::PanelAction::Handled => continue,
+                            searchpanel::PanelAction::Open(path, at) => {
+                                match buffers.open(path.clone()) {
+                                    Ok(()) => {
+                                        let editor = buffers.current_mut();
+                                        editor.move_to(editor.clamp(at), false);
+                                        projectPanel.close();
+                                    }
+                                    Err(e) => projectPanel.message = format!("Failed to open {}: {}", path.display(), e),
                                 }
+                                continue;
                             }
                         }
-                        SDLK_HOME => {
+                    }
+                    if searchBar.open && pressed && searchBar.handle_key(buffers.current_mut(), keycode, modifiers) { continue; }
+                    // NOTE: Vim gets the keys that don't come as text. Everything else goes through the usual bindings
+                    if let Some(v) = &mut vim {
+                        let key = match keycode {
+                            SDLK_r if ctrl => Some(vim::Key::Ctrl('r')),
+                            _ if ctrl || alt => None,
+                            SDLK_ESCAPE => Some(vim::Key::Esc),
+                            SDLK_RETURN | SDLK_KP_ENTER => Some(vim::Key::Enter),
+                            SDLK_TAB => Some(vim::Key::Tab),
+                            SDLK_BACKSPACE => Some(vim::Key::Backspace),
+                            SDLK_DELETE => Some(vim::Key::Delete),
+                            SDLK_LEFT => Some(vim::Key::Left),
+                            SDLK_RIGHT => Some(vim::Key::Right),
+                            SDLK_UP => Some(vim::Key::Up),
+                            SDLK_DOWN => Some(vim::Key::Down),
+                            SDLK_HOME => Some(vim::Key::Home),
+                            SDLK_END => Some(vim::Key::End),
+                            _ => None,
+                        };
+                        if let Some(key) = key {
                             if pressed {
-                                editor.cursor.chr = 0;
+                                if let vim::Action::Cmdline = v.handle(buffers.current_mut(), key) {
+                                    prompt = Some(Prompt::new(PromptKind::Ex, ":", ""));
+                                }
                             }
+                            continue;
                         }
-                        SDLK_END => {
-                            if pressed {
-                                editor.cursor.chr = editor.lines.inner.get(editor.cursor.line).unwrap().len-1;
+                    }
+                    if !pressed { continue; }
+                    match keymap.press(keymap::Chord::new(keycode, modifiers)) {
+                        keymap::KeyResult::Run(name) => {
+                            keyTaken = true;
+                            let bound = buffers.current().text_bound(&buffers.focused_bound(&editor_bound(renderer.window_size(), &searchBar, &font)), &font);
+                            let mut ctx = commands::Context { buffers: &mut buffers, prompt: &mut prompt, search: &mut searchBar, project: &mut projectPanel, finder: &mut finder, palette: &mut palette, jumps: &mut jumps, commands: &commands, keymap: &keymap, theme: &mut theme, font: &font, bound, quit: false };
+                            if let Err(e) = commands.run(&name, &mut ctx) {
+                                eprintln!("[WARN] {}: {}", name, e);
                             }
+                            if ctx.quit { break 'game_loop; }
                         }
-                        SDLK_SPACE => {
-                            if pressed {
-                                editor.insert_char(' ', editor.cursor);
+                        keymap::KeyResult::Pending => keyTaken = true,
+                        keymap::KeyResult::Unbound => {}
+                    }
+                }
+                #[allow(unused_variables)]
+                events::Event::MouseButton { win_id, mouse_id, button, pressed, clicks, x, y } => {
+                    let wsize = renderer.window_size();
+                    let p = Vector2f(x as f32, wsize.1 - y as f32);
+                    // NOTE: The finder and the palette are for the keyboard, the mouse leaves everything alone while they're up
+                    if finder.open || palette.open {
+                        dragging = false;
+                        continue;
+                    }
+                    // NOTE: Left click on a tab switches to it, middle click closes it
+                    if let Some(i) = buffers.tab_at(p, &tab_bound(wsize, &font), &font).filter(|_| pressed && prompt.is_none()) {
+                        if button == 1 {
+                            buffers.active = i;
+                        } else if button == 2 {
+                            buffers.active = i;
+                            if buffers.current().dirty {
+                                prompt = Some(Prompt::new(PromptKind::ConfirmClose, "Unsaved changes. Close anyway? (y/n): ", ""));
+                            } else {
+                                buffers.close(i);
                             }
                         }
-                        SDLK_BACKSPACE => {
-                            if pressed {
-                                let res = editor.delete_char(editor.cursor);
-                                match res {
-                                    Some(size) => editor.cursor.chr -= size,
-                                    None => {
-                                        editor.cursor.chr = editor.cursor.chr.clamp(0, editor.lines.inner.get(editor.cursor.line).unwrap().len);
-                                    }
-                                }
+                        continue;
+                    }
+                    // NOTE: SDL_BUTTON_LEFT
+                    if button == 1 {
+                        dragging = pressed;
+                        divider = None;
+                        if pressed && prompt.is_none() && !projectPanel.open {
+                            let area = editor_bound(wsize, &searchBar, &font);
+                            if let Some(d) = buffers.layout.divider_at(p, &area) {
+                                divider = Some(d);
+                                continue;
                             }
-                        }
-                        k => {
-                            if pressed {
-                                if let Some(chr) = std::char::from_u32(unsafe { std::mem::transmute(k.0) } ) {
-                                   editor.insert_char(chr, editor.cursor);
-                                }
+                            // NOTE: Clicking into another pane focuses it before moving the cursor there
+                            if let Some(i) = buffers.layout.pane_at(p, &area) {
+                                buffers.focus(i);
+                            }
+                            let bound = buffers.current().text_bound(&buffers.focused_bound(&area), &font);
+                            let editor = buffers.current_mut();
+                            // NOTE: Clicks on the status bar don't go to the text
+                            if p.1 < bound.pos.1 { continue; }
+                            let c = editor.point_to_cursor(p, &bound, &font);
+                            if keymods.0 & KMOD_CTRL.0 != 0 {
+                                editor.add_cursor(c);
+                            } else {
+                                editor.move_to(c, keymods.0 & KMOD_SHIFT.0 != 0);
                             }
                         }
                     }
@@ -467,26 +643,78 @@ fn main() {
                     let wsize = renderer.window_size();
                     mpos.0 = x_win as f32;
                     mpos.1 = wsize.1 - y_win as f32;
+                    // NOTE: SDL_BUTTON_LMASK
+                    if let Some(d) = divider.as_ref().filter(|_| button_state & 1 != 0) {
+                        buffers.layout.drag(d, mpos);
+                    } else if dragging && button_state & 1 != 0 && prompt.is_none() && !projectPanel.open {
+                        let bound = buffers.current().text_bound(&buffers.focused_bound(&editor_bound(wsize, &searchBar, &font)), &font);
+                        let editor = buffers.current_mut();
+                        let c = editor.point_to_cursor(mpos, &bound, &font);
+                        editor.extend_primary(c);
+                    }
                 }
                 #[allow(unused_variables)]
                 events::Event::MouseWheel { win_id, mouse_id, x, mut y } => {
                     y = y.clamp(-1, 1);
-                    scroll = y as f32;
+                    // NOTE: Shift turns the wheel sideways, for mice that can't do that on their own
+                    if keymods.0 & KMOD_SHIFT.0 != 0 {
+                        scrollX = -y as f32;
+                    } else {
+                        scroll = y as f32;
+                        scrollX = x.clamp(-1, 1) as f32;
+                    }
                 }
                 _ => ()
             }
         }
         renderer.update();
+        projectPanel.poll();
+        finder.poll();
+        jumps.track(jumps::Jump::of(buffers.current()));
+        if let Some(v) = &vim {
+            buffers.current_mut().mode = v.status();
+        }
+        if let Some(t) = themeFile.poll() {
+            theme = t;
+        }
     
-        editor.view.1 += -scroll * 10.0;
+        // NOTE: The wheel scrolls the pane under the mouse, which doesn't have to be the focused one
+        let area = editor_bound(renderer.window_size(), &searchBar, &font);
+        let hovered = buffers.layout.pane_at(mpos, &area).unwrap_or(buffers.layout.focused);
+        let spacing = font.get_spacing_char().map(|g| g.advance_x as f32).unwrap_or(8.0);
+        buffers.with_pane(hovered, |e| {
+            e.scroll(-scroll * 10.0);
+            e.scroll_x(scrollX * 10.0 * spacing);
+        });
+        {
+            let bound = buffers.current().text_bound(&buffers.focused_bound(&area), &font);
+            buffers.current_mut().scroll_to_cursor(&bound, &font);
+        }
         //println!("{}> editor.view: {:?}. lines: {}",scroll, editor.view, editor.lines.inner.len() as f32);
         //editor.view.1 = view.1.clamp(0.0, editor.lines.inner.len() as f32);
         renderer.begin();
-            renderer.clear(Color::from_hex(0x212121ff));
+            renderer.clear(theme.background);
             shader.bind();
             let ws = renderer.window_size();
             let bound = Boundary { pos: Vector2f::ZERO(), size: ws };
-            editor.display(&mut renderer, bound, &font);
+            buffers.display_tabs(&mut renderer, &tab_bound(ws, &font), &font, &theme);
+            buffers.display(&mut renderer, &editor_bound(ws, &searchBar, &font), &font, &theme);
+            let editor = buffers.current();
+            searchBar.display(&mut renderer, &bound, &font, &theme);
+            projectPanel.display(&mut renderer, &editor_bound(ws, &searchBar, &font), &font, &theme);
+            finder.display(&mut renderer, &bound, &font, &theme);
+            palette.display(&mut renderer, &bound, &font, &theme);
+            if let Some(p) = &prompt {
+                p.display(&mut renderer, &bound, &font, &theme);
+            }
+            // Keep the IME's candidate window next to whatever is being typed into
+            let caret = if prompt.is_some() { Vector2f(0.0, 0.0) } else { editor.cursor_to_point(editor.primary().head, &editor.text_bound(&buffers.focused_bound(&editor_bound(ws, &searchBar, &font)), &font), &font) };
+            let lineH = font.fontSize as i32 + 5;
+            let rect = (caret.0 as i32, (ws.1 - caret.1) as i32 - lineH, 1, lineH);
+            if rect != imeRect {
+                imeRect = rect;
+                input::set_text_input_rect(rect.0, rect.1, rect.2, rect.3);
+            }
             //let parts = msg.split('\n');
             //let ws = renderer.window_size();
             //let mut pos = ws+view;
@@ -0,0 +1,130 @@
+// Ctrl+Shift+P: every command by name, with the keys it's bound to. Typing filters them, and a command
+// that takes arguments gets them after its name, like "goto 120" or "set tabwidth 2"
+use beryllium::events::{SDL_Keycode, SDL_Keymod, SDLK_BACKSPACE, SDLK_DOWN, SDLK_ESCAPE, SDLK_KP_ENTER, SDLK_PAGEDOWN, SDLK_PAGEUP, SDLK_RETURN, SDLK_TAB, SDLK_UP};
+
+use crate::commands::{split_line, Commands};
+use crate::fuzzy;
+use crate::keymap::Keymap;
+use crate::picker::{self, Picker, Row, ROWS};
+use crate::renderer::{Boundary, Renderer};
+use crate::theme::Theme;
+use crate::Font;
+
+pub enum PaletteAction {
+    Ignored, // The key wasn't for the palette
+    Handled,
+    Run(String), // The command and its arguments
+}
+struct Entry {
+    name: String,
+    detail: String, // What it takes and what it does
+    usage: String,
+    keys: String,
+}
+pub struct Palette {
+    pub open: bool,
+    pub input: String,
+    pub preedit: String,
+    pub message: String, // Shown instead of the count, for when a command failed
+    selected: usize,
+    scroll: usize,
+    entries: Vec<Entry>,
+    matches: Vec<(usize, Vec<usize>)>, // Entries and which chars of their name matched, best first
+}
+impl Palette {
+    pub fn new() -> Self {
+        Self { open: false, input: String::new(), preedit: String::new(), message: String::new(), selected: 0, scroll: 0, entries: Vec::new(), matches: Vec::new() }
+    }
+    // Opens with `input` already typed in
+    // NOTE: The list is made again every time so that commands and bindings added since show up
+    pub fn show(&mut self, commands: &Commands, keymap: &Keymap, input: &str) {
+        self.open = true;
+        self.input = input.to_string();
+        self.message.clear();
+        self.entries = commands.commands.iter().map(|(name, command)| {
+            let keys = keymap.keys_for(name).map(|keys| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(" ")).unwrap_or_default();
+            let detail = if command.usage.is_empty() { command.description.clone() } else { format!("{}  {}", command.usage, command.description) };
+            Entry { name: name.clone(), detail, usage: command.usage.clone(), keys }
+        }).collect();
+        self.update();
+    }
+    pub fn close(&mut self) {
+        self.open = false;
+        self.preedit.clear();
+    }
+    // The entry named by what was typed, once there's a space after the name
+    fn exact(&self) -> Option<usize> {
+        let (name, _) = split_line(&self.input);
+        if !self.input.trim_start().contains(char::is_whitespace) { return None; }
+        self.entries.iter().position(|e| e.name == name)
+    }
+    pub fn update(&mut self) {
+        self.selected = 0;
+        self.scroll = 0;
+        if let Some(i) = self.exact() {
+            self.matches = vec![(i, (0..self.entries[i].name.chars().count()).collect())];
+            return;
+        }
+        let mut matches: Vec<(i32, usize, Vec<usize>)> = self.entries.iter().enumerate()
+            .filter_map(|(i, e)| fuzzy::score(&self.input, &e.name).map(|(score, positions)| (score, i, positions)))
+            .collect();
+        // NOTE: The entries are sorted by name already, and sorting is stable
+        matches.sort_by_key(|m| std::cmp::Reverse(m.0));
+        self.matches = matches.into_iter().map(|(_, i, positions)| (i, positions)).collect();
+    }
+    fn select(&mut self, selected: usize) {
+        self.selected = selected.min(self.matches.len().saturating_sub(1));
+        self.scroll = picker::scroll_to(self.selected, self.scroll);
+    }
+    // Puts the name of the selected command into the input, ready for its arguments
+    fn complete(&mut self) {
+        let Some((i, _)) = self.matches.get(self.selected) else { return; };
+        self.input = format!("{} ", self.entries[*i].name);
+        self.update();
+    }
+    pub fn handle_key(&mut self, keycode: SDL_Keycode, _modifiers: SDL_Keymod) -> PaletteAction {
+        match keycode {
+            SDLK_ESCAPE => self.close(),
+            SDLK_RETURN | SDLK_KP_ENTER => {
+                let Some((i, _)) = self.matches.get(self.selected) else { return PaletteAction::Handled; };
+                let entry = &self.entries[*i];
+                if self.exact().is_some() {
+                    return PaletteAction::Run(self.input.trim().to_string());
+                }
+                // NOTE: A command that needs arguments waits for them instead of failing right away
+                if !entry.usage.is_empty() {
+                    self.message = format!("{} {}", entry.name, entry.usage);
+                    self.complete();
+                    return PaletteAction::Handled;
+                }
+                return PaletteAction::Run(entry.name.clone());
+            }
+            SDLK_TAB => self.complete(),
+            SDLK_UP => self.select(self.selected.saturating_sub(1)),
+            SDLK_DOWN => self.select(self.selected + 1),
+            SDLK_PAGEUP => self.select(self.selected.saturating_sub(ROWS)),
+            SDLK_PAGEDOWN => self.select(self.selected + ROWS),
+            SDLK_BACKSPACE => {
+                self.input.pop();
+                self.message.clear();
+                self.update();
+            }
+            _ => return PaletteAction::Ignored,
+        }
+        PaletteAction::Handled
+    }
+    pub fn handle_text(&mut self, text: &str) {
+        self.input.extend(text.chars().filter(|c| !c.is_control()));
+        self.update();
+    }
+    pub fn display(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        if !self.open { return; }
+        let status = if self.message.is_empty() { format!("{}/{}", self.matches.len(), self.entries.len()) } else { self.message.clone() };
+        let rows: Vec<Row> = self.matches.iter().skip(self.scroll).take(ROWS).map(|(i, positions)| {
+            let e = &self.entries[*i];
+            Row { text: &e.name, matched: positions, detail: &e.detail, right: &e.keys }
+        }).collect();
+        let picker = Picker { input: &self.input, preedit: &self.preedit, status: &status, rows, selected: self.selected.checked_sub(self.scroll) };
+        picker.display(r, bound, font, theme);
+    }
+}
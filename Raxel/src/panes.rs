@@ -0,0 +1,317 @@
+// Splitting the editor area into panes. The layout is a tree: a pane is a leaf and a split holds two
+// subtrees side by side or one above the other. Panes are numbered in the order they appear, left to
+// right and top to bottom
+use crate::editor::{Cursor, Editor, Selection};
+use crate::renderer::Boundary;
+use crate::Vector2f;
+
+pub const DIVIDER: f32 = 4.0;
+const MIN_RATIO: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Right, // The new pane goes next to the old one
+    Down, // The new pane goes below the old one
+}
+// What a pane remembers of its buffer while another pane has the focus. The editor of the
+// buffer always holds the state of whichever pane showed it last
+#[derive(Debug, Clone)]
+pub struct ViewState {
+    pub view: Vector2f,
+    pub selections: Vec<Selection>,
+    pub primary: usize,
+}
+impl ViewState {
+    pub fn of(e: &Editor) -> Self {
+        Self { view: e.view, selections: e.selections.clone(), primary: e.primary }
+    }
+    // Trades places with the state in the editor
+    pub fn swap(&mut self, e: &mut Editor) {
+        std::mem::swap(&mut self.view, &mut e.view);
+        std::mem::swap(&mut self.selections, &mut e.selections);
+        std::mem::swap(&mut self.primary, &mut e.primary);
+    }
+    // Pulls the cursors back into the buffer. Another pane may have deleted the text they were on
+    // NOTE: Doesn't go through set_selections, that would make the editor scroll to the cursor
+    pub fn clamp(&mut self, e: &Editor) {
+        for sel in self.selections.iter_mut() {
+            sel.anchor = e.clamp(sel.anchor);
+            sel.head = e.clamp(sel.head);
+        }
+        self.primary = self.primary.min(self.selections.len().saturating_sub(1));
+        self.view.1 = self.view.1.min((e.buffer.len_lines() - 1) as f32);
+    }
+}
+#[derive(Debug, Clone)]
+pub struct Pane {
+    pub buffer: usize,
+    pub state: ViewState, // NOTE: Stale while the pane has the focus, the editor has the real one then
+}
+impl Pane {
+    // A pane at the top of the buffer
+    pub fn new(buffer: usize) -> Self {
+        Self { buffer, state: ViewState { view: Vector2f::ZERO(), selections: vec![Selection::caret(Cursor::new())], primary: 0 } }
+    }
+}
+pub enum Node {
+    Pane(Pane),
+    Split { direction: Direction, ratio: f32, first: Box<Node>, second: Box<Node> },
+}
+// A divider between the two sides of a split, for dragging it around
+pub struct Divider {
+    pub split: usize, // Which split it is, counting them in the same order as panes
+    pub rect: Boundary,
+    pub area: Boundary, // Everything the split covers
+    pub direction: Direction,
+}
+// The two parts of `bound` a split gives its sides, with room for the divider in between
+fn split_bound(bound: &Boundary, direction: Direction, ratio: f32) -> (Boundary, Boundary, Boundary) {
+    let (pos, size) = (bound.pos, bound.size);
+    match direction {
+        Direction::Right => {
+            let at = (size.0 * ratio).floor();
+            let first = Boundary { pos, size: Vector2f(at - DIVIDER / 2.0, size.1) };
+            let divider = Boundary { pos: Vector2f(pos.0 + at - DIVIDER / 2.0, pos.1), size: Vector2f(DIVIDER, size.1) };
+            let second = Boundary { pos: Vector2f(pos.0 + at + DIVIDER / 2.0, pos.1), size: Vector2f(size.0 - at - DIVIDER / 2.0, size.1) };
+            (first, divider, second)
+        }
+        Direction::Down => {
+            // NOTE: y goes up, so the first side is the upper one and starts higher
+            let at = (size.1 * ratio).floor();
+            let first = Boundary { pos: Vector2f(pos.0, pos.1 + size.1 - at + DIVIDER / 2.0), size: Vector2f(size.0, at - DIVIDER / 2.0) };
+            let divider = Boundary { pos: Vector2f(pos.0, pos.1 + size.1 - at - DIVIDER / 2.0), size: Vector2f(size.0, DIVIDER) };
+            let second = Boundary { pos, size: Vector2f(size.0, size.1 - at - DIVIDER / 2.0) };
+            (first, divider, second)
+        }
+    }
+}
+impl Node {
+    fn count(&self) -> usize {
+        match self {
+            Node::Pane(_) => 1,
+            Node::Split { first, second, .. } => first.count() + second.count(),
+        }
+    }
+    fn panes<'a>(&'a self, out: &mut Vec<&'a Pane>) {
+        match self {
+            Node::Pane(pane) => out.push(pane),
+            Node::Split { first, second, .. } => {
+                first.panes(out);
+                second.panes(out);
+            }
+        }
+    }
+    fn panes_mut<'a>(&'a mut self, out: &mut Vec<&'a mut Pane>) {
+        match self {
+            Node::Pane(pane) => out.push(pane),
+            Node::Split { first, second, .. } => {
+                first.panes_mut(out);
+                second.panes_mut(out);
+            }
+        }
+    }
+    fn bounds(&self, bound: &Boundary, out: &mut Vec<Boundary>) {
+        match self {
+            Node::Pane(_) => out.push(bound.clone()),
+            Node::Split { direction, ratio, first, second } => {
+                let (a, _, b) = split_bound(bound, *direction, *ratio);
+                first.bounds(&a, out);
+                second.bounds(&b, out);
+            }
+        }
+    }
+    fn dividers(&self, bound: &Boundary, out: &mut Vec<Divider>) {
+        if let Node::Split { direction, ratio, first, second } = self {
+            let (a, rect, b) = split_bound(bound, *direction, *ratio);
+            out.push(Divider { split: out.len(), rect, area: bound.clone(), direction: *direction });
+            first.dividers(&a, out);
+            second.dividers(&b, out);
+        }
+    }
+}
+pub struct Layout {
+    pub root: Node,
+    pub focused: usize,
+}
+impl Layout {
+    pub fn new() -> Self {
+        Self { root: Node::Pane(Pane::new(0)), focused: 0 }
+    }
+    pub fn count(&self) -> usize {
+        self.root.count()
+    }
+    pub fn panes(&self) -> Vec<&Pane> {
+        let mut out = Vec::new();
+        self.root.panes(&mut out);
+        out
+    }
+    pub fn panes_mut(&mut self) -> Vec<&mut Pane> {
+        let mut out = Vec::new();
+        self.root.panes_mut(&mut out);
+        out
+    }
+    // Where each pane goes in `bound`
+    pub fn bounds(&self, bound: &Boundary) -> Vec<Boundary> {
+        let mut out = Vec::new();
+        self.root.bounds(bound, &mut out);
+        out
+    }
+    pub fn dividers(&self, bound: &Boundary) -> Vec<Divider> {
+        let mut out = Vec::new();
+        self.root.dividers(bound, &mut out);
+        out
+    }
+    pub fn pane_at(&self, p: Vector2f, bound: &Boundary) -> Option<usize> {
+        self.bounds(bound).iter().position(|b| p.0 >= b.pos.0 && p.0 < b.pos.0 + b.size.0 && p.1 >= b.pos.1 && p.1 < b.pos.1 + b.size.1)
+    }
+    // The divider at `p`, with a bit of slack since they're thin
+    pub fn divider_at(&self, p: Vector2f, bound: &Boundary) -> Option<Divider> {
+        let slack = 3.0;
+        self.dividers(bound).into_iter().find(|d| {
+            let r = &d.rect;
+            p.0 >= r.pos.0 - slack && p.0 < r.pos.0 + r.size.0 + slack && p.1 >= r.pos.1 - slack && p.1 < r.pos.1 + r.size.1 + slack
+        })
+    }
+    // Moves the divider of a split to `p`, which is somewhere in the area of the split
+    pub fn drag(&mut self, divider: &Divider, p: Vector2f) {
+        let area = &divider.area;
+        let ratio = match divider.direction {
+            Direction::Right => (p.0 - area.pos.0) / area.size.0,
+            Direction::Down => (area.pos.1 + area.size.1 - p.1) / area.size.1,
+        };
+        let ratio = ratio.clamp(MIN_RATIO, 1.0 - MIN_RATIO);
+        fn set(node: &mut Node, split: usize, n: &mut usize, to: f32) {
+            if let Node::Split { ratio, first, second, .. } = node {
+                if *n == split {
+                    *ratio = to;
+                    return;
+                }
+                *n += 1;
+                set(first, split, n, to);
+                set(second, split, n, to);
+            }
+        }
+        set(&mut self.root, divider.split, &mut 0, ratio);
+    }
+    // Splits pane `index` in two, the new half showing `pane`. Gives back the index of the new pane
+    pub fn split(&mut self, index: usize, direction: Direction, pane: Pane) -> usize {
+        fn go(node: &mut Node, index: usize, n: &mut usize, direction: Direction, pane: &mut Option<Pane>) {
+            match node {
+                Node::Pane(_) => {
+                    if *n == index {
+                        let old = std::mem::replace(node, Node::Pane(Pane::new(0)));
+                        let new = Node::Pane(pane.take().unwrap());
+                        *node = Node::Split { direction, ratio: 0.5, first: Box::new(old), second: Box::new(new) };
+                    }
+                    *n += 1;
+                }
+                Node::Split { first, second, .. } => {
+                    go(first, index, n, direction, pane);
+                    go(second, index, n, direction, pane);
+                }
+            }
+        }
+        go(&mut self.root, index, &mut 0, direction, &mut Some(pane));
+        index + 1
+    }
+    // Takes pane `index` out and its sibling gets the space. The last pane can't be closed
+    pub fn close(&mut self, index: usize) -> Option<Pane> {
+        fn go(node: &mut Node, index: usize, n: &mut usize) -> Option<Pane> {
+            let Node::Split { first, second, .. } = node else {
+                *n += 1;
+                return None;
+            };
+            let firstCount = first.count();
+            let isFirst = matches!(**first, Node::Pane(_)) && *n == index;
+            let isSecond = matches!(**second, Node::Pane(_)) && *n + firstCount == index;
+            if isFirst || isSecond {
+                let Node::Split { first, second, .. } = std::mem::replace(node, Node::Pane(Pane::new(0))) else { unreachable!() };
+                let (closed, keep) = if isFirst { (first, second) } else { (second, first) };
+                *node = *keep;
+                let Node::Pane(pane) = *closed else { unreachable!() };
+                return Some(pane);
+            }
+            go(first, index, n).or_else(|| go(second, index, n))
+        }
+        go(&mut self.root, index, &mut 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Panes are told apart by their buffer
+    fn order(layout: &Layout) -> Vec<usize> {
+        layout.panes().iter().map(|p| p.buffer).collect()
+    }
+    fn ratios(node: &Node, out: &mut Vec<f32>) {
+        if let Node::Split { ratio, first, second, .. } = node {
+            out.push(*ratio);
+            ratios(first, out);
+            ratios(second, out);
+        }
+    }
+    fn area() -> Boundary {
+        Boundary { pos: Vector2f(0.0, 0.0), size: Vector2f(100.0, 100.0) }
+    }
+
+    #[test]
+    fn split_and_close() {
+        let mut layout = Layout::new();
+        assert_eq!(layout.split(0, Direction::Right, Pane::new(1)), 1);
+        assert_eq!(layout.split(0, Direction::Down, Pane::new(2)), 1);
+        assert_eq!(layout.split(2, Direction::Right, Pane::new(3)), 3);
+        assert_eq!(order(&layout), vec![0, 2, 1, 3]);
+        assert_eq!(layout.close(1).map(|p| p.buffer), Some(2));
+        assert_eq!(order(&layout), vec![0, 1, 3]);
+        assert_eq!(layout.close(0).map(|p| p.buffer), Some(0));
+        assert_eq!(order(&layout), vec![1, 3]);
+        assert_eq!(layout.close(1).map(|p| p.buffer), Some(3));
+        assert_eq!(order(&layout), vec![1]);
+        assert!(layout.close(0).is_none());
+        assert_eq!(layout.count(), 1);
+    }
+    #[test]
+    fn closing_next_to_a_split() {
+        let mut layout = Layout::new();
+        layout.split(0, Direction::Right, Pane::new(1));
+        layout.split(1, Direction::Down, Pane::new(2));
+        assert_eq!(layout.close(0).map(|p| p.buffer), Some(0));
+        assert_eq!(order(&layout), vec![1, 2]);
+        // The split that was the sibling takes over the whole area
+        let dividers = layout.dividers(&area());
+        assert_eq!(dividers.len(), 1);
+        assert_eq!(dividers[0].direction, Direction::Down);
+        assert_eq!((dividers[0].area.size.0, dividers[0].area.size.1), (100.0, 100.0));
+        assert!(layout.close(5).is_none());
+        assert_eq!(order(&layout), vec![1, 2]);
+    }
+    #[test]
+    fn drag_moves_its_own_split() {
+        let mut layout = Layout::new();
+        layout.split(0, Direction::Right, Pane::new(2));
+        layout.split(1, Direction::Down, Pane::new(3));
+        layout.split(0, Direction::Down, Pane::new(1));
+        assert_eq!(order(&layout), vec![0, 1, 2, 3]);
+        let dividers = layout.dividers(&area());
+        let directions: Vec<Direction> = dividers.iter().map(|d| d.direction).collect();
+        assert_eq!(directions, vec![Direction::Right, Direction::Down, Direction::Down]);
+        // The divider between panes 2 and 3, on the right
+        let right = dividers.iter().find(|d| d.direction == Direction::Down && d.area.pos.0 > 0.0).unwrap();
+        assert_eq!(right.split, 2);
+        layout.drag(right, Vector2f(75.0, 75.0));
+        let mut r = Vec::new();
+        ratios(&layout.root, &mut r);
+        assert_eq!(r, vec![0.5, 0.5, 0.25]);
+        let bounds = layout.bounds(&area());
+        assert_eq!(bounds[2].size.1, 25.0 - DIVIDER / 2.0);
+        // Dragged past the edge it stops short of squashing a side
+        let split = layout.divider_at(Vector2f(50.0, 10.0), &area()).unwrap();
+        assert_eq!(split.split, 0);
+        layout.drag(&split, Vector2f(0.0, 10.0));
+        let mut r = Vec::new();
+        ratios(&layout.root, &mut r);
+        assert_eq!(r, vec![MIN_RATIO, 0.5, 0.25]);
+    }
+}
@@ -0,0 +1,85 @@
+// The box quick open and the command palette are drawn in: what's been typed on top and the choices
+// below it, with the parts the query matched picked out
+use crate::renderer::{Boundary, Renderer};
+use crate::theme::Theme;
+use crate::{Font, Vector2f};
+
+pub const ROWS: usize = 12; // Choices shown at once
+
+pub struct Row<'a> {
+    pub text: &'a str,
+    pub matched: &'a [usize], // Chars of `text` the query matched, in order
+    pub detail: &'a str, // Dimmed, after the text
+    pub right: &'a str, // On the right edge
+}
+// The first row to show so that `selected` is on screen, starting from the one shown first so far
+pub fn scroll_to(selected: usize, scroll: usize) -> usize {
+    if selected < scroll {
+        selected
+    } else if selected >= scroll + ROWS {
+        selected + 1 - ROWS
+    } else {
+        scroll
+    }
+}
+pub struct Picker<'a> {
+    pub input: &'a str,
+    pub preedit: &'a str,
+    pub status: &'a str, // On the right of the input
+    pub rows: Vec<Row<'a>>, // Only the ones on screen
+    pub selected: Option<usize>, // Counting from the first row on screen
+}
+impl Picker<'_> {
+    pub fn display(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        let Self { input, preedit, status, rows, selected } = self;
+        let selected = *selected;
+        let width = |s: &str| font.measure_text(s, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+        let rowH = (font.fontSize + 6) as f32;
+        let w = (bound.size.0 - 40.0).clamp(0.0, 700.0);
+        let h = rowH * (rows.len() + 1) as f32 + 12.0;
+        let top = bound.top_left();
+        let pos = Vector2f(top.0 + (bound.size.0 - w) / 2.0, top.1 - 40.0 - h);
+        let area = Boundary { pos, size: Vector2f(w, h) };
+        r.scisorsBegin(&area);
+        r.draw_rect(theme.gutter, pos, area.size);
+        let inputY = pos.1 + h - rowH - 4.0;
+        r.draw_rect(theme.status, Vector2f(pos.0, inputY - 4.0), Vector2f(w, rowH + 8.0));
+        let text = Vector2f(pos.0 + 8.0, inputY + 3.0);
+        r.draw_str(font, input, text, theme.status_foreground);
+        let off = width(input);
+        if preedit.is_empty() {
+            r.draw_rect(theme.cursor, text + Vector2f(off, -4.0), Vector2f(2.0, rowH - 2.0));
+        } else {
+            r.draw_str(font, preedit, text + Vector2f(off, 0.0), theme.status_foreground);
+            r.draw_rect(theme.status_foreground, text + Vector2f(off, -3.0), Vector2f(width(preedit), 1.0));
+        }
+        r.draw_str(font, status, Vector2f(pos.0 + w - width(status) - 8.0, text.1), theme.status_foreground);
+        let mut y = inputY - 6.0;
+        for (i, row) in rows.iter().enumerate() {
+            y -= rowH;
+            if selected == Some(i) {
+                r.draw_rect(theme.selection, Vector2f(pos.0, y), Vector2f(w, rowH));
+            }
+            let mut x = pos.0 + 8.0;
+            let mut next = 0;
+            for (k, c) in row.text.chars().enumerate() {
+                if x > pos.0 + w { break; }
+                let matched = row.matched.get(next) == Some(&k);
+                if matched { next += 1; }
+                r.draw_char(font, c, Vector2f(x, y + 5.0), if matched { theme.keyword } else { theme.foreground });
+                x += font.get_char(c).advance_x as f32;
+            }
+            if !row.detail.is_empty() {
+                r.draw_str(font, row.detail, Vector2f(x + 16.0, y + 5.0), theme.gutter_foreground);
+            }
+            if !row.right.is_empty() {
+                // NOTE: Whatever the detail ran into gets covered up, the keys matter more
+                let rw = width(row.right);
+                let bg = if selected == Some(i) { theme.selection } else { theme.gutter };
+                r.draw_rect(bg, Vector2f(pos.0 + w - rw - 24.0, y), Vector2f(rw + 24.0, rowH));
+                r.draw_str(font, row.right, Vector2f(pos.0 + w - rw - 8.0, y + 5.0), theme.gutter_foreground);
+            }
+        }
+        r.scisorsEnd();
+    }
+}
@@ -0,0 +1,148 @@
+// Searching every file under a directory. The walk and the searching happen on other threads and
+// the results come back through a channel file by file, so nothing waits for the whole thing
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::search::Search;
+use crate::{encoding, endings, walk};
+
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+const MAX_HITS: usize = 10000;
+const MAX_PREVIEW: usize = 300; // In bytes
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub line: usize,
+    pub chr: usize, // NOTE: In bytes, like Cursor::chr
+    pub preview: String, // The line, cut short if it's long
+    pub matches: Vec<Range<usize>>, // Where in the preview the matches are
+}
+#[derive(Debug, Clone)]
+pub struct FileHits {
+    pub path: PathBuf,
+    pub hits: Vec<Hit>,
+}
+// Every hit in `bytes`, which are the contents of one file the way the editor has them
+pub fn search_bytes(search: &Search, bytes: &[u8]) -> Vec<Hit> {
+    let mut hits = Vec::new();
+    for (line, text) in bytes.split(|b| *b == b'\n').enumerate() {
+        let matches = search.line_matches(text);
+        let Some(first) = matches.first() else { continue; };
+        let mut end = text.len().min(MAX_PREVIEW);
+        while end < text.len() && crate::rope::is_continuation(text[end]) {
+            end -= 1;
+        }
+        let preview = String::from_utf8_lossy(&text[..end]).into_owned();
+        let matches = matches.iter().filter(|m| m.start < end).map(|m| m.start..m.end.min(end)).collect();
+        hits.push(Hit { line, chr: first.start, preview, matches });
+    }
+    hits
+}
+// The file decoded and with '\n' line endings, like Editor::open does it, so that the hits land on
+// the same line and byte once it's open. Binary files have no business in the results
+pub fn decoded(bytes: &[u8]) -> Option<Vec<u8>> {
+    let encoding = encoding::detect(bytes)?;
+    Some(endings::normalize(&encoding::decode(bytes, encoding)))
+}
+pub struct ProjectSearch {
+    pub root: PathBuf,
+    results: Receiver<FileHits>,
+    cancel: Arc<AtomicBool>,
+    pub files: Arc<AtomicUsize>, // How many have been searched so far
+    pub hits: usize,
+    pub done: bool,
+    pub truncated: bool, // Stopped early because there were too many hits
+}
+impl ProjectSearch {
+    pub fn start(root: &Path, search: Search) -> Self {
+        let (paths_tx, paths_rx) = mpsc::channel::<PathBuf>();
+        let (results_tx, results) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let files = Arc::new(AtomicUsize::new(0));
+        {
+            let root = root.to_path_buf();
+            let cancel = cancel.clone();
+            thread::spawn(move || walk::walk(&root, |path| !cancel.load(Ordering::Relaxed) && paths_tx.send(path).is_ok()));
+        }
+        // NOTE: mpsc receivers can't be shared, so the workers take turns on it
+        let paths = Arc::new(Mutex::new(paths_rx));
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        for _ in 0..workers {
+            let (paths, results_tx, cancel, files, search) = (paths.clone(), results_tx.clone(), cancel.clone(), files.clone(), search.clone());
+            thread::spawn(move || loop {
+                let next = paths.lock().unwrap().recv();
+                let Ok(path) = next else { return; };
+                if cancel.load(Ordering::Relaxed) { return; }
+                if std::fs::metadata(&path).is_ok_and(|m| m.len() > MAX_FILE_SIZE) { continue; }
+                let Ok(bytes) = std::fs::read(&path) else { continue; };
+                files.fetch_add(1, Ordering::Relaxed);
+                let Some(text) = decoded(&bytes) else { continue; };
+                let hits = search_bytes(&search, &text);
+                if !hits.is_empty() && results_tx.send(FileHits { path, hits }).is_err() { return; }
+            });
+        }
+        Self { root: root.to_path_buf(), results, cancel, files, hits: 0, done: false, truncated: false }
+    }
+    // Whatever arrived since the last time. Call it every frame
+    pub fn poll(&mut self) -> Vec<FileHits> {
+        let mut res = Vec::new();
+        while !self.done {
+            match self.results.try_recv() {
+                Ok(found) => {
+                    self.hits += found.hits.len();
+                    res.push(found);
+                    if self.hits >= MAX_HITS {
+                        self.truncated = true;
+                        self.done = true;
+                        self.cancel.store(true, Ordering::Relaxed);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // Every worker is gone, so there's nothing more to come
+                Err(TryRecvError::Disconnected) => self.done = true,
+            }
+        }
+        res
+    }
+}
+impl Drop for ProjectSearch {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    fn find(query: &str, bytes: &[u8]) -> Vec<(usize, usize, String)> {
+        let search = Search::new(query, SearchOptions::default()).unwrap().unwrap();
+        let Some(text) = decoded(bytes) else { return Vec::new(); };
+        search_bytes(&search, &text).into_iter().map(|h| (h.line, h.chr, h.preview)).collect()
+    }
+    fn utf16le(s: &str) -> Vec<u8> {
+        [0xFF, 0xFE].into_iter().chain(s.encode_utf16().flat_map(|u| u.to_le_bytes())).collect()
+    }
+
+    #[test]
+    fn hits_are_where_the_editor_puts_them() {
+        let want = vec![(0, 7, "héllo needle".to_string()), (2, 0, "needle needle".to_string())];
+        assert_eq!(find("needle", "héllo needle\nno\nneedle needle".as_bytes()), want);
+        assert_eq!(find("needle", "héllo needle\r\nno\r\nneedle needle\r\n".as_bytes()), want);
+        assert_eq!(find("needle", "héllo needle\rno\rneedle needle".as_bytes()), want);
+        assert_eq!(find("needle", &utf16le("héllo needle\r\nno\r\nneedle needle")), want);
+        assert_eq!(find("needle", b"h\xE9llo needle\nno\nneedle needle"), want);
+        // NOTE: The decoded "é" is two bytes, like in the buffer, where the file has just the one
+        assert_eq!(find("llo", b"h\xE9llo"), vec![(0, 3, "héllo".to_string())]);
+    }
+    #[test]
+    fn binary_files_are_skipped() {
+        assert_eq!(find("needle", b"needle\0\0\x01\x02"), Vec::new());
+        assert!(!find("needle", b"needle").is_empty());
+    }
+}
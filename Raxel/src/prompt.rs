@@ -0,0 +1,63 @@
+use beryllium::events::{SDL_Keycode, SDLK_BACKSPACE, SDLK_ESCAPE, SDLK_KP_ENTER, SDLK_RETURN};
+
+use crate::renderer::{Boundary, Renderer};
+use crate::theme::Theme;
+use crate::{Font, Vector2f};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    SaveAs,
+    Open,
+    ConfirmQuit,
+    ConfirmClose, // Closing the current buffer with unsaved changes in it
+    Ex, // The vim command line
+}
+pub enum PromptResult {
+    Pending,
+    Cancel,
+    Submit(String),
+}
+// Single line of input shown at the bottom of the window
+#[derive(Debug)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub label: String,
+    pub input: String,
+    pub preedit: String,
+}
+impl Prompt {
+    pub fn new(kind: PromptKind, label: &str, input: &str) -> Self {
+        Self { kind, label: label.to_string(), input: input.to_string(), preedit: String::new() }
+    }
+    pub fn handle_key(&mut self, keycode: SDL_Keycode) -> PromptResult {
+        match keycode {
+            SDLK_ESCAPE => PromptResult::Cancel,
+            SDLK_RETURN | SDLK_KP_ENTER => PromptResult::Submit(std::mem::take(&mut self.input)),
+            SDLK_BACKSPACE => {
+                self.input.pop();
+                PromptResult::Pending
+            }
+            _ => PromptResult::Pending,
+        }
+    }
+    pub fn handle_text(&mut self, text: &str) {
+        self.input.extend(text.chars().filter(|c| !c.is_control()));
+    }
+    pub fn height(font: &Font) -> f32 {
+        (font.fontSize + 8) as f32
+    }
+    pub fn display(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        let h = Self::height(font);
+        r.draw_rect(theme.status, bound.pos, Vector2f(bound.size.0, h));
+        let pos = bound.pos + Vector2f(4.0, 6.0);
+        r.draw_str(font, &self.label, pos, theme.status_foreground);
+        let off = font.measure_text(&self.label, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+        r.draw_str(font, &self.input, pos + Vector2f(off, 0.0), theme.status_foreground);
+        if !self.preedit.is_empty() {
+            let off = off + font.measure_text(&self.input, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+            let w = font.measure_text(&self.preedit, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+            r.draw_str(font, &self.preedit, pos + Vector2f(off, 0.0), theme.status_foreground);
+            r.draw_rect(theme.status_foreground, pos + Vector2f(off, -3.0), Vector2f(w, 1.0));
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Ctrl+P: finding a file in the project by typing part of its path. The files get listed on another
+// thread every time it opens, so new ones show up without having to restart
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use beryllium::events::{SDL_Keycode, SDL_Keymod, SDLK_BACKSPACE, SDLK_DOWN, SDLK_ESCAPE, SDLK_KP_ENTER, SDLK_PAGEDOWN, SDLK_PAGEUP, SDLK_RETURN, SDLK_UP};
+
+use crate::fuzzy;
+use crate::picker::{self, Picker, Row, ROWS};
+use crate::renderer::{Boundary, Renderer};
+use crate::theme::Theme;
+use crate::walk;
+use crate::Font;
+
+const MAX_FILES: usize = 100000;
+const BATCH: usize = 256;
+
+pub enum FinderAction {
+    Ignored, // The key wasn't for the finder
+    Handled,
+    Open(PathBuf),
+}
+struct Match {
+    file: usize,
+    score: i32,
+    positions: Vec<usize>, // Chars of the path the query matched
+}
+pub struct QuickOpen {
+    pub open: bool,
+    pub root: PathBuf,
+    pub query: String,
+    pub preedit: String,
+    pub selected: usize,
+    files: Vec<String>, // Relative to the root, with '/' between the parts
+    matches: Vec<Match>, // Best first
+    scroll: usize,
+    index: Option<Receiver<Vec<String>>>, // Files still coming in from the walk
+}
+impl QuickOpen {
+    pub fn new(root: PathBuf) -> Self {
+        Self { open: false, root, query: String::new(), preedit: String::new(), selected: 0, files: Vec::new(), matches: Vec::new(), scroll: 0, index: None }
+    }
+    pub fn show(&mut self) {
+        self.open = true;
+        self.query.clear();
+        self.files.clear();
+        self.matches.clear();
+        self.selected = 0;
+        self.scroll = 0;
+        let (tx, rx) = mpsc::channel();
+        let root = self.root.clone();
+        thread::spawn(move || {
+            let mut batch = Vec::new();
+            let mut count = 0;
+            // NOTE: Sending fails once the finder stopped listening, which ends the walk early
+            walk::walk(&root, |path| {
+                let Ok(rel) = path.strip_prefix(&root) else { return true; };
+                batch.push(rel.to_string_lossy().replace('\\', "/"));
+                count += 1;
+                if batch.len() < BATCH { return count < MAX_FILES; }
+                tx.send(std::mem::take(&mut batch)).is_ok() && count < MAX_FILES
+            });
+            let _ = tx.send(batch);
+        });
+        self.index = Some(rx);
+    }
+    pub fn close(&mut self) {
+        self.open = false;
+        self.preedit.clear();
+        self.index = None;
+    }
+    fn rank(&mut self) {
+        let files = &self.files;
+        self.matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| files[a.file].len().cmp(&files[b.file].len())).then_with(|| files[a.file].cmp(&files[b.file])));
+    }
+    fn add_matches(&mut self, from: usize) {
+        for (i, file) in self.files.iter().enumerate().skip(from) {
+            if let Some((score, positions)) = fuzzy::score(&self.query, file) {
+                self.matches.push(Match { file: i, score, positions });
+            }
+        }
+    }
+    // Scores every file again, after the query changed
+    pub fn update(&mut self) {
+        self.matches.clear();
+        self.add_matches(0);
+        self.rank();
+        self.selected = 0;
+        self.scroll = 0;
+    }
+    // Takes in the files the walk found since the last frame
+    pub fn poll(&mut self) {
+        let Some(index) = &self.index else { return; };
+        let from = self.files.len();
+        loop {
+            match index.try_recv() {
+                Ok(batch) => self.files.extend(batch),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.index = None;
+                    break;
+                }
+            }
+        }
+        if self.files.len() == from { return; }
+        self.add_matches(from);
+        self.rank();
+    }
+    fn select(&mut self, selected: usize) {
+        self.selected = selected.min(self.matches.len().saturating_sub(1));
+        self.scroll = picker::scroll_to(self.selected, self.scroll);
+    }
+    pub fn handle_key(&mut self, keycode: SDL_Keycode, _modifiers: SDL_Keymod) -> FinderAction {
+        match keycode {
+            SDLK_ESCAPE => self.close(),
+            SDLK_RETURN | SDLK_KP_ENTER => {
+                let Some(m) = self.matches.get(self.selected) else { return FinderAction::Handled; };
+                let path = self.root.join(&self.files[m.file]);
+                self.close();
+                return FinderAction::Open(path);
+            }
+            SDLK_UP => self.select(self.selected.saturating_sub(1)),
+            SDLK_DOWN => self.select(self.selected + 1),
+            SDLK_PAGEUP => self.select(self.selected.saturating_sub(ROWS)),
+            SDLK_PAGEDOWN => self.select(self.selected + ROWS),
+            SDLK_BACKSPACE => {
+                self.query.pop();
+                self.update();
+            }
+            _ => return FinderAction::Ignored,
+        }
+        FinderAction::Handled
+    }
+    pub fn handle_text(&mut self, text: &str) {
+        self.query.extend(text.chars().filter(|c| !c.is_control()));
+        self.update();
+    }
+    // Drawn as a box near the top of `bound`, over whatever is below it
+    pub fn display(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        if !self.open { return; }
+        let status = if self.index.is_some() { format!("{} files ...", self.files.len()) } else { format!("{}/{}", self.matches.len(), self.files.len()) };
+        let rows: Vec<Row> = self.matches.iter().skip(self.scroll).take(ROWS).map(|m| Row { text: &self.files[m.file], matched: &m.positions, detail: "", right: "" }).collect();
+        let picker = Picker { input: &self.query, preedit: &self.preedit, status: &status, rows, selected: self.selected.checked_sub(self.scroll) };
+        picker.display(r, bound, font, theme);
+    }
+}
@@ -77,6 +77,8 @@ impl Renderer {
             tex
         };
         }
+        // Shapes get the first batch so things like selections end up behind the text
+        self.batch_mut(self.texshape.id);
     }
     pub fn gl_position2d(&self, pos: &Vector2f) -> Vector2f {
         Vector2f(
@@ -90,10 +92,14 @@ impl Renderer {
         self.time.update = self.time.now.duration_since(self.time.then).expect("TIme has gone backwards").as_secs_f32();
         self.time.then = self.time.now;
     }
-    pub fn end(&mut self) {
+    // Draws everything batched so far. Anything drawn after this ends up on top of it
+    pub fn flush(&mut self) {
         for batch in self.batches.iter_mut() {
             batch.update();
         }
+    }
+    pub fn end(&mut self) {
+        self.flush();
         self.win.swap_window();
         self.time.now = SystemTime::now();
         self.time.draw = self.time.now.duration_since(self.time.then).expect("TIme has gone backwards").as_secs_f32();
@@ -112,7 +118,10 @@ impl Renderer {
         }
         self.time.dt += (self.time.wait) as f32;
     }
-    pub fn scisorsBegin(&self, bound: &Boundary) {
+    // NOTE: Batches are only drawn at flush time, so changing the scissor state has to flush
+    // whatever was drawn under the previous one first
+    pub fn scisorsBegin(&mut self, bound: &Boundary) {
+        self.flush();
         let x = bound.pos.0.round() as i32;
         let y = bound.pos.1.round() as i32;
         let w = bound.size.0.round() as i32;
@@ -122,7 +131,8 @@ impl Renderer {
             glScissor(x,y,w,h);
         }
     }
-    pub fn scisorsEnd(&self) {
+    pub fn scisorsEnd(&mut self) {
+        self.flush();
         unsafe {
         glDisable(GL_SCISSOR_TEST);
         }
@@ -0,0 +1,551 @@
+use std::ops::Range;
+
+// NOTE: Leaves are kept at or below this size. Small enough that splicing into one is cheap,
+// big enough that the tree stays shallow for multi-megabyte files.
+const MAX_LEAF: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub bytes: usize,
+    pub chars: usize, // NOTE: Counted as utf8 lead bytes, so invalid utf8 still counts as something
+    pub newlines: usize,
+}
+impl Metrics {
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut m = Self { bytes: bytes.len(), chars: 0, newlines: 0 };
+        for b in bytes {
+            if !is_continuation(*b) { m.chars += 1; }
+            if *b == b'\n' { m.newlines += 1; }
+        }
+        m
+    }
+}
+impl std::ops::Add for Metrics {
+    type Output = Metrics;
+    fn add(self, rhs: Metrics) -> Self::Output {
+        Metrics { bytes: self.bytes + rhs.bytes, chars: self.chars + rhs.chars, newlines: self.newlines + rhs.newlines }
+    }
+}
+impl std::ops::Sub for Metrics {
+    type Output = Metrics;
+    fn sub(self, rhs: Metrics) -> Self::Output {
+        Metrics { bytes: self.bytes - rhs.bytes, chars: self.chars - rhs.chars, newlines: self.newlines - rhs.newlines }
+    }
+}
+#[inline]
+pub fn is_continuation(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
+// Same shape as the old Lines index used to hand out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line {
+    pub at: usize, // NOTE: In bytes
+    pub len: usize // NOTE: In bytes. Does not include the '\n'
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf { bytes: Vec<u8>, metrics: Metrics },
+    Branch { left: Box<Node>, right: Box<Node>, metrics: Metrics, height: usize },
+}
+impl Node {
+    fn leaf(bytes: Vec<u8>) -> Box<Node> {
+        let metrics = Metrics::of(&bytes);
+        Box::new(Node::Leaf { bytes, metrics })
+    }
+    fn branch(left: Box<Node>, right: Box<Node>) -> Box<Node> {
+        let metrics = left.metrics() + right.metrics();
+        let height = left.height().max(right.height()) + 1;
+        Box::new(Node::Branch { left, right, metrics, height })
+    }
+    fn metrics(&self) -> Metrics {
+        match self {
+            Node::Leaf { metrics, .. } => *metrics,
+            Node::Branch { metrics, .. } => *metrics,
+        }
+    }
+    fn height(&self) -> usize {
+        match self {
+            Node::Leaf { .. } => 0,
+            Node::Branch { height, .. } => *height,
+        }
+    }
+    fn len(&self) -> usize {
+        self.metrics().bytes
+    }
+    // Builds a perfectly balanced tree out of a run of leaves
+    fn build(leaves: Vec<Node>) -> Box<Node> {
+        fn go(leaves: &mut std::vec::IntoIter<Node>, n: usize) -> Box<Node> {
+            if n == 1 { return Box::new(leaves.next().expect("Counted leaves")); }
+            let left = go(leaves, n / 2);
+            let right = go(leaves, n - n / 2);
+            Node::branch(left, right)
+        }
+        let n = leaves.len();
+        if n == 0 { return Node::leaf(Vec::new()); }
+        go(&mut leaves.into_iter(), n)
+    }
+    fn from_bytes(bytes: &[u8]) -> Box<Node> {
+        let leaves: Vec<Node> = bytes.chunks(MAX_LEAF).map(|c| *Node::leaf(c.to_vec())).collect();
+        Self::build(leaves)
+    }
+
+    fn rotate_left(node: Node) -> Box<Node> {
+        match node {
+            Node::Branch { left, right, .. } => match *right {
+                Node::Branch { left: rl, right: rr, .. } => Node::branch(Node::branch(left, rl), rr),
+                right => Node::branch(left, Box::new(right)),
+            },
+            leaf => Box::new(leaf),
+        }
+    }
+    fn rotate_right(node: Node) -> Box<Node> {
+        match node {
+            Node::Branch { left, right, .. } => match *left {
+                Node::Branch { left: ll, right: lr, .. } => Node::branch(ll, Node::branch(lr, right)),
+                left => Node::branch(Box::new(left), right),
+            },
+            leaf => Box::new(leaf),
+        }
+    }
+    fn balance_factor(&self) -> isize {
+        match self {
+            Node::Leaf { .. } => 0,
+            Node::Branch { left, right, .. } => left.height() as isize - right.height() as isize,
+        }
+    }
+    // Standard AVL rebalance of a node whose children differ in height by at most 2
+    fn rebalance(node: Box<Node>) -> Box<Node> {
+        let bf = node.balance_factor();
+        if bf > 1 {
+            let (left, right) = match *node {
+                Node::Branch { left, right, .. } => (left, right),
+                _ => unreachable!(),
+            };
+            let left = if left.balance_factor() < 0 { Self::rotate_left(*left) } else { left };
+            Self::rotate_right(*Node::branch(left, right))
+        } else if bf < -1 {
+            let (left, right) = match *node {
+                Node::Branch { left, right, .. } => (left, right),
+                _ => unreachable!(),
+            };
+            let right = if right.balance_factor() > 0 { Self::rotate_right(*right) } else { right };
+            Self::rotate_left(*Node::branch(left, right))
+        } else {
+            node
+        }
+    }
+    fn join(l: Box<Node>, r: Box<Node>) -> Box<Node> {
+        if l.len() == 0 { return r; }
+        if r.len() == 0 { return l; }
+        if let (Node::Leaf { bytes: lb, .. }, Node::Leaf { bytes: rb, .. }) = (&*l, &*r) {
+            if lb.len() + rb.len() <= MAX_LEAF {
+                let mut bytes = Vec::with_capacity(lb.len() + rb.len());
+                bytes.extend_from_slice(lb);
+                bytes.extend_from_slice(rb);
+                return Node::leaf(bytes);
+            }
+        }
+        let (lh, rh) = (l.height(), r.height());
+        if lh > rh + 1 {
+            match *l {
+                Node::Branch { left, right, .. } => Self::rebalance(Node::branch(left, Self::join(right, r))),
+                _ => unreachable!("Leaves have height 0"),
+            }
+        } else if rh > lh + 1 {
+            match *r {
+                Node::Branch { left, right, .. } => Self::rebalance(Node::branch(Self::join(l, left), right)),
+                _ => unreachable!("Leaves have height 0"),
+            }
+        } else {
+            Node::branch(l, r)
+        }
+    }
+    fn split(node: Node, at: usize) -> (Box<Node>, Box<Node>) {
+        match node {
+            Node::Leaf { mut bytes, .. } => {
+                let right = bytes.split_off(at);
+                (Node::leaf(bytes), Node::leaf(right))
+            }
+            Node::Branch { left, right, .. } => {
+                let ll = left.len();
+                if at <= ll {
+                    let (a, b) = Self::split(*left, at);
+                    (a, Self::join(b, right))
+                } else {
+                    let (a, b) = Self::split(*right, at - ll);
+                    (Self::join(left, a), b)
+                }
+            }
+        }
+    }
+    // Fast path for typing: splice straight into a leaf when it has room.
+    // Returns false (without touching anything) if it doesn't.
+    fn insert_in_place(&mut self, at: usize, text: &[u8], added: Metrics) -> bool {
+        match self {
+            Node::Leaf { bytes, metrics } => {
+                if bytes.len() + text.len() > MAX_LEAF { return false; }
+                bytes.splice(at..at, text.iter().copied());
+                *metrics = *metrics + added;
+                true
+            }
+            Node::Branch { left, right, metrics, .. } => {
+                let ll = left.len();
+                let ok = if at <= ll { left.insert_in_place(at, text, added) } else { right.insert_in_place(at - ll, text, added) };
+                if ok { *metrics = *metrics + added; }
+                ok
+            }
+        }
+    }
+    fn remove_in_place(&mut self, range: Range<usize>) -> bool {
+        match self {
+            Node::Leaf { bytes, metrics } => {
+                if range.end - range.start >= bytes.len() { return false; }
+                let removed = Metrics::of(&bytes[range.clone()]);
+                bytes.drain(range);
+                *metrics = *metrics - removed;
+                true
+            }
+            Node::Branch { left, right, metrics, .. } => {
+                let ll = left.len();
+                let ok = if range.end <= ll {
+                    left.remove_in_place(range)
+                } else if range.start >= ll {
+                    right.remove_in_place(range.start - ll..range.end - ll)
+                } else {
+                    false
+                };
+                if ok { *metrics = left.metrics() + right.metrics(); }
+                ok
+            }
+        }
+    }
+
+    fn line_to_byte(&self, line: usize) -> usize {
+        match self {
+            Node::Leaf { bytes, .. } => {
+                if line == 0 { return 0; }
+                bytes.iter().enumerate().filter(|(_, b)| **b == b'\n').nth(line - 1).map(|(i, _)| i + 1).unwrap_or(bytes.len())
+            }
+            Node::Branch { left, right, .. } => {
+                let lm = left.metrics();
+                if line <= lm.newlines { left.line_to_byte(line) } else { lm.bytes + right.line_to_byte(line - lm.newlines) }
+            }
+        }
+    }
+    fn byte_to_line(&self, at: usize) -> usize {
+        match self {
+            Node::Leaf { bytes, .. } => bytes[..at].iter().filter(|b| **b == b'\n').count(),
+            Node::Branch { left, right, .. } => {
+                let lm = left.metrics();
+                if at <= lm.bytes { left.byte_to_line(at) } else { lm.newlines + right.byte_to_line(at - lm.bytes) }
+            }
+        }
+    }
+    fn char_to_byte(&self, chr: usize) -> usize {
+        match self {
+            Node::Leaf { bytes, .. } => {
+                bytes.iter().enumerate().filter(|(_, b)| !is_continuation(**b)).nth(chr).map(|(i, _)| i).unwrap_or(bytes.len())
+            }
+            Node::Branch { left, right, .. } => {
+                let lm = left.metrics();
+                if chr < lm.chars { left.char_to_byte(chr) } else { lm.bytes + right.char_to_byte(chr - lm.chars) }
+            }
+        }
+    }
+    fn byte_to_char(&self, at: usize) -> usize {
+        match self {
+            Node::Leaf { bytes, .. } => bytes[..at].iter().filter(|b| !is_continuation(**b)).count(),
+            Node::Branch { left, right, .. } => {
+                let lm = left.metrics();
+                if at <= lm.bytes { left.byte_to_char(at) } else { lm.chars + right.byte_to_char(at - lm.bytes) }
+            }
+        }
+    }
+    fn byte(&self, at: usize) -> u8 {
+        match self {
+            Node::Leaf { bytes, .. } => bytes[at],
+            Node::Branch { left, right, .. } => {
+                let ll = left.len();
+                if at < ll { left.byte(at) } else { right.byte(at - ll) }
+            }
+        }
+    }
+    fn for_each_chunk<'a>(&'a self, range: Range<usize>, f: &mut impl FnMut(&'a [u8])) {
+        if range.start >= range.end { return; }
+        match self {
+            Node::Leaf { bytes, .. } => f(&bytes[range]),
+            Node::Branch { left, right, .. } => {
+                let ll = left.len();
+                if range.start < ll {
+                    left.for_each_chunk(range.start..range.end.min(ll), f);
+                }
+                if range.end > ll {
+                    right.for_each_chunk(range.start.max(ll) - ll..range.end - ll, f);
+                }
+            }
+        }
+    }
+}
+
+// A balanced tree of byte chunks. Every edit and every line/char/byte lookup is O(log n)
+// in the size of the buffer, which is what lets us open and type into huge log files.
+#[derive(Debug, Clone)]
+pub struct Rope {
+    root: Box<Node>,
+}
+impl Rope {
+    pub fn new() -> Self {
+        Self { root: Node::leaf(Vec::new()) }
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self { root: Node::from_bytes(bytes) }
+    }
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn len_chars(&self) -> usize {
+        self.root.metrics().chars
+    }
+    pub fn len_lines(&self) -> usize {
+        self.root.metrics().newlines + 1
+    }
+    pub fn insert(&mut self, at: usize, text: &[u8]) {
+        assert!(at <= self.len(), "Insert out of bounds: {} > {}", at, self.len());
+        if text.is_empty() { return; }
+        if self.root.insert_in_place(at, text, Metrics::of(text)) { return; }
+        let root = std::mem::replace(&mut self.root, Node::leaf(Vec::new()));
+        let (left, right) = Node::split(*root, at);
+        self.root = Node::join(Node::join(left, Node::from_bytes(text)), right);
+    }
+    pub fn remove(&mut self, range: Range<usize>) {
+        assert!(range.start <= range.end && range.end <= self.len(), "Remove out of bounds: {:?} in {}", range, self.len());
+        if range.start == range.end { return; }
+        if self.root.remove_in_place(range.clone()) { return; }
+        let root = std::mem::replace(&mut self.root, Node::leaf(Vec::new()));
+        let (left, rest) = Node::split(*root, range.start);
+        let (_, right) = Node::split(*rest, range.end - range.start);
+        self.root = Node::join(left, right);
+    }
+    pub fn replace(&mut self, range: Range<usize>, text: &[u8]) {
+        let at = range.start;
+        self.remove(range);
+        self.insert(at, text);
+    }
+    pub fn byte(&self, at: usize) -> Option<u8> {
+        if at >= self.len() { return None; }
+        Some(self.root.byte(at))
+    }
+    // Byte offset of the first byte of `line`. `line == len_lines()` gives you the end
+    pub fn line_to_byte(&self, line: usize) -> usize {
+        if line >= self.len_lines() { return self.len(); }
+        self.root.line_to_byte(line)
+    }
+    // The line containing the byte at `at`
+    pub fn byte_to_line(&self, at: usize) -> usize {
+        self.root.byte_to_line(at.min(self.len()))
+    }
+    pub fn char_to_byte(&self, chr: usize) -> usize {
+        if chr >= self.len_chars() { return self.len(); }
+        self.root.char_to_byte(chr)
+    }
+    pub fn byte_to_char(&self, at: usize) -> usize {
+        self.root.byte_to_char(at.min(self.len()))
+    }
+    pub fn line(&self, i: usize) -> Line {
+        assert!(i < self.len_lines(), "Line out of bounds: {} >= {}", i, self.len_lines());
+        let at = self.line_to_byte(i);
+        let end = if i + 1 < self.len_lines() { self.line_to_byte(i + 1) - 1 } else { self.len() };
+        Line { at, len: end - at }
+    }
+    pub fn line_bytes(&self, i: usize) -> Vec<u8> {
+        let l = self.line(i);
+        self.slice(l.at..l.at + l.len)
+    }
+    pub fn slice(&self, range: Range<usize>) -> Vec<u8> {
+        let mut res = Vec::with_capacity(range.end.saturating_sub(range.start));
+        self.for_each_chunk(range, |c| res.extend_from_slice(c));
+        res
+    }
+    pub fn for_each_chunk<'a>(&'a self, range: Range<usize>, mut f: impl FnMut(&'a [u8])) {
+        let range = range.start.min(self.len())..range.end.min(self.len());
+        self.root.for_each_chunk(range, &mut f);
+    }
+    // First occurrence of `needle` starting at or after `from`. Walks the chunks directly, only
+    // keeping around enough of the previous chunk to catch matches that straddle two of them
+    pub fn find(&self, needle: &[u8], from: usize) -> Option<usize> {
+        if needle.is_empty() || from >= self.len() { return None; }
+        let mut window: Vec<u8> = Vec::new();
+        let mut window_at = from; // Offset of window[0] in the rope
+        let mut res = None;
+        self.for_each_chunk(from..self.len(), |chunk| {
+            if res.is_some() { return; }
+            window.extend_from_slice(chunk);
+            if let Some(i) = window.windows(needle.len()).position(|w| w == needle) {
+                res = Some(window_at + i);
+                return;
+            }
+            let keep = (needle.len() - 1).min(window.len());
+            let drop = window.len() - keep;
+            window.drain(..drop);
+            window_at += drop;
+        });
+        res
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.slice(0..self.len())
+    }
+}
+impl Default for Rope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, so the tests don't need a crate for random numbers and always run the same way
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n.max(1) as u64) as usize
+        }
+    }
+    const PIECES: [&str; 8] = ["a", "hello ", "\n", "é", "日本", "🦀", "line\r\n", "xyz\n\n"];
+    fn text(rng: &mut Rng, pieces: usize) -> Vec<u8> {
+        (0..pieces).flat_map(|_| PIECES[rng.below(PIECES.len())].bytes()).collect()
+    }
+    // Every tree invariant: cached metrics and heights are right, leaves aren't too big, the tree is balanced
+    fn check_node(node: &Node) -> (Metrics, usize) {
+        match node {
+            Node::Leaf { bytes, metrics } => {
+                assert!(bytes.len() <= MAX_LEAF, "Leaf of {} bytes", bytes.len());
+                assert_eq!(*metrics, Metrics::of(bytes));
+                (*metrics, 0)
+            }
+            Node::Branch { left, right, metrics, height } => {
+                let (lm, lh) = check_node(left);
+                let (rm, rh) = check_node(right);
+                assert_eq!(*metrics, lm + rm);
+                assert_eq!(*height, lh.max(rh) + 1);
+                assert!(lh.abs_diff(rh) <= 1, "Unbalanced: {} vs {}", lh, rh);
+                (*metrics, *height)
+            }
+        }
+    }
+    // What every lookup should give, worked out the slow way on the plain bytes
+    fn check(rope: &Rope, model: &[u8]) {
+        check_node(&rope.root);
+        assert_eq!(rope.to_bytes(), model);
+        assert_eq!(rope.len(), model.len());
+        let starts: Vec<usize> = std::iter::once(0).chain(model.iter().enumerate().filter(|(_, b)| **b == b'\n').map(|(i, _)| i + 1)).collect();
+        assert_eq!(rope.len_lines(), starts.len());
+        for (i, at) in starts.iter().enumerate().step_by(5) {
+            assert_eq!(rope.line_to_byte(i), *at);
+            let end = starts.get(i + 1).map(|s| s - 1).unwrap_or(model.len());
+            assert_eq!(rope.line(i), Line { at: *at, len: end - at });
+        }
+        assert_eq!(rope.line_to_byte(starts.len()), model.len());
+        let chars: Vec<usize> = model.iter().enumerate().filter(|(_, b)| !is_continuation(**b)).map(|(i, _)| i).collect();
+        assert_eq!(rope.len_chars(), chars.len());
+        for (c, at) in chars.iter().enumerate().step_by(11) {
+            assert_eq!(rope.char_to_byte(c), *at);
+            assert_eq!(rope.byte_to_char(*at), c);
+        }
+        assert_eq!(rope.char_to_byte(chars.len()), model.len());
+        let mut line = 0;
+        for (at, b) in model.iter().chain(std::iter::once(&0)).enumerate() {
+            if at % 13 == 0 { assert_eq!(rope.byte_to_line(at), line); }
+            if *b == b'\n' { line += 1; }
+        }
+    }
+    fn find(model: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+        if needle.is_empty() || from >= model.len() { return None; }
+        model[from..].windows(needle.len()).position(|w| w == needle).map(|i| i + from)
+    }
+
+    #[test]
+    fn random_edits_match_a_vec() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for round in 0..12 {
+            let mut model = text(&mut rng, 200 + round * 100);
+            let mut rope = Rope::from_bytes(&model);
+            check(&rope, &model);
+            for _ in 0..60 {
+                let at = rng.below(model.len() + 1);
+                match rng.below(3) {
+                    0 => {
+                        // Sometimes big enough to need leaves of its own
+                        let pieces = if rng.below(4) == 0 { 600 } else { 3 };
+                        let new = text(&mut rng, pieces);
+                        rope.insert(at, &new);
+                        model.splice(at..at, new);
+                    }
+                    1 => {
+                        let most = if rng.below(4) == 0 { 3000 } else { 20 };
+                        let end = (at + rng.below(most)).min(model.len());
+                        rope.remove(at..end);
+                        model.drain(at..end);
+                    }
+                    _ => {
+                        let end = (at + rng.below(50)).min(model.len());
+                        let pieces = rng.below(10);
+                        let new = text(&mut rng, pieces);
+                        rope.replace(at..end, &new);
+                        model.splice(at..end, new);
+                    }
+                }
+                check(&rope, &model);
+                let needle = PIECES[rng.below(PIECES.len())].as_bytes();
+                let from = rng.below(model.len() + 1);
+                assert_eq!(rope.find(needle, from), find(&model, needle, from));
+            }
+        }
+    }
+    #[test]
+    fn chars_across_leaves() {
+        // The crab starts 2 bytes before the end of the first leaf, so it's split in between two of them
+        let mut model = vec![b'a'; MAX_LEAF - 2];
+        model.extend_from_slice("🦀\nb🦀".as_bytes());
+        let mut rope = Rope::from_bytes(&model);
+        check(&rope, &model);
+        assert_eq!(rope.byte_to_char(MAX_LEAF + 2), MAX_LEAF - 1);
+        assert_eq!(rope.char_to_byte(MAX_LEAF - 1), MAX_LEAF + 2);
+        assert_eq!(rope.find("🦀".as_bytes(), 0), Some(MAX_LEAF - 2));
+        rope.insert(MAX_LEAF - 2, "é".as_bytes());
+        model.splice(MAX_LEAF - 2..MAX_LEAF - 2, "é".bytes());
+        check(&rope, &model);
+        rope.remove(MAX_LEAF - 2..MAX_LEAF + 4);
+        model.drain(MAX_LEAF - 2..MAX_LEAF + 4);
+        check(&rope, &model);
+    }
+    #[test]
+    fn big_files_stay_balanced() {
+        let mut rng = Rng(42);
+        let model = text(&mut rng, 20000);
+        let mut rope = Rope::from_bytes(&model);
+        let mut model = model;
+        // Lots of small edits in one place, then cutting out most of the middle
+        for i in 0..500 {
+            rope.insert(model.len() / 2, b"ab\n");
+            model.splice(model.len() / 2..model.len() / 2, b"ab\n".iter().copied());
+            if i % 100 == 0 { check(&rope, &model); }
+        }
+        let (from, to) = (model.len() / 10, model.len() * 9 / 10);
+        rope.remove(from..to);
+        model.drain(from..to);
+        check(&rope, &model);
+    }
+}
@@ -0,0 +1,194 @@
+// Find and replace. Searching goes through the buffer a line at a time, so a match can't span
+// more than one line but nothing ever has to hold the whole file at once
+use std::ops::Range;
+
+use regex::bytes::Regex;
+
+use crate::editor::{Cursor, Editor, Selection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool, // Otherwise the query is taken literally
+}
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub regex: Regex,
+    pub options: SearchOptions,
+}
+impl Search {
+    // Nothing to search for gives back None
+    pub fn new(query: &str, options: SearchOptions) -> Result<Option<Self>, String> {
+        if query.is_empty() { return Ok(None); }
+        let mut pattern = if options.regex { query.to_string() } else { regex::escape(query) };
+        if options.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        if !options.case_sensitive {
+            pattern = format!("(?i){}", pattern);
+        }
+        let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+        Ok(Some(Self { regex, options }))
+    }
+    // Bytes of the line without its line ending
+    fn line(e: &Editor, line: usize) -> Vec<u8> {
+        let mut bytes = e.buffer.line_bytes(line);
+        bytes.truncate(e.line_end(line));
+        bytes
+    }
+    // Matches in a line, in bytes from its start. Empty matches are skipped since there's nothing to show of them
+    pub fn line_matches(&self, line: &[u8]) -> Vec<Range<usize>> {
+        self.regex.find_iter(line).filter(|m| !m.is_empty()).map(|m| m.range()).collect()
+    }
+    // The first match starting at or after `from` (or the last one before it when going backwards),
+    // wrapping around the end of the buffer. In bytes from the start of the buffer
+    pub fn find(&self, e: &Editor, from: Cursor, forward: bool) -> Option<Range<usize>> {
+        let lines = e.buffer.len_lines();
+        let from = e.clamp(from);
+        // NOTE: The line the search starts on comes up twice, once for each side of `from`
+        for step in 0..=lines {
+            let line = if forward { (from.line + step) % lines } else { (from.line + lines - step % lines) % lines };
+            let matches = self.line_matches(&Self::line(e, line));
+            let at = e.buffer.line(line).at;
+            let found = match (forward, step) {
+                (true, 0) => matches.into_iter().find(|m| m.start >= from.chr),
+                (true, _) if step == lines => matches.into_iter().find(|m| m.start < from.chr),
+                (true, _) => matches.into_iter().next(),
+                (false, 0) => matches.into_iter().rev().find(|m| m.start < from.chr),
+                (false, _) if step == lines => matches.into_iter().rev().find(|m| m.start >= from.chr),
+                (false, _) => matches.into_iter().next_back(),
+            };
+            if let Some(m) = found {
+                return Some(at + m.start..at + m.end);
+            }
+        }
+        None
+    }
+    // What the match at `start` in `line` gets replaced with. Regexes can refer to their groups with $1
+    fn expand(&self, line: &[u8], start: usize, replacement: &str) -> Vec<u8> {
+        if !self.options.regex { return replacement.as_bytes().to_vec(); }
+        let mut res = Vec::new();
+        if let Some(caps) = self.regex.captures_at(line, start) {
+            caps.expand(replacement.as_bytes(), &mut res);
+        }
+        res
+    }
+    // Replaces the primary selection if it's exactly a match. Gives back whether it was
+    pub fn replace(&self, e: &mut Editor, replacement: &str) -> bool {
+        let sel = e.primary();
+        let range = e.range_of(&sel);
+        if sel.is_empty() || sel.start().line != sel.end().line { return false; }
+        let line = Self::line(e, sel.start().line);
+        let start = sel.start().chr;
+        let exact = self.line_matches(&line).into_iter().any(|m| m.start == start && m.end == sel.end().chr);
+        if !exact { return false; }
+        let text = self.expand(&line, start, replacement);
+        e.replace_range(range, &text);
+        true
+    }
+    // Replaces every match as a single undo step. Gives back how many there were
+    pub fn replace_all(&self, e: &mut Editor, replacement: &str) -> usize {
+        let mut count = 0;
+        e.history.begin();
+        // NOTE: Bottom up, so that replacements with line breaks in them don't move the lines still to go
+        for line in (0..e.buffer.len_lines()).rev() {
+            let bytes = Self::line(e, line);
+            // NOTE: The same matches find goes through, so the empty ones a regex can have are left alone
+            let matches = self.line_matches(&bytes);
+            if matches.is_empty() { continue; }
+            let mut replaced = Vec::with_capacity(bytes.len());
+            let mut last = 0;
+            for m in &matches {
+                replaced.extend_from_slice(&bytes[last..m.start]);
+                replaced.extend(self.expand(&bytes, m.start, replacement));
+                last = m.end;
+            }
+            replaced.extend_from_slice(&bytes[last..]);
+            let at = e.buffer.line(line).at;
+            e.replace_range(at..at + bytes.len(), &replaced);
+            count += matches.len();
+        }
+        e.history.end();
+        count
+    }
+}
+// Selects a match `find` gave back
+pub fn select(e: &mut Editor, m: Range<usize>) {
+    let sel = Selection { anchor: e.cursor_at(m.start), head: e.cursor_at(m.end) };
+    e.set_selections(vec![sel], 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(query: &str, options: SearchOptions) -> Search {
+        Search::new(query, options).unwrap().unwrap()
+    }
+    fn text(e: &Editor) -> String {
+        String::from_utf8(e.buffer.to_bytes()).unwrap()
+    }
+    fn at(line: usize, chr: usize) -> Cursor {
+        Cursor { line, chr }
+    }
+
+    #[test]
+    fn find_wraps_around() {
+        let e = Editor::from_bytes(b"foo bar foo\nbaz foo".to_vec());
+        let s = search("foo", SearchOptions::default());
+        assert_eq!(s.find(&e, at(0, 0), true), Some(0..3));
+        assert_eq!(s.find(&e, at(0, 1), true), Some(8..11));
+        assert_eq!(s.find(&e, at(1, 5), true), Some(0..3));
+        assert_eq!(s.find(&e, at(0, 4), false), Some(0..3));
+        assert_eq!(s.find(&e, at(0, 0), false), Some(16..19));
+        assert_eq!(s.find(&e, at(1, 0), false), Some(8..11));
+        // The only match, found from inside of itself
+        let e = Editor::from_bytes(b"one\nfoo\ntwo".to_vec());
+        assert_eq!(s.find(&e, at(1, 1), true), Some(4..7));
+        assert_eq!(s.find(&e, at(1, 0), false), Some(4..7));
+        assert_eq!(search("nope", SearchOptions::default()).find(&e, at(0, 0), true), None);
+    }
+    #[test]
+    fn options() {
+        let line = b"Foo foo foobar a.c abc";
+        let matches = |query: &str, options: SearchOptions| search(query, options).line_matches(line);
+        assert_eq!(matches("foo", SearchOptions::default()), vec![0..3, 4..7, 8..11]);
+        assert_eq!(matches("foo", SearchOptions { case_sensitive: true, ..Default::default() }), vec![4..7, 8..11]);
+        assert_eq!(matches("foo", SearchOptions { whole_word: true, ..Default::default() }), vec![0..3, 4..7]);
+        assert_eq!(matches("a.c", SearchOptions::default()), vec![15..18]);
+        assert_eq!(matches("a.c", SearchOptions { regex: true, ..Default::default() }), vec![15..18, 19..22]);
+        assert_eq!(matches("f\\w+r", SearchOptions { regex: true, ..Default::default() }), vec![8..14]);
+        assert!(Search::new("(", SearchOptions { regex: true, ..Default::default() }).is_err());
+        assert!(Search::new("", SearchOptions::default()).unwrap().is_none());
+    }
+    #[test]
+    fn replace_all_with_line_breaks() {
+        let mut e = Editor::from_bytes(b"a,b,c\nd,e\nf".to_vec());
+        let s = search(",", SearchOptions::default());
+        assert_eq!(s.replace_all(&mut e, "\n"), 3);
+        assert_eq!(text(&e), "a\nb\nc\nd\ne\nf");
+        e.undo();
+        assert_eq!(text(&e), "a,b,c\nd,e\nf");
+    }
+    #[test]
+    fn replace_all_skips_empty_matches() {
+        let regex = SearchOptions { regex: true, ..Default::default() };
+        let mut e = Editor::from_bytes(b"axbx\nyy".to_vec());
+        assert_eq!(search("x?", regex).replace_all(&mut e, "-"), 2);
+        assert_eq!(text(&e), "a-b-\nyy");
+        let mut e = Editor::from_bytes(b"baab\nb".to_vec());
+        assert_eq!(search("a*", regex).replace_all(&mut e, "-"), 1);
+        assert_eq!(text(&e), "b-b\nb");
+    }
+    #[test]
+    fn replace_all_expands_groups() {
+        let mut e = Editor::from_bytes(b"me@home you@work\na @ b".to_vec());
+        let regex = SearchOptions { regex: true, ..Default::default() };
+        assert_eq!(search(r"(\w+)@(\w+)", regex).replace_all(&mut e, "$2:$1"), 2);
+        assert_eq!(text(&e), "home:me work:you\na @ b");
+        // Taken literally without regex mode
+        assert_eq!(search("@", SearchOptions::default()).replace_all(&mut e, "$1"), 1);
+        assert_eq!(text(&e), "home:me work:you\na $1 b");
+    }
+}
@@ -0,0 +1,194 @@
+use beryllium::events::{SDL_Keycode, SDL_Keymod, KMOD_ALT, KMOD_CTRL, KMOD_SHIFT, SDLK_BACKSPACE, SDLK_ESCAPE, SDLK_KP_ENTER, SDLK_RETURN, SDLK_TAB, SDLK_c, SDLK_r, SDLK_w};
+
+use crate::editor::{Cursor, Editor};
+use crate::renderer::{Boundary, Renderer};
+use crate::search::{select, Search, SearchOptions};
+use crate::theme::Theme;
+use crate::{Font, Vector2f};
+
+// The find (and replace) bar at the bottom of the window. Ctrl+F opens it
+pub struct SearchBar {
+    pub open: bool,
+    pub replacing: bool, // Whether the replace field is shown
+    pub in_replace: bool, // Typing goes into the replace field instead of the query
+    pub query: String,
+    pub replacement: String,
+    pub preedit: String,
+    pub options: SearchOptions,
+    pub message: String,
+    origin: Cursor, // Where the search started, incremental searching looks from here
+}
+impl Default for SearchBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl SearchBar {
+    pub fn new() -> Self {
+        Self {
+            open: false, replacing: false, in_replace: false, query: String::new(), replacement: String::new(),
+            preedit: String::new(), options: SearchOptions::default(), message: String::new(), origin: Cursor::new(),
+        }
+    }
+    // Opens the bar, searching for the selection if there's one on a single line
+    pub fn show(&mut self, e: &mut Editor, replacing: bool) {
+        self.open = true;
+        self.replacing = replacing;
+        self.in_replace = false;
+        let sel = e.primary();
+        self.origin = sel.start();
+        if !sel.is_empty() && sel.start().line == sel.end().line {
+            self.query = String::from_utf8_lossy(&e.buffer.slice(e.range_of(&sel))).into_owned();
+            if self.options.regex {
+                self.query = regex::escape(&self.query);
+            }
+        }
+        self.update(e);
+    }
+    pub fn close(&mut self, e: &mut Editor) {
+        self.open = false;
+        self.preedit.clear();
+        e.search = None;
+    }
+    // The query or the options changed. Compiles them again and selects the first match from where the search started
+    pub fn update(&mut self, e: &mut Editor) {
+        self.message.clear();
+        e.search = match Search::new(&self.query, self.options) {
+            Ok(search) => search,
+            Err(err) => {
+                self.message = err.lines().last().unwrap_or("Invalid regex").trim().to_string();
+                None
+            }
+        };
+        let Some(search) = &e.search else { return; };
+        match search.find(e, self.origin, true) {
+            Some(m) => select(e, m),
+            None => {
+                self.message = "No matches".to_string();
+                let origin = self.origin;
+                e.move_to(origin, false);
+            }
+        }
+    }
+    // F3 and Shift+F3. Works with the bar closed too, with whatever was searched for last
+    pub fn next(&mut self, e: &mut Editor, forward: bool) {
+        if e.search.is_none() {
+            e.search = Search::new(&self.query, self.options).ok().flatten();
+        }
+        let Some(search) = &e.search else { return; };
+        let sel = e.primary();
+        let from = if forward { sel.end() } else { sel.start() };
+        match search.find(e, from, forward) {
+            Some(m) => {
+                select(e, m);
+                self.message.clear();
+            }
+            None => self.message = "No matches".to_string(),
+        }
+        self.origin = e.primary().start();
+        if !self.open {
+            // NOTE: Keep the matches highlighted only while the bar is there to say what they are
+            e.search = None;
+        }
+    }
+    pub fn replace(&mut self, e: &mut Editor) {
+        let Some(search) = e.search.clone() else { return; };
+        search.replace(e, &self.replacement);
+        self.next(e, true);
+    }
+    pub fn replace_all(&mut self, e: &mut Editor) {
+        let Some(search) = e.search.clone() else { return; };
+        let count = search.replace_all(e, &self.replacement);
+        self.message = format!("Replaced {}", count);
+    }
+    // Gives back whether the key was for the bar. The ones that aren't still go to the keymap
+    pub fn handle_key(&mut self, e: &mut Editor, keycode: SDL_Keycode, modifiers: SDL_Keymod) -> bool {
+        let ctrl = modifiers.0 & KMOD_CTRL.0 != 0;
+        let shift = modifiers.0 & KMOD_SHIFT.0 != 0;
+        let alt = modifiers.0 & KMOD_ALT.0 != 0;
+        match keycode {
+            SDLK_ESCAPE => self.close(e),
+            SDLK_RETURN | SDLK_KP_ENTER if self.in_replace && ctrl => self.replace_all(e),
+            SDLK_RETURN | SDLK_KP_ENTER if self.in_replace => self.replace(e),
+            SDLK_RETURN | SDLK_KP_ENTER => self.next(e, !shift),
+            SDLK_TAB if self.replacing => self.in_replace = !self.in_replace,
+            SDLK_BACKSPACE if !ctrl && !alt => {
+                if self.in_replace {
+                    self.replacement.pop();
+                } else {
+                    self.query.pop();
+                    self.update(e);
+                }
+            }
+            SDLK_c if alt => {
+                self.options.case_sensitive = !self.options.case_sensitive;
+                self.update(e);
+            }
+            SDLK_w if alt => {
+                self.options.whole_word = !self.options.whole_word;
+                self.update(e);
+            }
+            SDLK_r if alt => {
+                self.options.regex = !self.options.regex;
+                self.update(e);
+            }
+            _ => return false,
+        }
+        true
+    }
+    pub fn handle_text(&mut self, e: &mut Editor, text: &str) {
+        let text = text.chars().filter(|c| !c.is_control());
+        if self.in_replace {
+            self.replacement.extend(text);
+        } else {
+            self.query.extend(text);
+            self.update(e);
+        }
+    }
+    pub fn height(&self, font: &Font) -> f32 {
+        if !self.open { return 0.0; }
+        let row = (font.fontSize + 8) as f32;
+        if self.replacing { row * 2.0 } else { row }
+    }
+    pub fn display(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        if !self.open { return; }
+        let row = (font.fontSize + 8) as f32;
+        let width = |s: &str| font.measure_text(s, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+        r.draw_rect(theme.status, bound.pos, Vector2f(bound.size.0, self.height(font)));
+        let mut fields = vec![("Find: ", &self.query, !self.in_replace)];
+        if self.replacing {
+            fields.push(("Replace: ", &self.replacement, self.in_replace));
+        }
+        let labelW = fields.iter().map(|(label, _, _)| width(label)).fold(0.0, f32::max);
+        // NOTE: The first field goes on top
+        for (i, (label, text, focused)) in fields.iter().enumerate() {
+            let pos = bound.pos + Vector2f(4.0, 6.0 + row * (fields.len() - 1 - i) as f32);
+            let color = if *focused { theme.status_foreground } else { theme.gutter_foreground };
+            r.draw_str(font, label, pos, color);
+            let pos = pos + Vector2f(labelW, 0.0);
+            r.draw_str(font, text, pos, color);
+            if *focused {
+                let off = width(text);
+                if !self.preedit.is_empty() {
+                    r.draw_str(font, &self.preedit, pos + Vector2f(off, 0.0), color);
+                    r.draw_rect(color, pos + Vector2f(off, -3.0), Vector2f(width(&self.preedit), 1.0));
+                } else {
+                    r.draw_rect(theme.cursor, pos + Vector2f(off, -4.0), Vector2f(2.0, row - 4.0));
+                }
+            }
+        }
+        // The options and what happened, on the right of the query
+        let top = bound.pos + Vector2f(0.0, 6.0 + row * (fields.len() - 1) as f32);
+        let toggles = [("Aa", self.options.case_sensitive), ("W", self.options.whole_word), (".*", self.options.regex)];
+        let mut x = bound.pos.0 + bound.size.0 - 8.0;
+        for (name, on) in toggles.iter().rev() {
+            x -= width(name) + 12.0;
+            let color = if *on { theme.keyword } else { theme.gutter_foreground };
+            r.draw_str(font, name, Vector2f(x, top.1), color);
+        }
+        if !self.message.is_empty() {
+            x -= width(&self.message) + 24.0;
+            r.draw_str(font, &self.message, Vector2f(x, top.1), theme.status_foreground);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use beryllium::events::{SDL_Keycode, SDL_Keymod, KMOD_ALT, SDLK_BACKSPACE, SDLK_DOWN, SDLK_ESCAPE, SDLK_KP_ENTER, SDLK_PAGEDOWN, SDLK_PAGEUP, SDLK_RETURN, SDLK_UP, SDLK_c, SDLK_r, SDLK_w};
+
+use crate::editor::{Cursor, Editor};
+use crate::projectsearch::{FileHits, Hit, ProjectSearch};
+use crate::renderer::{Boundary, Renderer};
+use crate::search::{Search, SearchOptions};
+use crate::theme::Theme;
+use crate::{Font, Vector2f};
+
+// How long the query has to stay the same before the search starts, so typing doesn't start one per key
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+pub enum PanelAction {
+    Ignored, // The key wasn't for the panel
+    Handled,
+    Open(PathBuf, Cursor),
+}
+enum Row {
+    File(usize),
+    Hit(usize, usize, usize), // File, hit in it, and which hit it is overall
+}
+// The find in files panel, covering the editor while it's open. Ctrl+Shift+F opens it
+pub struct SearchPanel {
+    pub open: bool,
+    pub root: PathBuf,
+    pub query: String,
+    pub preedit: String,
+    pub options: SearchOptions,
+    pub results: Vec<FileHits>,
+    pub selected: usize, // Counts the hits of every file one after the other
+    pub message: String,
+    search: Option<ProjectSearch>,
+    pending: Option<(Search, Instant)>, // The search to start, and when the query last changed
+    scroll: Cell<usize>, // First row shown. NOTE: Drawing is what knows how many rows fit, so it keeps this up to date
+}
+impl SearchPanel {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            open: false, root, query: String::new(), preedit: String::new(), options: SearchOptions::default(),
+            results: Vec::new(), selected: 0, message: String::new(), search: None, pending: None, scroll: Cell::new(0),
+        }
+    }
+    // Opens the panel, searching for the selection if there's one on a single line
+    pub fn show(&mut self, e: &Editor) {
+        self.open = true;
+        let sel = e.primary();
+        if !sel.is_empty() && sel.start().line == sel.end().line {
+            self.query = String::from_utf8_lossy(&e.buffer.slice(e.range_of(&sel))).into_owned();
+            if self.options.regex {
+                self.query = regex::escape(&self.query);
+            }
+            self.update();
+        }
+    }
+    pub fn close(&mut self) {
+        self.open = false;
+        self.preedit.clear();
+        self.search = None;
+        self.pending = None;
+    }
+    // Starts over with the current query. The search that was going on gets cancelled, and the new one
+    // starts from poll once the query has settled
+    pub fn update(&mut self) {
+        self.results.clear();
+        self.selected = 0;
+        self.scroll.set(0);
+        self.message.clear();
+        self.search = None;
+        self.pending = match Search::new(&self.query, self.options) {
+            Ok(search) => search.map(|s| (s, Instant::now())),
+            Err(err) => {
+                self.message = err.lines().last().unwrap_or("Invalid regex").trim().to_string();
+                None
+            }
+        };
+    }
+    // Takes in whatever the search found since the last frame
+    pub fn poll(&mut self) {
+        if self.pending.as_ref().is_some_and(|(_, at)| at.elapsed() >= DEBOUNCE) {
+            let (search, _) = self.pending.take().unwrap();
+            self.search = Some(ProjectSearch::start(&self.root, search));
+        }
+        if let Some(search) = &mut self.search {
+            self.results.extend(search.poll());
+        }
+    }
+    fn hit_count(&self) -> usize {
+        self.results.iter().map(|f| f.hits.len()).sum()
+    }
+    fn hit(&self, mut index: usize) -> Option<(&FileHits, &Hit)> {
+        for file in self.results.iter() {
+            if index < file.hits.len() { return Some((file, &file.hits[index])); }
+            index -= file.hits.len();
+        }
+        None
+    }
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        let mut n = 0;
+        for (i, file) in self.results.iter().enumerate() {
+            rows.push(Row::File(i));
+            for j in 0..file.hits.len() {
+                rows.push(Row::Hit(i, j, n));
+                n += 1;
+            }
+        }
+        rows
+    }
+    pub fn handle_key(&mut self, keycode: SDL_Keycode, modifiers: SDL_Keymod) -> PanelAction {
+        let alt = modifiers.0 & KMOD_ALT.0 != 0;
+        let count = self.hit_count();
+        match keycode {
+            SDLK_ESCAPE => self.close(),
+            SDLK_RETURN | SDLK_KP_ENTER => {
+                let Some((file, hit)) = self.hit(self.selected) else { return PanelAction::Handled; };
+                return PanelAction::Open(file.path.clone(), Cursor { line: hit.line, chr: hit.chr });
+            }
+            SDLK_UP => self.selected = self.selected.saturating_sub(1),
+            SDLK_DOWN => self.selected = (self.selected + 1).min(count.saturating_sub(1)),
+            SDLK_PAGEUP => self.selected = self.selected.saturating_sub(10),
+            SDLK_PAGEDOWN => self.selected = (self.selected + 10).min(count.saturating_sub(1)),
+            SDLK_BACKSPACE if !alt => {
+                self.query.pop();
+                self.update();
+            }
+            SDLK_c if alt => {
+                self.options.case_sensitive = !self.options.case_sensitive;
+                self.update();
+            }
+            SDLK_w if alt => {
+                self.options.whole_word = !self.options.whole_word;
+                self.update();
+            }
+            SDLK_r if alt => {
+                self.options.regex = !self.options.regex;
+                self.update();
+            }
+            _ => return PanelAction::Ignored,
+        }
+        PanelAction::Handled
+    }
+    pub fn handle_text(&mut self, text: &str) {
+        self.query.extend(text.chars().filter(|c| !c.is_control()));
+        self.update();
+    }
+    pub fn display(&self, r: &mut Renderer, bound: &Boundary, font: &Font, theme: &Theme) {
+        if !self.open { return; }
+        let width = |s: &str| font.measure_text(s, font.fontSize as f32).map(|m| m.0).unwrap_or(0.0);
+        let rowH = (font.fontSize + 5) as f32;
+        r.scisorsBegin(bound);
+        r.draw_rect(theme.background, bound.pos, bound.size);
+        // The query on top
+        let top = bound.top_left();
+        r.draw_rect(theme.status, Vector2f(top.0, top.1 - rowH - 8.0), Vector2f(bound.size.0, rowH + 8.0));
+        let label = format!("Find in {}: ", self.root.display());
+        let pos = Vector2f(top.0 + 4.0, top.1 - rowH - 2.0);
+        r.draw_str(font, &label, pos, theme.status_foreground);
+        let pos = pos + Vector2f(width(&label), 0.0);
+        r.draw_str(font, &self.query, pos, theme.status_foreground);
+        let off = width(&self.query);
+        if self.preedit.is_empty() {
+            r.draw_rect(theme.cursor, pos + Vector2f(off, -4.0), Vector2f(2.0, rowH));
+        } else {
+            r.draw_str(font, &self.preedit, pos + Vector2f(off, 0.0), theme.status_foreground);
+            r.draw_rect(theme.status_foreground, pos + Vector2f(off, -3.0), Vector2f(width(&self.preedit), 1.0));
+        }
+        let toggles = [("Aa", self.options.case_sensitive), ("W", self.options.whole_word), (".*", self.options.regex)];
+        let mut x = bound.pos.0 + bound.size.0 - 8.0;
+        for (name, on) in toggles.iter().rev() {
+            x -= width(name) + 12.0;
+            r.draw_str(font, name, Vector2f(x, pos.1), if *on { theme.keyword } else { theme.gutter_foreground });
+        }
+        let status = if !self.message.is_empty() {
+            self.message.clone()
+        } else if let Some(search) = &self.search {
+            let files = self.results.len();
+            let mut status = format!("{} hits in {} files", self.hit_count(), files);
+            if search.truncated {
+                status.push_str(" (stopped, too many)");
+            } else if !search.done {
+                status.push_str(" ...");
+            }
+            status
+        } else if self.pending.is_some() {
+            "...".to_string()
+        } else {
+            String::new()
+        };
+        x -= width(&status) + 24.0;
+        r.draw_str(font, &status, Vector2f(x, pos.1), theme.status_foreground);
+
+        // The results below it, grouped by file
+        let rows = self.rows();
+        let visible = (((bound.size.1 - rowH - 8.0) / rowH).floor().max(1.0)) as usize;
+        let selectedRow = rows.iter().position(|row| matches!(row, Row::Hit(_, _, n) if *n == self.selected)).unwrap_or(0);
+        let mut scroll = self.scroll.get();
+        // NOTE: Keep the file the selected hit is in visible when it's the first one
+        if selectedRow <= scroll { scroll = selectedRow.saturating_sub(1); }
+        if selectedRow >= scroll + visible { scroll = selectedRow + 1 - visible; }
+        self.scroll.set(scroll);
+        let mut y = top.1 - rowH - 8.0;
+        for row in rows.iter().skip(scroll).take(visible) {
+            y -= rowH;
+            let pos = Vector2f(bound.pos.0 + 8.0, y + 4.0);
+            match row {
+                Row::File(i) => {
+                    let file = &self.results[*i];
+                    let path = file.path.strip_prefix(&self.root).unwrap_or(&file.path);
+                    r.draw_str(font, &format!("{} ({})", path.display(), file.hits.len()), pos, theme.keyword);
+                }
+                Row::Hit(i, j, n) => {
+                    let hit = &self.results[*i].hits[*j];
+                    if *n == self.selected {
+                        r.draw_rect(theme.selection, Vector2f(bound.pos.0, y), Vector2f(bound.size.0, rowH));
+                    }
+                    let number = format!("{:>6}  ", hit.line + 1);
+                    r.draw_str(font, &number, pos, theme.gutter_foreground);
+                    let mut x = pos.0 + width(&number);
+                    for (k, chr) in hit.preview.char_indices() {
+                        if x > bound.pos.0 + bound.size.0 { break; }
+                        let adv = font.get_char(chr).advance_x as f32;
+                        if hit.matches.iter().any(|m| m.contains(&k)) {
+                            r.draw_rect(theme.search_match, Vector2f(x, y), Vector2f(adv, rowH));
+                        }
+                        if chr != '\t' {
+                            r.draw_char(font, chr, Vector2f(x, pos.1), theme.foreground);
+                        }
+                        x += adv;
+                    }
+                }
+            }
+        }
+        r.scisorsEnd();
+    }
+}
@@ -0,0 +1,315 @@
+// Syntax definitions loaded from files, so that new languages don't need a recompile.
+// A definition looks like this:
+//
+//     # Comments start with '#'
+//     name GLSL
+//     extensions glsl vert frag
+//
+//     context main
+//         match /\/\/.*/ comment
+//         push /\/\*/ comment block_comment
+//         keywords keyword if else for while return
+//
+//     context block_comment comment
+//         pop /\*\// comment
+//
+// `context <name> [kind]` starts a context. The first one is where every file starts and `kind`
+// is what the text none of its rules match is (normal by default). Rules are tried on the rest of
+// the line and the one matching earliest wins, ties go to the one written first:
+// - `match /regex/ kind` colors what it matches
+// - `push /regex/ kind context` does the same and then enters `context`
+// - `pop /regex/ kind` does the same and then goes back to the context it came from
+// - `keywords kind word...` matches any of the words, as long as they're whole
+// The regex can be delimited by any character, '\' escapes it inside. Kinds are the TokenKind names:
+// normal, keyword, type, string, number, comment and preprocessor
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use regex::bytes::Regex;
+
+use crate::highlight::{LexState, Lexer, Span, TokenKind};
+
+enum Action {
+    Stay,
+    Push(u16),
+    Pop,
+}
+struct Rule {
+    regex: Regex,
+    kind: TokenKind,
+    action: Action,
+}
+struct Context {
+    kind: TokenKind,
+    rules: Vec<Rule>,
+}
+pub struct Syntax {
+    pub name: String,
+    pub extensions: Vec<String>,
+    contexts: Vec<Context>,
+}
+// Splits `/regex/ rest` into the regex and the rest
+fn delimited(s: &str) -> Result<(String, &str), String> {
+    let mut chars = s.char_indices();
+    let (_, delim) = chars.next().ok_or("Expected a regex")?;
+    let mut regex = String::new();
+    let mut escaped = false;
+    for (i, c) in chars {
+        if escaped {
+            if c != delim { regex.push('\\'); }
+            regex.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delim {
+            return Ok((regex, &s[i + c.len_utf8()..]));
+        } else {
+            regex.push(c);
+        }
+    }
+    Err(format!("Regex is missing its closing '{}'", delim))
+}
+fn kind(name: Option<&str>) -> Result<TokenKind, String> {
+    let name = name.ok_or("Expected a token kind")?;
+    TokenKind::from_name(name).ok_or_else(|| format!("Unknown token kind '{}'", name))
+}
+impl Syntax {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut name = None;
+        let mut extensions = Vec::new();
+        let mut names: Vec<String> = Vec::new();
+        let mut contexts: Vec<Context> = Vec::new();
+        // Contexts can be pushed before they're declared, so those get resolved at the end
+        let mut pushes: Vec<(usize, usize, String, usize)> = Vec::new();
+        for (n, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim_start();
+            let err = |e: String| format!("line {}: {}", n + 1, e);
+            match directive {
+                "name" => name = Some(rest.to_string()),
+                "extensions" => extensions.extend(rest.split_whitespace().map(|e| e.to_string())),
+                "context" => {
+                    let mut words = rest.split_whitespace();
+                    let cname = words.next().ok_or_else(|| err("Context needs a name".to_string()))?;
+                    let kind = match words.next() {
+                        Some(k) => kind(Some(k)).map_err(err)?,
+                        None => TokenKind::Normal,
+                    };
+                    names.push(cname.to_string());
+                    contexts.push(Context { kind, rules: Vec::new() });
+                }
+                "match" | "push" | "pop" | "keywords" => {
+                    let ci = contexts.len().wrapping_sub(1);
+                    let ctx = contexts.last_mut().ok_or_else(|| err("Rule outside of a context".to_string()))?;
+                    let (pattern, rest) = if directive == "keywords" {
+                        let mut words = rest.split_whitespace();
+                        let k = words.next().unwrap_or("");
+                        let alternatives: Vec<String> = words.map(regex::escape).collect();
+                        if alternatives.is_empty() { return Err(err("Keywords without any words".to_string())); }
+                        (format!(r"\b(?:{})\b", alternatives.join("|")), k)
+                    } else {
+                        delimited(rest).map_err(err)?
+                    };
+                    let mut words = rest.split_whitespace();
+                    let kind = kind(words.next()).map_err(err)?;
+                    let action = match directive {
+                        "push" => {
+                            let target = words.next().ok_or_else(|| err("Push needs a context".to_string()))?;
+                            pushes.push((ci, ctx.rules.len(), target.to_string(), n + 1));
+                            Action::Push(0)
+                        }
+                        "pop" => Action::Pop,
+                        _ => Action::Stay,
+                    };
+                    let regex = Regex::new(&pattern).map_err(|e| err(e.to_string()))?;
+                    ctx.rules.push(Rule { regex, kind, action });
+                }
+                _ => return Err(err(format!("Unknown directive '{}'", directive))),
+            }
+        }
+        for (ctx, rule, target, n) in pushes {
+            let id = names.iter().position(|c| *c == target).ok_or_else(|| format!("line {}: No context named '{}'", n, target))?;
+            contexts[ctx].rules[rule].action = Action::Push(id as u16);
+        }
+        if contexts.is_empty() { return Err("Syntax has no contexts".to_string()); }
+        let name = name.ok_or("Syntax has no name")?;
+        Ok(Self { name, extensions, contexts })
+    }
+}
+pub struct SyntaxLexer(pub Arc<Syntax>);
+impl Lexer for SyntaxLexer {
+    fn lex_line(&self, line: &[u8], state: LexState) -> (Vec<Span>, LexState) {
+        let contexts = &self.0.contexts;
+        let mut stack = match state {
+            LexState::Contexts(stack) => stack,
+            _ => vec![0],
+        };
+        let mut spans: Vec<Span> = Vec::new();
+        let mut push = |start: usize, end: usize, kind: TokenKind| {
+            if start >= end || kind == TokenKind::Normal { return; }
+            match spans.last_mut() {
+                Some(last) if last.end == start && last.kind == kind => last.end = end,
+                _ => spans.push(Span { start, end, kind }),
+            }
+        };
+        let mut i = 0;
+        // NOTE: Rules that match nothing can still push and pop, but only so many times in the same spot
+        let mut empty = 0;
+        loop {
+            let ctx = &contexts[*stack.last().unwrap() as usize];
+            let mut best: Option<(usize, usize, &Rule)> = None;
+            for rule in ctx.rules.iter() {
+                if let Some(m) = rule.regex.find_at(line, i) {
+                    if best.is_none_or(|(start, _, _)| m.start() < start) {
+                        best = Some((m.start(), m.end(), rule));
+                    }
+                }
+            }
+            let Some((start, end, rule)) = best else {
+                push(i, line.len(), ctx.kind);
+                break;
+            };
+            push(i, start, ctx.kind);
+            push(start, end, rule.kind);
+            match rule.action {
+                Action::Stay => {}
+                Action::Push(c) => stack.push(c),
+                Action::Pop => if stack.len() > 1 { stack.pop(); },
+            }
+            if end > i {
+                i = end;
+                empty = 0;
+                continue;
+            }
+            empty += 1;
+            if matches!(rule.action, Action::Stay) || empty > 8 {
+                // Stuck, so the next character is just text of whatever context we're in
+                if i >= line.len() { break; }
+                let mut next = i + 1;
+                while next < line.len() && crate::rope::is_continuation(line[next]) {
+                    next += 1;
+                }
+                push(i, next, contexts[*stack.last().unwrap() as usize].kind);
+                i = next;
+                empty = 0;
+            }
+        }
+        let state = if stack.len() == 1 && stack[0] == 0 { LexState::Normal } else { LexState::Contexts(stack) };
+        (spans, state)
+    }
+}
+static SYNTAXES: OnceLock<Vec<Arc<Syntax>>> = OnceLock::new();
+// Reads every *.syntax file in `dir`. The broken ones get reported and skipped
+pub fn load_dir(dir: &Path) -> Vec<Syntax> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new(); };
+    let mut syntaxes = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "syntax") { continue; }
+        match std::fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|src| Syntax::parse(&src)) {
+            Ok(syntax) => syntaxes.push(syntax),
+            Err(e) => eprintln!("[ERROR] Failed to load syntax {}: {}", path.display(), e),
+        }
+    }
+    syntaxes
+}
+// Makes the syntaxes available to lexer_for. Only the first call does anything
+pub fn register(syntaxes: Vec<Syntax>) {
+    let _ = SYNTAXES.set(syntaxes.into_iter().map(Arc::new).collect());
+}
+pub fn lexer_for(path: &Path) -> Option<Box<dyn Lexer>> {
+    let ext = path.extension()?.to_str()?;
+    let syntax = SYNTAXES.get()?.iter().find(|s| s.extensions.iter().any(|e| e == ext))?;
+    Some(Box::new(SyntaxLexer(syntax.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    type Line = &'static [(usize, usize, TokenKind)];
+    // Lexes every line of `sample` and checks the spans each one got
+    fn check(syntax: &str, sample: &str, expected: &[Line]) {
+        let lexer = SyntaxLexer(Arc::new(Syntax::parse(syntax).unwrap()));
+        let lines: Vec<&str> = sample.lines().collect();
+        assert_eq!(lines.len(), expected.len());
+        let mut state = LexState::Normal;
+        for (n, (line, expected)) in lines.iter().zip(expected).enumerate() {
+            let (spans, next) = lexer.lex_line(line.as_bytes(), state);
+            let spans: Vec<(usize, usize, TokenKind)> = spans.iter().map(|s| (s.start, s.end, s.kind)).collect();
+            assert_eq!(spans, *expected, "line {}: {:?}", n + 1, line);
+            state = next;
+        }
+        assert_eq!(state, LexState::Normal);
+    }
+
+    #[test]
+    fn toml() {
+        check(include_str!("../../syntax/toml.syntax"), include_str!("../../syntax/samples/sample.toml"), &[
+            &[(0, 10, Comment)],
+            &[(0, 8, Type)],
+            &[(0, 4, Keyword), (7, 22, String)],
+            &[(0, 5, Keyword), (9, 13, Number), (15, 19, Number)],
+            &[(0, 11, Keyword), (14, 17, String)],
+            &[(0, 13, String)],
+            &[(0, 3, String)],
+            &[(0, 7, Keyword), (10, 14, Keyword)],
+            &[(0, 8, Keyword), (11, 21, Number)],
+            // A basic string ends with the line even without its quote
+            &[(0, 3, Keyword), (6, 11, String)],
+        ]);
+    }
+    #[test]
+    fn python() {
+        check(include_str!("../../syntax/python.syntax"), include_str!("../../syntax/samples/sample.py"), &[
+            &[(0, 10, Preprocessor)],
+            &[(0, 3, Keyword), (16, 19, Type), (24, 28, Keyword)],
+            &[(4, 16, String)],
+            &[(0, 16, String)],
+            &[(4, 10, Keyword), (11, 23, String), (26, 29, String), (30, 36, Comment)],
+            &[(4, 8, Number), (11, 18, Number)],
+            &[(4, 8, String)],
+            &[(0, 4, String)],
+            &[],
+        ]);
+    }
+    #[test]
+    fn glsl() {
+        check(include_str!("../../syntax/glsl.syntax"), include_str!("../../syntax/samples/sample.glsl"), &[
+            &[(0, 8, Preprocessor), (9, 12, Number)],
+            &[(0, 7, Keyword), (8, 17, Type), (23, 33, Comment)],
+            &[(0, 21, Comment), (22, 24, Keyword), (25, 29, Type)],
+            &[(0, 4, Type)],
+            &[(4, 16, Type), (38, 42, Number), (44, 51, Comment)],
+            &[],
+        ]);
+    }
+    #[test]
+    fn states_across_lines() {
+        let syntax = Syntax::parse(include_str!("../../syntax/toml.syntax")).unwrap();
+        let multiline = syntax.contexts.len() - 2;
+        let lexer = SyntaxLexer(Arc::new(syntax));
+        let (_, state) = lexer.lex_line(b"a = \"\"\"", LexState::Normal);
+        assert_eq!(state, LexState::Contexts(vec![0, multiline as u16]));
+        let (_, state) = lexer.lex_line(b"still \"\" going", state);
+        assert_eq!(state, LexState::Contexts(vec![0, multiline as u16]));
+        let (_, state) = lexer.lex_line(b"done\"\"\" = 1", state);
+        assert_eq!(state, LexState::Normal);
+    }
+    #[test]
+    fn empty_matches_dont_get_stuck() {
+        // `y*` matches nothing everywhere, so it always comes first
+        let lexer = SyntaxLexer(Arc::new(Syntax::parse("name Stuck\ncontext main\n    match /x+/ keyword\n    match /y*/ keyword\n").unwrap()));
+        let (spans, state) = lexer.lex_line(b"axxb", LexState::Normal);
+        assert_eq!(spans, vec![Span { start: 1, end: 3, kind: Keyword }]);
+        assert_eq!(state, LexState::Normal);
+        // Pushing and popping in the same spot over and over
+        let src = "name Loop\ncontext main\n    push /\\b/ normal other\ncontext other comment\n    pop /\\b/ normal\n";
+        let lexer = SyntaxLexer(Arc::new(Syntax::parse(src).unwrap()));
+        let (spans, _) = lexer.lex_line("ab cd é".as_bytes(), LexState::Normal);
+        assert!(spans.iter().all(|s| s.start < s.end && s.end <= "ab cd é".len()), "{:?}", spans);
+    }
+}
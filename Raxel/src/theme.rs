@@ -0,0 +1,248 @@
+// Colors of everything the editor draws. A theme file is a list of `name = "color"` lines in
+// TOML style, where the color is anything Color::from_hex_str takes:
+//
+//     # Start from one of the built in themes, "dark" unless said otherwise
+//     base = "light"
+//     background = "#fdf6e3"
+//     keyword = "#859900"
+//
+// `[sections]` may be used to group things, but they don't change what the names mean.
+// Every name not in the file keeps the color of the base theme
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::highlight::TokenKind;
+use crate::Color;
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub background: Color,
+    pub foreground: Color,
+    pub cursor: Color,
+    pub selection: Color,
+    pub search_match: Color,
+    pub gutter: Color,
+    pub gutter_foreground: Color,
+    pub current_line: Color, // Background of the line numbers that have a cursor on them
+    pub current_line_foreground: Color,
+    pub bookmark: Color, // The marker in the gutter
+    pub status: Color,
+    pub status_foreground: Color,
+    pub warning: Color, // Things in the status bar to look out for
+    pub keyword: Color,
+    pub types: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub preprocessor: Color,
+}
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            background: Color::from_hex(0x212121ff),
+            foreground: Color::WHITE(),
+            cursor: Color::WHITE(),
+            selection: Color::from_hex(0x264f78ff),
+            search_match: Color::from_hex(0x623315ff),
+            gutter: Color::from_hex(0x1c1c1cff),
+            gutter_foreground: Color::from_hex(0x6e7681ff),
+            current_line: Color::from_hex(0x2a2a2aff),
+            current_line_foreground: Color::from_hex(0xc6c6c6ff),
+            bookmark: Color::from_hex(0x3794ffff),
+            status: Color::from_hex(0x303030ff),
+            status_foreground: Color::WHITE(),
+            warning: Color::from_hex(0xcca700ff),
+            keyword: Color::from_hex(0x569cd6ff),
+            types: Color::from_hex(0x4ec9b0ff),
+            string: Color::from_hex(0xce9178ff),
+            number: Color::from_hex(0xb5cea8ff),
+            comment: Color::from_hex(0x6a9955ff),
+            preprocessor: Color::from_hex(0xc586c0ff),
+        }
+    }
+    pub fn light() -> Self {
+        Self {
+            background: Color::from_hex(0xffffffff),
+            foreground: Color::from_hex(0x1f1f1fff),
+            cursor: Color::from_hex(0x000000ff),
+            selection: Color::from_hex(0xadd6ffff),
+            search_match: Color::from_hex(0xf8c9abff),
+            gutter: Color::from_hex(0xf3f3f3ff),
+            gutter_foreground: Color::from_hex(0x8a8a8aff),
+            current_line: Color::from_hex(0xe4e4e4ff),
+            current_line_foreground: Color::from_hex(0x1f1f1fff),
+            bookmark: Color::from_hex(0x005fb8ff),
+            status: Color::from_hex(0xddddddff),
+            status_foreground: Color::from_hex(0x1f1f1fff),
+            warning: Color::from_hex(0x895503ff),
+            keyword: Color::from_hex(0x0000ffff),
+            types: Color::from_hex(0x267f99ff),
+            string: Color::from_hex(0xa31515ff),
+            number: Color::from_hex(0x098658ff),
+            comment: Color::from_hex(0x008000ff),
+            preprocessor: Color::from_hex(0xaf00dbff),
+        }
+    }
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+    pub fn token(&self, kind: TokenKind) -> Color {
+        match kind {
+            TokenKind::Normal       => self.foreground,
+            TokenKind::Keyword      => self.keyword,
+            TokenKind::Type         => self.types,
+            TokenKind::String       => self.string,
+            TokenKind::Number       => self.number,
+            TokenKind::Comment      => self.comment,
+            TokenKind::Preprocessor => self.preprocessor,
+        }
+    }
+    fn slot(&mut self, name: &str) -> Option<&mut Color> {
+        Some(match name {
+            "background"              => &mut self.background,
+            "foreground"              => &mut self.foreground,
+            "cursor"                  => &mut self.cursor,
+            "selection"               => &mut self.selection,
+            "search_match"            => &mut self.search_match,
+            "gutter"                  => &mut self.gutter,
+            "gutter_foreground"       => &mut self.gutter_foreground,
+            "current_line"            => &mut self.current_line,
+            "current_line_foreground" => &mut self.current_line_foreground,
+            "bookmark"                => &mut self.bookmark,
+            "status"                  => &mut self.status,
+            "status_foreground"       => &mut self.status_foreground,
+            "warning"                 => &mut self.warning,
+            // NOTE: The token kinds go by the same names as in the syntax files
+            _ => match TokenKind::from_name(name)? {
+                TokenKind::Normal       => &mut self.foreground,
+                TokenKind::Keyword      => &mut self.keyword,
+                TokenKind::Type         => &mut self.types,
+                TokenKind::String       => &mut self.string,
+                TokenKind::Number       => &mut self.number,
+                TokenKind::Comment      => &mut self.comment,
+                TokenKind::Preprocessor => &mut self.preprocessor,
+            },
+        })
+    }
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        let mut base = "dark".to_string();
+        for (n, line) in src.lines().enumerate() {
+            let line = strip_comment(line);
+            let line = line.trim();
+            if line.is_empty() || (line.starts_with('[') && line.ends_with(']')) { continue; }
+            let (key, value) = line.split_once('=').ok_or_else(|| format!("line {}: Expected `name = \"color\"`", n + 1))?;
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+            if key.trim() == "base" {
+                base = value.to_string();
+            } else {
+                entries.push((n + 1, key.trim().to_string(), value.to_string()));
+            }
+        }
+        let mut theme = Self::builtin(&base).ok_or_else(|| format!("No built in theme called '{}'", base))?;
+        for (n, key, value) in entries {
+            let slot = theme.slot(&key).ok_or_else(|| format!("line {}: Unknown name '{}'", n, key))?;
+            *slot = Color::from_hex_str(&value).ok_or_else(|| format!("line {}: '{}' is not a color", n, value))?;
+        }
+        Ok(theme)
+    }
+}
+// NOTE: Colors start with '#' too, so only a '#' outside of quotes starts a comment
+pub fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+// A theme file that gets loaded again whenever it changes on disk
+pub struct ThemeFile {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Option<Instant>,
+}
+impl ThemeFile {
+    const CHECK_EVERY: Duration = Duration::from_millis(500);
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, modified: None, checked: None }
+    }
+    // Gives back the theme if the file changed since the last time. A file that doesn't parse gets
+    // reported and the current theme stays
+    pub fn poll(&mut self) -> Option<Theme> {
+        if self.checked.is_some_and(|c| c.elapsed() < Self::CHECK_EVERY) { return None; }
+        self.checked = Some(Instant::now());
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if self.modified == Some(modified) { return None; }
+        self.modified = Some(modified);
+        let src = match std::fs::read_to_string(&self.path) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("[ERROR] Failed to read theme {}: {}", self.path.display(), e);
+                return None;
+            }
+        };
+        match Theme::parse(&src) {
+            Ok(theme) => Some(theme),
+            Err(e) => {
+                eprintln!("[ERROR] Failed to load theme {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(c: Color) -> (f32, f32, f32, f32) {
+        (c.r, c.g, c.b, c.a)
+    }
+
+    #[test]
+    fn base_theme() {
+        let theme = Theme::parse("base = \"light\"\nkeyword = \"#ff0000\"").unwrap();
+        assert_eq!(rgba(theme.keyword), (1.0, 0.0, 0.0, 1.0));
+        assert_eq!(rgba(theme.background), rgba(Theme::light().background));
+        assert_eq!(rgba(theme.comment), rgba(Theme::light().comment));
+        // Dark unless said otherwise, wherever base is in the file
+        let theme = Theme::parse("string = \"0x00ff0080\"").unwrap();
+        assert_eq!(rgba(theme.background), rgba(Theme::dark().background));
+        assert_eq!(rgba(theme.string), (0.0, 1.0, 0.0, 128.0 / 255.0));
+        let theme = Theme::parse("[colors]\nbackground = \"#000000\"\nbase = \"light\"").unwrap();
+        assert_eq!(rgba(theme.background), (0.0, 0.0, 0.0, 1.0));
+        assert_eq!(rgba(theme.foreground), rgba(Theme::light().foreground));
+        assert!(Theme::parse("base = \"solarized\"").is_err());
+    }
+    #[test]
+    fn unknown_names() {
+        let err = Theme::parse("background = \"#000000\"\nkeywords = \"#000000\"").unwrap_err();
+        assert!(err.starts_with("line 2:") && err.contains("keywords"), "{}", err);
+        assert!(Theme::parse("preprocessor = \"#000000\"\ntype = \"#000000\"").is_ok());
+        assert!(Theme::parse("just a line").unwrap_err().starts_with("line 1:"));
+    }
+    #[test]
+    fn malformed_colors() {
+        for color in ["#12345", "#1234567", "#gggggg", "#+fffff", "red", "", "#"] {
+            let err = Theme::parse(&format!("cursor = \"{}\"", color)).unwrap_err();
+            assert!(err.contains("is not a color"), "{}: {}", color, err);
+        }
+    }
+    #[test]
+    fn comments() {
+        assert_eq!(strip_comment("cursor = \"#ffffff\" # white"), "cursor = \"#ffffff\" ");
+        assert_eq!(strip_comment("# cursor = \"#ffffff\""), "");
+        assert_eq!(strip_comment("\"Ctrl+#\" = \"a\""), "\"Ctrl+#\" = \"a\"");
+        let theme = Theme::parse("# A comment\ncursor = \"#ff0000\" # red\n").unwrap();
+        assert_eq!(rgba(theme.cursor), (1.0, 0.0, 0.0, 1.0));
+    }
+}
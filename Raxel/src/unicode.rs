@@ -0,0 +1,184 @@
+// The three ways of counting along a line, and going from one to the other:
+//  - bytes. That's what cursors hold (Cursor::chr) and what the rope works in
+//  - grapheme clusters, what reads as one character: an 'e' with an accent on it, a flag, an emoji
+//    with a skin tone. The cursor moves over and deletes whole ones and never stops inside of one
+//  - columns on screen. A tab goes up to the next tab stop and wide characters like CJK take two
+// Bytes that aren't UTF-8 are clusters of their own, four columns wide to be shown as \xNN
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster<'a> {
+    pub range: Range<usize>, // Bytes of the line
+    pub text: &'a str, // Empty for a byte that isn't UTF-8
+    pub col: usize, // The column it starts at
+    pub width: usize, // In columns, never 0 so that the cursor can always be seen next to it
+}
+// How many columns `c` takes up. Control characters and the like still get one
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(1).max(1)
+}
+// Chars that are there to change how the ones around them look and show nothing themselves: joiners,
+// variation selectors and tags
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200C}' | '\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{E0020}'..='\u{E007F}' | '\u{E0100}'..='\u{E01EF}')
+}
+// What to draw for a cluster: each char that shows, with the column it goes at, and how many columns
+// that adds up to. Marks go on top of the char before them. The font can't join things like emoji
+// sequences into one, so those come out one after the other and take more columns than the cluster has
+pub fn glyphs(text: &str) -> (Vec<(char, usize)>, usize) {
+    let mut out = Vec::new();
+    let mut col = 0;
+    let mut last = 0;
+    for c in text.chars().filter(|c| *c != '\t' && !is_invisible(*c)) {
+        match c.width() {
+            Some(0) => out.push((c, last)),
+            w => {
+                out.push((c, col));
+                last = col;
+                col += w.unwrap_or(1);
+            }
+        }
+    }
+    (out, col)
+}
+// Splits `line` into clusters and finds the column of each one. `line` shouldn't have its line ending in it
+pub fn layout(line: &[u8], tab_width: usize) -> Vec<Cluster<'_>> {
+    let mut out = Vec::new();
+    let mut at = 0;
+    let mut col = 0;
+    for chunk in line.utf8_chunks() {
+        for (i, g) in chunk.valid().grapheme_indices(true) {
+            let width = if g == "\t" { tab_width - col % tab_width } else { g.width().max(1) };
+            out.push(Cluster { range: at + i..at + i + g.len(), text: g, col, width });
+            col += width;
+        }
+        at += chunk.valid().len();
+        for _ in chunk.invalid() {
+            out.push(Cluster { range: at..at + 1, text: "", col, width: 4 });
+            at += 1;
+            col += 4;
+        }
+    }
+    out
+}
+// The column of the cluster `byte` is in. Past the end is the column right after the last one
+pub fn byte_to_column(line: &[u8], byte: usize, tab_width: usize) -> usize {
+    let clusters = layout(line, tab_width);
+    match clusters.iter().find(|c| c.range.end > byte) {
+        Some(c) => c.col,
+        None => clusters.last().map(|c| c.col + c.width).unwrap_or(0),
+    }
+}
+// Where the cluster covering `col` starts. A column past the end of the line is the end of the line
+pub fn column_to_byte(line: &[u8], col: usize, tab_width: usize) -> usize {
+    let clusters = layout(line, tab_width);
+    clusters.iter().find(|c| c.col + c.width > col).map(|c| c.range.start).unwrap_or(line.len())
+}
+// The start of the cluster `byte` is in
+pub fn floor_boundary(line: &[u8], byte: usize) -> usize {
+    if byte >= line.len() { return line.len(); }
+    layout(line, 1).into_iter().find(|c| c.range.end > byte).map(|c| c.range.start).unwrap_or(0)
+}
+// The boundary after the one at (or before) `byte`
+pub fn next_boundary(line: &[u8], byte: usize) -> usize {
+    layout(line, 1).into_iter().find(|c| c.range.end > byte).map(|c| c.range.end).unwrap_or(line.len())
+}
+// The boundary before `byte`
+pub fn prev_boundary(line: &[u8], byte: usize) -> usize {
+    layout(line, 1).into_iter().rev().find(|c| c.range.start < byte).map(|c| c.range.start).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each cluster as (bytes, text, col, width)
+    fn clusters(line: &[u8], tab_width: usize) -> Vec<(Range<usize>, &str, usize, usize)> {
+        layout(line, tab_width).into_iter().map(|c| (c.range, c.text, c.col, c.width)).collect()
+    }
+
+    #[test]
+    fn combining_marks() {
+        let line = "e\u{301}x\u{301}\u{323}!".as_bytes();
+        assert_eq!(clusters(line, 4), vec![(0..3, "e\u{301}", 0, 1), (3..8, "x\u{301}\u{323}", 1, 1), (8..9, "!", 2, 1)]);
+        assert_eq!(byte_to_column(line, 1, 4), 0);
+        assert_eq!(byte_to_column(line, 5, 4), 1);
+        assert_eq!(byte_to_column(line, 9, 4), 3);
+        assert_eq!(column_to_byte(line, 1, 4), 3);
+        assert_eq!(column_to_byte(line, 9, 4), 9);
+        assert_eq!(floor_boundary(line, 2), 0);
+        assert_eq!(floor_boundary(line, 7), 3);
+        assert_eq!(next_boundary(line, 0), 3);
+        assert_eq!(next_boundary(line, 4), 8);
+        assert_eq!(prev_boundary(line, 8), 3);
+        assert_eq!(prev_boundary(line, 5), 3);
+        assert_eq!(prev_boundary(line, 3), 0);
+    }
+    #[test]
+    fn emoji_sequences() {
+        // A family joined with ZWJs, a thumbs up with a skin tone and a flag
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let line = format!("{}\u{1F44D}\u{1F3FD}\u{1F1EF}\u{1F1F5}a", family);
+        let line = line.as_bytes();
+        let got = clusters(line, 4);
+        assert_eq!(got.iter().map(|c| (c.0.clone(), c.2, c.3)).collect::<Vec<_>>(), vec![(0..18, 0, 2), (18..26, 2, 2), (26..34, 4, 2), (34..35, 6, 1)]);
+        assert_eq!(next_boundary(line, 0), 18);
+        assert_eq!(next_boundary(line, 5), 18);
+        assert_eq!(prev_boundary(line, 26), 18);
+        assert_eq!(floor_boundary(line, 30), 26);
+        assert_eq!(byte_to_column(line, 20, 4), 2);
+        assert_eq!(column_to_byte(line, 3, 4), 18);
+        assert_eq!(column_to_byte(line, 6, 4), 34);
+    }
+    #[test]
+    fn wide_characters() {
+        let line = "a日本b".as_bytes();
+        assert_eq!(clusters(line, 4), vec![(0..1, "a", 0, 1), (1..4, "日", 1, 2), (4..7, "本", 3, 2), (7..8, "b", 5, 1)]);
+        // NOTE: Both columns of a wide character are that character
+        assert_eq!(column_to_byte(line, 1, 4), 1);
+        assert_eq!(column_to_byte(line, 2, 4), 1);
+        assert_eq!(column_to_byte(line, 3, 4), 4);
+        assert_eq!(byte_to_column(line, 4, 4), 3);
+        assert_eq!(byte_to_column(line, 8, 4), 6);
+        assert_eq!(floor_boundary(line, 5), 4);
+        assert_eq!(prev_boundary(line, 7), 4);
+    }
+    #[test]
+    fn tabs() {
+        let line = b"\ta\tbc\t";
+        let cols = |tab_width| layout(line, tab_width).into_iter().map(|c| (c.col, c.width)).collect::<Vec<_>>();
+        assert_eq!(cols(4), vec![(0, 4), (4, 1), (5, 3), (8, 1), (9, 1), (10, 2)]);
+        assert_eq!(cols(8), vec![(0, 8), (8, 1), (9, 7), (16, 1), (17, 1), (18, 6)]);
+        assert_eq!(cols(1), vec![(0, 1), (1, 1), (2, 1), (3, 1), (4, 1), (5, 1)]);
+        assert_eq!(column_to_byte(line, 3, 4), 0);
+        assert_eq!(column_to_byte(line, 7, 4), 2);
+        assert_eq!(column_to_byte(line, 7, 8), 0);
+        assert_eq!(byte_to_column(line, 3, 8), 16);
+        assert_eq!(byte_to_column(line, 6, 8), 24);
+    }
+    #[test]
+    fn invalid_bytes() {
+        let line = b"a\xFF\xC3b\xE6\x97\xA5";
+        assert_eq!(clusters(line, 4), vec![(0..1, "a", 0, 1), (1..2, "", 1, 4), (2..3, "", 5, 4), (3..4, "b", 9, 1), (4..7, "日", 10, 2)]);
+        assert_eq!(next_boundary(line, 1), 2);
+        assert_eq!(prev_boundary(line, 3), 2);
+        assert_eq!(floor_boundary(line, 2), 2);
+        assert_eq!(column_to_byte(line, 7, 4), 2);
+        assert_eq!(byte_to_column(line, 3, 4), 9);
+        // A multibyte char cut short is broken bytes too, and the cursor can stop between them
+        let line = b"\xE6\x97x";
+        assert_eq!(clusters(line, 4), vec![(0..1, "", 0, 4), (1..2, "", 4, 4), (2..3, "x", 8, 1)]);
+    }
+    #[test]
+    fn what_gets_drawn() {
+        assert_eq!(glyphs("a"), (vec![('a', 0)], 1));
+        assert_eq!(glyphs("\t"), (vec![], 0));
+        assert_eq!(glyphs("x\u{301}\u{323}"), (vec![('x', 0), ('\u{301}', 0), ('\u{323}', 0)], 1));
+        assert_eq!(glyphs("日\u{301}"), (vec![('日', 0), ('\u{301}', 0)], 2));
+        assert_eq!(glyphs("\u{1F468}\u{200D}\u{1F469}"), (vec![('\u{1F468}', 0), ('\u{1F469}', 2)], 4));
+        assert_eq!(glyphs("\u{2764}\u{FE0F}"), (vec![('\u{2764}', 0)], 1));
+    }
+}
@@ -0,0 +1,1089 @@
+// Vim style modal editing on top of an Editor. It knows nothing about SDL, main turns events
+// into Keys and feeds them to handle, which is also how to drive it without a window:
+//
+//     let mut vim = Vim::new();
+//     for c in "dwP".chars() {
+//         vim.handle(&mut editor, Key::Char(c));
+//     }
+//
+// Normal mode understands `["x][count][operator][count]motion`, where the operators are d c y and
+// the motions h j k l w b e 0 ^ $ gg G f t F T ; , and %. Doubling an operator (dd, 3yy) works
+// on whole lines. Anything the ex command line (`:`) takes goes through execute_ex
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use regex::bytes::Regex;
+
+use crate::clipboard;
+use crate::editor::{Cursor, Editor, Selection};
+use crate::endings;
+use crate::panes::Direction;
+use crate::rope;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char), // NOTE: Always lowercase
+    Esc,
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+}
+pub enum Action {
+    None,
+    Cmdline, // `:` was pressed, main should ask for an ex command and give it to execute_ex
+}
+pub enum ExResult {
+    Done,
+    Quit, // Closes the buffer, and the editor along with the last one
+    QuitAll { force: bool },
+    Edit(PathBuf), // Open a file
+    Cycle(bool), // Next buffer, or the previous one with false
+    Split(Direction),
+    Error(String),
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordStart,
+    WordBack,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FileStart,
+    FileEnd,
+    Find { target: char, till: bool, back: bool },
+    Match,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+impl Motion {
+    fn kind(self) -> Kind {
+        match self {
+            Motion::Up | Motion::Down | Motion::FileStart | Motion::FileEnd => Kind::Linewise,
+            Motion::WordEnd | Motion::LineEnd | Motion::Match => Kind::Inclusive,
+            Motion::Find { back: false, .. } => Kind::Inclusive,
+            _ => Kind::Exclusive,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub text: Vec<u8>,
+    pub linewise: bool,
+}
+
+fn class(c: char) -> u8 {
+    if c.is_whitespace() { 0 } else if c.is_alphanumeric() || c == '_' { 2 } else { 1 }
+}
+fn char_at(e: &Editor, at: usize) -> char {
+    let Some(b) = e.buffer.byte(at) else { return '\n'; };
+    if b < 0x80 { return b as char; }
+    let len = if b >= 0xF0 { 4 } else if b >= 0xE0 { 3 } else { 2 };
+    let bytes = e.buffer.slice(at..(at + len).min(e.buffer.len()));
+    std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+fn next_at(e: &Editor, at: usize) -> usize {
+    let mut at = at + 1;
+    while at < e.buffer.len() && rope::is_continuation(e.buffer.byte(at).unwrap()) {
+        at += 1;
+    }
+    at.min(e.buffer.len())
+}
+fn prev_at(e: &Editor, at: usize) -> usize {
+    if at == 0 { return 0; }
+    let mut at = at - 1;
+    while at > 0 && rope::is_continuation(e.buffer.byte(at).unwrap()) {
+        at -= 1;
+    }
+    at
+}
+// Empty lines count as words of their own
+fn is_empty_line(e: &Editor, at: usize) -> bool {
+    let c = e.cursor_at(at);
+    c.chr == 0 && e.line_end(c.line) == 0
+}
+fn first_non_blank(e: &Editor, line: usize) -> Cursor {
+    let at = e.buffer.line(line).at;
+    let end = e.line_end(line);
+    let mut chr = 0;
+    while chr < end && matches!(e.buffer.byte(at + chr), Some(b' ' | b'\t')) {
+        chr += 1;
+    }
+    Cursor { line, chr }
+}
+// Normal mode sits on characters, so the cursor can't be past the last one
+fn on_char(e: &Editor, c: Cursor) -> Cursor {
+    let c = e.clamp(c);
+    let end = e.line_end(c.line);
+    if end > 0 && c.chr >= end { e.left(Cursor { line: c.line, chr: end }) } else { c }
+}
+fn word_start(e: &Editor, from: usize) -> usize {
+    let len = e.buffer.len();
+    let mut at = from;
+    if at >= len { return len; }
+    let c = class(char_at(e, at));
+    if c != 0 {
+        while at < len && class(char_at(e, at)) == c {
+            at = next_at(e, at);
+        }
+    }
+    while at < len && class(char_at(e, at)) == 0 {
+        if at != from && is_empty_line(e, at) { break; }
+        at = next_at(e, at);
+    }
+    at
+}
+fn word_end(e: &Editor, from: usize) -> usize {
+    let len = e.buffer.len();
+    let mut at = next_at(e, from);
+    while at < len && class(char_at(e, at)) == 0 {
+        at = next_at(e, at);
+    }
+    if at >= len { return prev_at(e, len); }
+    let c = class(char_at(e, at));
+    loop {
+        let next = next_at(e, at);
+        if next >= len || class(char_at(e, next)) != c { return at; }
+        at = next;
+    }
+}
+fn word_back(e: &Editor, from: usize) -> usize {
+    let mut at = prev_at(e, from);
+    while at > 0 && class(char_at(e, at)) == 0 {
+        if is_empty_line(e, at) { return at; }
+        at = prev_at(e, at);
+    }
+    let c = class(char_at(e, at));
+    while at > 0 && class(char_at(e, prev_at(e, at))) == c {
+        at = prev_at(e, at);
+    }
+    at
+}
+// `%`. Jumps from the bracket under (or after) the cursor on this line to the one matching it
+fn matching_bracket(e: &Editor, c: Cursor) -> Option<usize> {
+    let l = e.buffer.line(c.line);
+    let end = l.at + e.line_end(c.line);
+    let mut at = e.offset(c);
+    let (open, close, forward) = loop {
+        if at >= end { return None; }
+        match e.buffer.byte(at)? {
+            b'(' => break (b'(', b')', true),
+            b'[' => break (b'[', b']', true),
+            b'{' => break (b'{', b'}', true),
+            b')' => break (b')', b'(', false),
+            b']' => break (b']', b'[', false),
+            b'}' => break (b'}', b'{', false),
+            _ => at += 1,
+        }
+    };
+    let mut depth = 0;
+    loop {
+        let b = e.buffer.byte(at)?;
+        if b == open { depth += 1; }
+        if b == close {
+            depth -= 1;
+            if depth == 0 { return Some(at); }
+        }
+        if forward { at += 1; } else if at == 0 { return None; } else { at -= 1; }
+    }
+}
+// Vim writes & and \1 in replacements where the regex crate wants ${0} and ${1}
+fn replacement(rep: &str) -> String {
+    let mut res = String::new();
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => res.push_str("${0}"),
+            '$' => res.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => res.push_str(&format!("${{{}}}", d)),
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some(d) => res.push(d),
+                None => res.push('\\'),
+            },
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+pub struct Vim {
+    pub mode: Mode,
+    pub registers: HashMap<char, Register>,
+    pub message: Option<String>, // Shown instead of the mode until the next key
+    count: usize,
+    register: Option<char>,
+    operator: Option<(char, usize)>, // With the count typed before it
+    prefix: Option<char>, // g f t F T r and " wait for another key
+    keys: Vec<Key>, // Of the command typed so far, for `.`
+    recording: Option<Vec<Key>>, // A change that went into insert mode, until it leaves it
+    last_change: Vec<Key>,
+    replaying: bool,
+    last_find: Option<Motion>,
+    anchor: Cursor, // Where visual mode started
+    head: Cursor,
+    changing: bool,
+}
+impl Default for Vim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Vim {
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Normal, registers: HashMap::new(), message: None, count: 0, register: None, operator: None,
+            prefix: None, keys: Vec::new(), recording: None, last_change: Vec::new(), replaying: false, last_find: None,
+            anchor: Cursor::new(), head: Cursor::new(), changing: false,
+        }
+    }
+    // What to show in the status bar
+    pub fn status(&self) -> String {
+        if let Some(message) = &self.message { return message.clone(); }
+        let mode = match self.mode {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+        };
+        let pending: String = self.keys.iter().filter_map(|k| if let Key::Char(c) = k { Some(*c) } else { None }).collect();
+        if pending.is_empty() { mode.to_string() } else { format!("{} {}", mode, pending) }
+    }
+    pub fn handle(&mut self, e: &mut Editor, key: Key) -> Action {
+        self.message = None;
+        match self.mode {
+            Mode::Insert => {
+                self.insert_key(e, key);
+                Action::None
+            }
+            _ => self.normal_key(e, key),
+        }
+    }
+    fn cursor(&self, e: &Editor) -> Cursor {
+        if self.visual() { self.head } else { e.primary().head }
+    }
+    fn visual(&self) -> bool {
+        matches!(self.mode, Mode::Visual | Mode::VisualLine)
+    }
+    fn begin_change(&mut self, e: &mut Editor) {
+        if !self.changing {
+            e.history.begin();
+            self.changing = true;
+        }
+    }
+    fn end_change(&mut self, e: &mut Editor) {
+        if self.changing {
+            e.history.end();
+            self.changing = false;
+        }
+    }
+    fn reset(&mut self) {
+        self.count = 0;
+        self.register = None;
+        self.operator = None;
+        self.prefix = None;
+        self.keys.clear();
+    }
+    // A command finished. Changes get remembered for `.`, unless they went into insert mode in
+    // which case the recording goes on until Esc
+    fn finish(&mut self, e: &mut Editor, change: bool) {
+        if change && !self.replaying {
+            if self.mode == Mode::Insert {
+                self.recording = Some(self.keys.clone());
+            } else {
+                self.last_change = self.keys.clone();
+            }
+        }
+        if self.mode != Mode::Insert {
+            self.end_change(e);
+        }
+        self.reset();
+    }
+    fn set_visual(&mut self, e: &mut Editor) {
+        let (start, end) = (self.anchor.min(self.head), self.anchor.max(self.head));
+        let (start, end) = if self.mode == Mode::VisualLine {
+            (Cursor { line: start.line, chr: 0 }, e.end(end))
+        } else {
+            (start, e.right(end).min(e.end(end)).max(end))
+        };
+        // NOTE: The selection is shown inclusive of the character under the cursor, like vim does
+        let sel = if self.head < self.anchor { Selection { anchor: end, head: start } } else { Selection { anchor: start, head: end } };
+        e.set_selections(vec![sel], 0);
+    }
+    fn enter_insert(&mut self, e: &mut Editor, c: Cursor) {
+        self.mode = Mode::Insert;
+        e.move_to(c, false);
+    }
+    fn enter_normal(&mut self, e: &mut Editor, c: Cursor) {
+        self.mode = Mode::Normal;
+        e.move_to(on_char(e, c), false);
+    }
+
+    fn insert_key(&mut self, e: &mut Editor, key: Key) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(key);
+        }
+        match key {
+            Key::Char(c) => e.type_char(c),
+            Key::Enter => e.newline(),
+            Key::Tab => e.tab(),
+            Key::Backspace => e.backspace(),
+            Key::Delete => e.delete(),
+            Key::Left => e.move_each(false, |e, s| e.left(s.head)),
+            Key::Right => e.move_each(false, |e, s| e.right(s.head)),
+            Key::Up => e.move_each(false, |e, s| e.up(s.head)),
+            Key::Down => e.move_each(false, |e, s| e.down(s.head)),
+            Key::Home => e.move_each(false, |e, s| e.home(s.head)),
+            Key::End => e.move_each(false, |e, s| e.end(s.head)),
+            Key::Esc => {
+                let c = e.primary().head;
+                // Leaving insert mode steps back onto the last character typed
+                let c = if c.chr > 0 { e.left(c) } else { c };
+                self.enter_normal(e, c);
+                self.end_change(e);
+                if let Some(recording) = self.recording.take() {
+                    self.last_change = recording;
+                }
+            }
+            Key::Ctrl(_) => {}
+        }
+    }
+
+    fn normal_key(&mut self, e: &mut Editor, key: Key) -> Action {
+        self.keys.push(key);
+        if let Some(prefix) = self.prefix.take() {
+            let Key::Char(c) = key else {
+                self.reset();
+                return Action::None;
+            };
+            match prefix {
+                '"' => self.register = Some(c),
+                'g' if c == 'g' => self.motion(e, Motion::FileStart),
+                'f' | 't' | 'F' | 'T' => {
+                    let m = Motion::Find { target: c, till: prefix == 't' || prefix == 'T', back: prefix == 'F' || prefix == 'T' };
+                    self.last_find = Some(m);
+                    self.motion(e, m);
+                }
+                'r' => self.replace_chars(e, c),
+                _ => self.reset(),
+            }
+            return Action::None;
+        }
+        let motion = match key {
+            Key::Esc => {
+                if self.visual() {
+                    let c = self.head;
+                    self.enter_normal(e, c);
+                }
+                self.reset();
+                return Action::None;
+            }
+            Key::Ctrl('r') => {
+                for _ in 0..self.count.max(1) {
+                    e.redo();
+                }
+                let c = e.primary().head;
+                e.move_to(on_char(e, c), false);
+                self.reset();
+                return Action::None;
+            }
+            Key::Char(c) if c.is_ascii_digit() && (c != '0' || self.count > 0) => {
+                self.count = self.count * 10 + c.to_digit(10).unwrap() as usize;
+                return Action::None;
+            }
+            Key::Char(c @ ('"' | 'g' | 'f' | 't' | 'F' | 'T' | 'r')) => {
+                self.prefix = Some(c);
+                return Action::None;
+            }
+            Key::Char('h') | Key::Left | Key::Backspace => Motion::Left,
+            Key::Char('l') | Key::Char(' ') | Key::Right => Motion::Right,
+            Key::Char('k') | Key::Up => Motion::Up,
+            Key::Char('j') | Key::Down | Key::Enter => Motion::Down,
+            Key::Char('w') => Motion::WordStart,
+            Key::Char('b') => Motion::WordBack,
+            Key::Char('e') => Motion::WordEnd,
+            Key::Char('0') | Key::Home => Motion::LineStart,
+            Key::Char('^') => Motion::FirstNonBlank,
+            Key::Char('$') | Key::End => Motion::LineEnd,
+            Key::Char('G') => Motion::FileEnd,
+            Key::Char('%') => Motion::Match,
+            Key::Char(';') | Key::Char(',') => {
+                let Some(Motion::Find { target, till, back }) = self.last_find else {
+                    self.reset();
+                    return Action::None;
+                };
+                Motion::Find { target, till, back: back != (key == Key::Char(',')) }
+            }
+            Key::Char(c) => return self.command(e, c),
+            _ => {
+                self.reset();
+                return Action::None;
+            }
+        };
+        self.motion(e, motion);
+        Action::None
+    }
+    // Everything that isn't a motion
+    fn command(&mut self, e: &mut Editor, c: char) -> Action {
+        let count = self.count.max(1);
+        let cur = self.cursor(e);
+        if self.visual() {
+            return self.visual_command(e, c);
+        }
+        if let Some((op, op_count)) = self.operator {
+            // dd, cc and yy work on `count` whole lines
+            if c == op {
+                let lines = op_count.max(1) * count;
+                let last = (cur.line + lines - 1).min(e.buffer.len_lines() - 1);
+                self.begin_change(e);
+                self.apply_lines(e, op, cur.line, last);
+                self.finish(e, op != 'y');
+            } else {
+                self.reset();
+            }
+            return Action::None;
+        }
+        match c {
+            'd' | 'c' | 'y' => {
+                self.operator = Some((c, self.count));
+                self.count = 0;
+                return Action::None;
+            }
+            ':' => {
+                self.reset();
+                return Action::Cmdline;
+            }
+            'v' | 'V' => {
+                self.mode = if c == 'v' { Mode::Visual } else { Mode::VisualLine };
+                self.anchor = cur;
+                self.head = cur;
+                self.set_visual(e);
+                self.reset();
+                return Action::None;
+            }
+            'u' => {
+                for _ in 0..count {
+                    e.undo();
+                }
+                let c = e.primary().head;
+                e.move_to(on_char(e, c), false);
+                self.reset();
+                return Action::None;
+            }
+            '.' => {
+                let keys = self.last_change.clone();
+                self.reset();
+                self.replaying = true;
+                for _ in 0..count {
+                    for key in keys.iter() {
+                        self.handle(e, *key);
+                    }
+                }
+                self.replaying = false;
+                return Action::None;
+            }
+            _ => {}
+        }
+        self.begin_change(e);
+        let change = match c {
+            'i' => { self.enter_insert(e, cur); true }
+            'a' => {
+                let c = e.right(cur).min(e.end(cur));
+                self.enter_insert(e, c);
+                true
+            }
+            'I' => { self.enter_insert(e, first_non_blank(e, cur.line)); true }
+            'A' => { self.enter_insert(e, e.end(cur)); true }
+            'o' | 'O' => {
+                if c == 'o' {
+                    let at = e.offset(e.end(cur));
+                    e.replace_range(at..at, b"\n");
+                } else {
+                    let at = e.buffer.line(cur.line).at;
+                    e.replace_range(at..at, b"\n");
+                    e.move_to(Cursor { line: cur.line, chr: 0 }, false);
+                }
+                self.mode = Mode::Insert;
+                true
+            }
+            'x' | 'X' | 'D' | 'C' | 's' => {
+                let (op, motion) = match c {
+                    'x' => ('d', Motion::Right),
+                    'X' => ('d', Motion::Left),
+                    'D' => ('d', Motion::LineEnd),
+                    'C' => ('c', Motion::LineEnd),
+                    _ => ('c', Motion::Right),
+                };
+                self.operator = Some((op, 0));
+                self.motion(e, motion);
+                return Action::None;
+            }
+            'S' | 'Y' => {
+                let op = if c == 'S' { 'c' } else { 'y' };
+                let last = (cur.line + count - 1).min(e.buffer.len_lines() - 1);
+                self.apply_lines(e, op, cur.line, last);
+                op == 'c'
+            }
+            'p' | 'P' => { self.put(e, c == 'P', count); true }
+            'J' => { self.join(e, cur.line, count.max(2)); true }
+            _ => false,
+        };
+        self.finish(e, change);
+        Action::None
+    }
+    fn visual_command(&mut self, e: &mut Editor, c: char) -> Action {
+        match c {
+            'v' | 'V' => {
+                let mode = if c == 'v' { Mode::Visual } else { Mode::VisualLine };
+                if self.mode == mode {
+                    let head = self.head;
+                    self.enter_normal(e, head);
+                } else {
+                    self.mode = mode;
+                    self.set_visual(e);
+                }
+            }
+            'o' => {
+                std::mem::swap(&mut self.anchor, &mut self.head);
+                self.set_visual(e);
+            }
+            ':' => {
+                self.reset();
+                return Action::Cmdline;
+            }
+            'd' | 'x' | 'c' | 's' | 'y' => {
+                let op = match c { 'x' => 'd', 's' => 'c', c => c };
+                let (start, end) = (self.anchor.min(self.head), self.anchor.max(self.head));
+                self.begin_change(e);
+                if self.mode == Mode::VisualLine {
+                    self.mode = Mode::Normal;
+                    self.apply_lines(e, op, start.line, end.line);
+                } else {
+                    self.mode = Mode::Normal;
+                    let a = e.offset(start);
+                    let b = e.offset(end).max(e.offset(e.right(end).min(e.end(end))));
+                    self.apply(e, op, a, b);
+                }
+                if self.mode == Mode::Normal {
+                    let c = e.primary().head;
+                    e.move_to(on_char(e, c), false);
+                }
+                // NOTE: `.` doesn't repeat visual changes, it'd need to remember the size of the selection
+                self.finish(e, false);
+                return Action::None;
+            }
+            _ => {}
+        }
+        self.reset();
+        Action::None
+    }
+    fn motion(&mut self, e: &mut Editor, motion: Motion) {
+        let from = self.cursor(e);
+        let count = self.count.max(1) * self.operator.map(|(_, c)| c.max(1)).unwrap_or(1);
+        let explicit = self.count > 0 || self.operator.is_some_and(|(_, c)| c > 0);
+        let Some(to) = self.target(e, motion, from, count, explicit) else {
+            self.reset();
+            return;
+        };
+        if self.visual() {
+            self.head = to;
+            self.set_visual(e);
+            self.reset();
+            return;
+        }
+        let Some((op, _)) = self.operator else {
+            e.move_to(on_char(e, to), false);
+            self.reset();
+            return;
+        };
+        self.begin_change(e);
+        let kind = if motion == Motion::WordStart && self.changes_word(e, from) { Kind::Inclusive } else { motion.kind() };
+        match kind {
+            Kind::Linewise => self.apply_lines(e, op, from.line.min(to.line), from.line.max(to.line)),
+            kind => {
+                let (start, mut end) = (from.min(to), from.max(to));
+                let mut b = e.offset(end);
+                if kind == Kind::Inclusive {
+                    b = next_at(e, b).min(e.offset(e.end(end)));
+                } else if end.line > start.line && end.chr == 0 {
+                    // A motion like `dw` on the last word of a line stops at the end of that line
+                    end = e.end(Cursor { line: end.line - 1, chr: 0 });
+                    b = e.offset(end);
+                }
+                self.apply(e, op, e.offset(start), b);
+            }
+        }
+        self.finish(e, op != 'y');
+    }
+    fn changes_word(&self, e: &Editor, from: Cursor) -> bool {
+        self.operator.is_some_and(|(op, _)| op == 'c') && class(char_at(e, e.offset(from))) != 0
+    }
+    fn target(&self, e: &Editor, motion: Motion, from: Cursor, count: usize, explicit: bool) -> Option<Cursor> {
+        let last = e.buffer.len_lines() - 1;
+        let operating = self.operator.is_some();
+        Some(match motion {
+            Motion::Left => {
+                let mut c = from;
+                for _ in 0..count {
+                    if c.chr == 0 { break; }
+                    c = e.left(c);
+                }
+                c
+            }
+            Motion::Right => {
+                let mut c = from;
+                for _ in 0..count {
+                    if c.chr >= e.line_end(c.line) { break; }
+                    c = e.right(c);
+                }
+                // `l` stops on the last character, but `dl` on it still deletes it
+                if operating { c } else { on_char(e, c) }
+            }
+            Motion::Up => e.cursor_at_column(from.line.saturating_sub(count), e.column(from)),
+            Motion::Down => e.cursor_at_column((from.line + count).min(last), e.column(from)),
+            // `cw` on a word changes just the word, like `ce` but without jumping off a word that's one character long
+            Motion::WordStart if self.changes_word(e, from) => {
+                let mut at = e.offset(from);
+                for i in 0..count {
+                    let next = next_at(e, at);
+                    if i > 0 || (next < e.buffer.len() && class(char_at(e, next)) == class(char_at(e, at))) {
+                        at = word_end(e, at);
+                    }
+                }
+                e.cursor_at(at)
+            }
+            Motion::WordStart | Motion::WordBack | Motion::WordEnd => {
+                let mut at = e.offset(from);
+                for _ in 0..count {
+                    at = match motion {
+                        Motion::WordStart => word_start(e, at),
+                        Motion::WordBack => word_back(e, at),
+                        _ => word_end(e, at),
+                    };
+                }
+                e.cursor_at(at)
+            }
+            Motion::LineStart => e.home(from),
+            Motion::FirstNonBlank => first_non_blank(e, from.line),
+            Motion::LineEnd => {
+                let line = (from.line + count - 1).min(last);
+                let c = e.end(Cursor { line, chr: 0 });
+                if operating { e.left(c).max(Cursor { line, chr: 0 }) } else { on_char(e, c) }
+            }
+            Motion::FileStart => first_non_blank(e, if explicit { (count - 1).min(last) } else { 0 }),
+            Motion::FileEnd => first_non_blank(e, if explicit { (count - 1).min(last) } else { last }),
+            Motion::Find { target, till, back } => {
+                let line = e.buffer.line_bytes(from.line);
+                let line = &line[..e.line_end(from.line)];
+                // NOTE: Offsets into the line itself, bytes that aren't UTF-8 can't be what's looked for
+                let found = line.utf8_chunks().scan(0, |at, chunk| {
+                    let start = *at;
+                    *at += chunk.valid().len() + chunk.invalid().len();
+                    Some(chunk.valid().char_indices().map(move |(i, c)| (start + i, c)).collect::<Vec<_>>())
+                }).flatten().filter(|(_, c)| *c == target).map(|(i, _)| i).collect::<Vec<_>>();
+                let found = if back {
+                    let i = found.into_iter().rev().filter(|i| *i < from.chr).nth(count - 1)?;
+                    if till { i + target.len_utf8() } else { i }
+                } else {
+                    let i = found.into_iter().filter(|i| *i > from.chr).nth(count - 1)?;
+                    if till { e.left(Cursor { line: from.line, chr: i }).chr } else { i }
+                };
+                Cursor { line: from.line, chr: found }
+            }
+            Motion::Match => e.cursor_at(matching_bracket(e, from)?),
+        })
+    }
+    // Applies an operator to the bytes in `a..b`
+    fn apply(&mut self, e: &mut Editor, op: char, a: usize, b: usize) {
+        let text = e.buffer.slice(a..b);
+        match op {
+            'y' => {
+                self.store(text, false, true);
+                e.move_to(e.cursor_at(a), false);
+            }
+            'd' => {
+                self.store(text, false, false);
+                e.replace_range(a..b, b"");
+                let c = e.primary().head;
+                e.move_to(on_char(e, c), false);
+            }
+            _ => {
+                self.store(text, false, false);
+                e.replace_range(a..b, b"");
+                self.mode = Mode::Insert;
+            }
+        }
+    }
+    // Applies an operator to the lines `first..=last`
+    fn apply_lines(&mut self, e: &mut Editor, op: char, first: usize, last: usize) {
+        let lines = e.buffer.len_lines();
+        let start = e.buffer.line(first).at;
+        let end = if last + 1 < lines { e.buffer.line(last + 1).at } else { e.buffer.len() };
+        let mut text = e.buffer.slice(start..end);
+        if last + 1 >= lines {
+            text.push(b'\n');
+        }
+        match op {
+            'y' => self.store(text, true, true),
+            'd' => {
+                self.store(text, true, false);
+                // The last line takes the line ending before it along instead
+                let range = if last + 1 < lines || first == 0 { start..end } else { e.offset(e.end(Cursor { line: first - 1, chr: 0 }))..end };
+                e.replace_range(range, b"");
+                let line = first.min(e.buffer.len_lines() - 1);
+                e.move_to(first_non_blank(e, line), false);
+            }
+            _ => {
+                self.store(text, true, false);
+                let indent = first_non_blank(e, first);
+                let end = e.offset(e.end(Cursor { line: last, chr: 0 }));
+                e.replace_range(e.offset(indent)..end, b"");
+                self.mode = Mode::Insert;
+            }
+        }
+    }
+    fn store(&mut self, text: Vec<u8>, linewise: bool, yank: bool) {
+        let name = self.register.take().unwrap_or('"');
+        if name == '_' { return; }
+        let mut reg = Register { text, linewise };
+        if name.is_ascii_uppercase() {
+            // Uppercase appends to the lowercase register
+            let existing = self.registers.entry(name.to_ascii_lowercase()).or_insert(Register { text: Vec::new(), linewise });
+            existing.text.extend_from_slice(&reg.text);
+            reg = existing.clone();
+        } else if name == '+' || name == '*' {
+            clipboard::set_text(&String::from_utf8_lossy(&reg.text));
+        } else if name != '"' {
+            self.registers.insert(name, reg.clone());
+        }
+        if yank && name == '"' {
+            self.registers.insert('0', reg.clone());
+        }
+        self.registers.insert('"', reg);
+    }
+    fn fetch(&mut self) -> Option<Register> {
+        let name = self.register.take().unwrap_or('"');
+        if name == '+' || name == '*' {
+            let text = endings::normalize(clipboard::get_text()?.as_bytes());
+            return Some(Register { linewise: text.ends_with(b"\n"), text });
+        }
+        self.registers.get(&name.to_ascii_lowercase()).cloned()
+    }
+    fn put(&mut self, e: &mut Editor, before: bool, count: usize) {
+        let Some(reg) = self.fetch() else { return; };
+        let text = reg.text.repeat(count);
+        if text.is_empty() { return; }
+        let cur = e.primary().head;
+        if reg.linewise {
+            let lines = e.buffer.len_lines();
+            if before || cur.line + 1 < lines {
+                let line = if before { cur.line } else { cur.line + 1 };
+                let at = e.buffer.line(line).at;
+                e.replace_range(at..at, &text);
+                e.move_to(first_non_blank(e, line), false);
+            } else {
+                // After the last line, which has no line ending of its own to put it after
+                let mut text = text;
+                if text.ends_with(b"\n") { text.pop(); }
+                let mut inserted = b"\n".to_vec();
+                inserted.extend_from_slice(&text);
+                let at = e.buffer.len();
+                e.replace_range(at..at, &inserted);
+                e.move_to(first_non_blank(e, cur.line + 1), false);
+            }
+        } else {
+            let at = if before || e.line_end(cur.line) == 0 { e.offset(cur) } else { next_at(e, e.offset(cur)) };
+            e.replace_range(at..at, &text);
+            let end = prev_at(e, at + text.len());
+            e.move_to(e.cursor_at(end), false);
+        }
+    }
+    // `J`. Joins `count` lines starting at `line` with a space in between
+    fn join(&mut self, e: &mut Editor, line: usize, count: usize) {
+        let mut joined = None;
+        for _ in 1..count {
+            if line + 1 >= e.buffer.len_lines() { break; }
+            let end = e.offset(e.end(Cursor { line, chr: 0 }));
+            let next = first_non_blank(e, line + 1);
+            let space: &[u8] = if e.line_end(line + 1) == next.chr || e.line_end(line) == 0 { b"" } else { b" " };
+            e.replace_range(end..e.offset(next), space);
+            joined = Some(end);
+        }
+        if let Some(at) = joined {
+            e.move_to(on_char(e, e.cursor_at(at)), false);
+        }
+    }
+    // `r`. Replaces `count` characters under the cursor with `c`
+    fn replace_chars(&mut self, e: &mut Editor, c: char) {
+        let count = self.count.max(1);
+        let cur = e.primary().head;
+        let start = e.offset(cur);
+        let end = e.offset(e.end(cur));
+        let mut b = start;
+        for _ in 0..count {
+            if b >= end {
+                self.reset();
+                return;
+            }
+            b = next_at(e, b);
+        }
+        self.begin_change(e);
+        e.replace_range(start..b, c.to_string().repeat(count).as_bytes());
+        let at = e.offset(e.primary().head);
+        e.move_to(e.cursor_at(prev_at(e, at)), false);
+        self.finish(e, true);
+    }
+
+    // Runs what was typed after `:`. `:w [path]`, `:q`, `:q!`, `:qa`, `:qa!`, `:wq`, `:x`, `:e <path>`, `:bn`, `:bp`,
+    // `:sp`, `:vs`, `:<line>` and `:[range]s/pattern/replacement/[gi]` where the range is `%`, `.`, `$`, a line or `a,b`
+    pub fn execute_ex(&mut self, e: &mut Editor, cmd: &str) -> ExResult {
+        let cmd = cmd.trim();
+        let cmd = cmd.strip_prefix(':').unwrap_or(cmd).trim_start();
+        let cur = e.primary().head.line;
+        let last = e.buffer.len_lines() - 1;
+        // The range, if there is one
+        let line_number = |s: &str| -> Option<usize> {
+            match s {
+                "." => Some(cur),
+                "$" => Some(last),
+                _ => s.parse::<usize>().ok().map(|n| n.saturating_sub(1).min(last)),
+            }
+        };
+        let end = cmd.find(|c: char| !(c.is_ascii_digit() || c == ',' || c == '.' || c == '$' || c == '%')).unwrap_or(cmd.len());
+        let (range, rest) = cmd.split_at(end);
+        let range = match range {
+            "" => None,
+            "%" => Some((0, last)),
+            r => {
+                let parsed = match r.split_once(',') {
+                    Some((a, b)) => line_number(a).zip(line_number(b)),
+                    None => line_number(r).map(|l| (l, l)),
+                };
+                let Some((a, b)) = parsed else { return ExResult::Error(format!("Invalid range: {}", r)); };
+                Some((a.min(b), a.max(b)))
+            }
+        };
+        let rest = rest.trim_start();
+        if rest.is_empty() {
+            if let Some((_, line)) = range {
+                e.move_to(first_non_blank(e, line), false);
+            }
+            return ExResult::Done;
+        }
+        let (name, arg) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let arg = arg.trim();
+        match name {
+            "w" | "wq" | "x" => {
+                if name == "x" && !e.dirty { return ExResult::Quit; }
+                let res = if arg.is_empty() { e.save() } else { e.save_as(PathBuf::from(arg)) };
+                if let Err(err) = res { return ExResult::Error(format!("Failed to save: {}", err)); }
+                if name == "w" {
+                    let path = e.path.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+                    self.message = Some(format!("\"{}\" written", path));
+                    return ExResult::Done;
+                }
+                ExResult::Quit
+            }
+            "q" if e.dirty => ExResult::Error("No write since last change (add ! to override)".to_string()),
+            "q" | "q!" => ExResult::Quit,
+            "qa" | "qall" => ExResult::QuitAll { force: false },
+            "qa!" | "qall!" => ExResult::QuitAll { force: true },
+            "e" | "edit" if arg.is_empty() => ExResult::Error("Argument required".to_string()),
+            "e" | "edit" => ExResult::Edit(PathBuf::from(arg)),
+            "bn" | "bnext" => ExResult::Cycle(true),
+            "bp" | "bprevious" => ExResult::Cycle(false),
+            "sp" | "split" => ExResult::Split(Direction::Down),
+            "vs" | "vsplit" => ExResult::Split(Direction::Right),
+            _ if rest.starts_with('s') && rest.len() > 1 && !rest[1..].starts_with(char::is_alphanumeric) => {
+                let (first, last) = range.unwrap_or((cur, cur));
+                self.substitute(e, &rest[1..], first, last)
+            }
+            _ => ExResult::Error(format!("Not an editor command: {}", rest)),
+        }
+    }
+    fn substitute(&mut self, e: &mut Editor, args: &str, first: usize, last: usize) -> ExResult {
+        let delim = args.chars().next().unwrap();
+        let mut parts = Vec::new();
+        let mut part = String::new();
+        let mut escaped = false;
+        for c in args[delim.len_utf8()..].chars() {
+            if escaped {
+                if c != delim { part.push('\\'); }
+                part.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delim && parts.len() < 2 {
+                parts.push(std::mem::take(&mut part));
+            } else {
+                part.push(c);
+            }
+        }
+        if escaped { part.push('\\'); }
+        parts.push(part);
+        let pattern = parts[0].as_str();
+        let rep = replacement(parts.get(1).map(|s| s.as_str()).unwrap_or(""));
+        let flags = parts.get(2).map(|s| s.as_str()).unwrap_or("");
+        if let Some(f) = flags.chars().find(|c| !matches!(c, 'g' | 'i')) {
+            return ExResult::Error(format!("Unknown flag '{}'", f));
+        }
+        let pattern = if flags.contains('i') { format!("(?i){}", pattern) } else { pattern.to_string() };
+        let regex = match Regex::new(&pattern) {
+            Ok(regex) => regex,
+            Err(err) => return ExResult::Error(err.to_string()),
+        };
+        let mut count = 0;
+        let mut lines = 0;
+        let mut last_changed = None;
+        e.history.begin();
+        // NOTE: Bottom up so that replacements with line breaks in them don't move the lines still to go
+        for line in (first..=last).rev() {
+            let bytes = e.buffer.line_bytes(line);
+            let bytes = &bytes[..e.line_end(line)];
+            let matches = if flags.contains('g') { regex.find_iter(bytes).count() } else { regex.is_match(bytes) as usize };
+            if matches == 0 { continue; }
+            let replaced = regex.replacen(bytes, if flags.contains('g') { 0 } else { 1 }, rep.as_bytes());
+            let at = e.buffer.line(line).at;
+            e.replace_range(at..at + bytes.len(), &replaced);
+            count += matches;
+            lines += 1;
+            last_changed.get_or_insert(line);
+        }
+        e.history.end();
+        let Some(line) = last_changed else { return ExResult::Error(format!("Pattern not found: {}", parts[0])); };
+        e.move_to(first_non_blank(e, line), false);
+        if lines > 1 {
+            self.message = Some(format!("{} substitutions on {} lines", count, lines));
+        }
+        ExResult::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(e: &Editor) -> String {
+        String::from_utf8_lossy(&e.buffer.to_bytes()).into_owned()
+    }
+    // Feeds the keys one by one, with <Esc> for escape and <Tab> for tab
+    fn feed(v: &mut Vim, e: &mut Editor, keys: &str) {
+        let mut rest = keys;
+        while let Some(c) = rest.chars().next() {
+            let key = if let Some(after) = rest.strip_prefix("<Esc>") {
+                rest = after;
+                Key::Esc
+            } else if let Some(after) = rest.strip_prefix("<Tab>") {
+                rest = after;
+                Key::Tab
+            } else {
+                rest = &rest[c.len_utf8()..];
+                Key::Char(c)
+            };
+            v.handle(e, key);
+        }
+    }
+    // Types `keys` into a buffer holding `src`, then checks the text and where the cursor ended up
+    fn check(src: &[u8], keys: &str, want: &[u8], cursor: (usize, usize)) {
+        let mut e = Editor::from_bytes(src.to_vec());
+        let mut v = Vim::new();
+        feed(&mut v, &mut e, keys);
+        assert_eq!(text(&e), String::from_utf8_lossy(want), "keys {:?}", keys);
+        let head = e.primary().head;
+        assert_eq!((head.line, head.chr), cursor, "cursor after keys {:?}", keys);
+    }
+
+    #[test]
+    fn operators_and_motions() {
+        check(b"hello world foo", "dw", b"world foo", (0, 0));
+        check(b"hello world foo", "2dw", b"foo", (0, 0));
+        check(b"hello world foo", "d2w", b"foo", (0, 0));
+        check(b"hello world\nnext", "wdw", b"hello \nnext", (0, 5));
+        check(b"hello world foo", "cwbye<Esc>", b"bye world foo", (0, 2));
+        check(b"a b", "cwx<Esc>", b"x b", (0, 0));
+        check(b"hello world", "de", b" world", (0, 0));
+        check(b"hello world", "d$", b"", (0, 0));
+        check(b"hello world", "3x", b"lo world", (0, 0));
+        check(b"a\nb\nc\nd", "3dd", b"d", (0, 0));
+        check(b"a\nb\nc\nd", "jdd", b"a\nc\nd", (1, 0));
+        check(b"a\nb\nc\nd", "Gdd", b"a\nb\nc", (2, 0));
+        check(b"a\nb\nc\nd", "jdG", b"a", (0, 0));
+        check(b"foo(bar, baz)", "f(d%", b"foo", (0, 2));
+    }
+    #[test]
+    fn find_in_line() {
+        check(b"foo(bar, baz)", "f,D", b"foo(bar", (0, 6));
+        check(b"foo(bar, baz)", "dt,", b", baz)", (0, 0));
+        check(b"a.b.c.d", "f.;;x", b"a.b.cd", (0, 5));
+        check(b"a.b.c.d", "$F.,x", b"a.b.cd", (0, 5));
+        check(b"a.b.c.d", "2f.x", b"a.bc.d", (0, 3));
+        check(b"a.b.c.d", "$T.x", b"a.b.c.", (0, 5));
+        check("é.ü.x".as_bytes(), "f.;x", "é.üx".as_bytes(), (0, 5));
+        // Bytes that aren't UTF-8 keep their place, and F doesn't cut into them
+        check(b"\xff\xfe.a.b", "f.x", b"\xff\xfea.b", (0, 2));
+        check(b"a.\xff\xfeb.c", "$F.x", b"a.\xff\xfebc", (0, 5));
+        check(b"a.\xff\xfeb.c", "$2F.x", b"a\xff\xfeb.c", (0, 1));
+        check(b"a.\xff\xfeb.c", "$T.x", b"a.\xff\xfeb.", (0, 5));
+        check(b"x\xffy", "fyx", b"x\xff", (0, 1));
+    }
+    #[test]
+    fn repeat_and_undo() {
+        check(b"one two three", "dw.", b"three", (0, 0));
+        check(b"a1 a2 a3", "cwX<Esc>w.", b"X X a3", (0, 2));
+        check(b"x\ny\nz", "dd.", b"z", (0, 0));
+        check(b"one two three", "dwdwuu", b"one two three", (0, 0));
+        check(b"one two three", "cwX<Esc>u", b"one two three", (0, 0));
+        check(b"a\nb", "i<Tab><Esc>j.", b"\ta\n\tb", (1, 0));
+    }
+    #[test]
+    fn registers() {
+        check(b"a\nb\nc\nd", "yyp", b"a\na\nb\nc\nd", (1, 0));
+        check(b"abc", "xp", b"bac", (0, 1));
+        check(b"one two", "\"ayw$\"ap", b"one twoone ", (0, 10));
+        check(b"one two", "\"_dwP", b"two", (0, 0));
+        check(b"one two", "yw\"_dw\"0P", b"one two", (0, 3));
+        // Appending to a register with its uppercase name
+        check(b"one two", "\"ayww\"Ayw$\"ap", b"one twoone two", (0, 13));
+    }
+    #[test]
+    fn substitute() {
+        let mut e = Editor::from_bytes(b"one two\nfoo bar foo\nfoo".to_vec());
+        let mut v = Vim::new();
+        assert!(matches!(v.execute_ex(&mut e, "2"), ExResult::Done));
+        assert!(matches!(v.execute_ex(&mut e, "s/foo/X/"), ExResult::Done));
+        assert_eq!(text(&e), "one two\nX bar foo\nfoo");
+        assert!(matches!(v.execute_ex(&mut e, "%s/fo(o)/[&\\1]/g"), ExResult::Done));
+        assert_eq!(text(&e), "one two\nX bar [fooo]\n[fooo]");
+        assert_eq!(v.status(), "2 substitutions on 2 lines");
+        assert!(matches!(v.execute_ex(&mut e, "s/zzz/y/"), ExResult::Error(_)));
+        assert!(matches!(v.execute_ex(&mut e, "1,2s/O/0/gi"), ExResult::Done));
+        assert_eq!(text(&e), "0ne tw0\nX bar [f000]\n[fooo]");
+        // The whole substitution comes back with one undo
+        feed(&mut v, &mut e, "u");
+        assert_eq!(text(&e), "one two\nX bar [fooo]\n[fooo]");
+    }
+}
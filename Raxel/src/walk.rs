@@ -0,0 +1,139 @@
+// Walks a directory tree the way git sees it: .git itself and everything a .gitignore on the way
+// down ignores gets skipped. Supports the usual gitignore patterns: `*`, `?`, `[abc]`, `**`,
+// a leading `/` or a `/` in the middle to anchor to the .gitignore's directory, a trailing `/`
+// for directories only, and `!` to take a path back in
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use regex::Regex;
+
+struct Rule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+// The rules of one .gitignore, which apply to paths relative to `dir`
+pub struct Ignore {
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+fn glob_to_regex(glob: &str) -> String {
+    let mut res = String::new();
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**/` matches any number of directories, zero included
+                if chars.get(i + 2) == Some(&'/') {
+                    res.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    res.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => res.push_str("[^/]*"),
+            '?' => res.push_str("[^/]"),
+            '[' => {
+                let close = chars[i + 1..].iter().position(|c| *c == ']').map(|p| i + 1 + p);
+                let Some(close) = close else {
+                    res.push_str(r"\[");
+                    i += 1;
+                    continue;
+                };
+                res.push('[');
+                for (k, c) in chars[i + 1..close].iter().enumerate() {
+                    match c {
+                        '!' if k == 0 => res.push('^'),
+                        '\\' | '[' | '&' | '~' => {
+                            res.push('\\');
+                            res.push(*c);
+                        }
+                        c => res.push(*c),
+                    }
+                }
+                res.push(']');
+                i = close;
+            }
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                res.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => res.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    res
+}
+impl Ignore {
+    pub fn parse(dir: &Path, src: &str) -> Self {
+        let mut rules = Vec::new();
+        for line in src.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            // NOTE: Only a pattern with a '/' somewhere before its end is relative to the .gitignore,
+            // the others match a name at any depth
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            let pattern = glob_to_regex(line);
+            let pattern = if anchored { format!("^{}$", pattern) } else { format!("(?:^|/){}$", pattern) };
+            if let Ok(regex) = Regex::new(&pattern) {
+                rules.push(Rule { regex, negated, dir_only });
+            }
+        }
+        Self { dir: dir.to_path_buf(), rules }
+    }
+    pub fn load(dir: &Path) -> Option<Self> {
+        let src = std::fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(Self::parse(dir, &src))
+    }
+    // Some(true) if the last rule matching `path` ignores it, Some(false) if it takes it back in
+    pub fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel = path.strip_prefix(&self.dir).ok()?;
+        let rel = rel.to_string_lossy().replace('\\', "/");
+        self.rules.iter().rev().find(|r| (!r.dir_only || is_dir) && r.regex.is_match(&rel)).map(|r| !r.negated)
+    }
+}
+// The deeper a .gitignore the more say it has
+pub fn is_ignored(ignores: &[Arc<Ignore>], path: &Path, is_dir: bool) -> bool {
+    ignores.iter().rev().find_map(|i| i.matches(path, is_dir)).unwrap_or(false)
+}
+// Calls `f` with every file under `root` that isn't ignored, until it gives back false
+pub fn walk(root: &Path, mut f: impl FnMut(PathBuf) -> bool) {
+    let mut stack: Vec<(PathBuf, Vec<Arc<Ignore>>)> = vec![(root.to_path_buf(), Vec::new())];
+    while let Some((dir, mut ignores)) = stack.pop() {
+        if let Some(ignore) = Ignore::load(&dir) {
+            ignores.push(Arc::new(ignore));
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue; };
+        let mut entries: Vec<_> = entries.flatten().collect();
+        entries.sort_by_key(|e| e.file_name());
+        let mut dirs = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            let Ok(kind) = entry.file_type() else { continue; };
+            // NOTE: Symlinks aren't followed, they could loop
+            if kind.is_symlink() { continue; }
+            if entry.file_name() == ".git" || is_ignored(&ignores, &path, kind.is_dir()) { continue; }
+            if kind.is_dir() {
+                dirs.push(path);
+            } else if !f(path) {
+                return;
+            }
+        }
+        // Reversed so that the directories come off the stack in order
+        for dir in dirs.into_iter().rev() {
+            stack.push((dir, ignores.clone()));
+        }
+    }
+}
@@ -0,0 +1,6 @@
+#version 330 core
+uniform sampler2D tex; /* texture
+   still a comment */ in vec2 uv;
+void main() {
+    gl_FragColor = texture(tex, uv) * 0.5f; // half
+}
@@ -0,0 +1,9 @@
+@decorator
+def greet(name: str) -> None:
+    """Say hello
+    to 'name'"""
+    return f"hi {name}" + 'x' # done
+x = 0x1F + 3.5e-2j
+s = '''a
+b'''
+print(s)